@@ -0,0 +1,219 @@
+use nalgebra_glm::{look_at, perspective_rh_zo, vec3, Mat4, Vec3};
+use winit::event::{
+    DeviceEvent, ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode,
+    WindowEvent,
+};
+
+// Pitch stays just short of straight up or down, where the view direction would be parallel to
+// the up vector.
+const MAX_PITCH: f32 = 89.0 * std::f32::consts::PI / 180.0;
+
+// Wheel deltas given in pixels (touchpads) are converted at this many pixels per line.
+const PIXELS_PER_LINE: f32 = 40.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    // Circles around `target` at `distance`, always looking at it.
+    Orbit,
+    // Moves freely from `position` in the direction it is looking.
+    Fly,
+}
+
+// Right-handed, y-up perspective camera. Dragging with the left mouse button turns it, the wheel
+// zooms in orbit mode and changes the speed in fly mode, WASD moves in fly mode with E and Q
+// going up and down, and Tab switches between the two modes.
+#[derive(Debug, Clone)]
+pub struct Camera {
+    pub mode: CameraMode,
+    pub target: Vec3,
+    pub distance: f32,
+    pub position: Vec3,
+    // Rotation around the world y axis and elevation, in radians. Both zero looks down -z.
+    pub yaw: f32,
+    pub pitch: f32,
+    // Vertical field of view in radians.
+    pub fov_y: f32,
+    pub near: f32,
+    pub far: f32,
+    // Width divided by height of the image, see `resize`.
+    pub aspect: f32,
+    // Radians per pixel of mouse motion.
+    pub rotate_speed: f32,
+    // Units per second in fly mode.
+    pub move_speed: f32,
+    // Fraction of the distance or speed changed per wheel line.
+    pub zoom_speed: f32,
+    input: Input,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Input {
+    rotating: bool,
+    forward: bool,
+    backward: bool,
+    left: bool,
+    right: bool,
+    up: bool,
+    down: bool,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera::orbit(vec3(0.0, 0.0, 0.0), 5.0)
+    }
+}
+
+impl Camera {
+    // Looks down -z at `target` from `distance` away.
+    pub fn orbit(target: Vec3, distance: f32) -> Camera {
+        Camera {
+            mode: CameraMode::Orbit,
+            target,
+            distance,
+            position: target + vec3(0.0, 0.0, distance),
+            yaw: 0.0,
+            pitch: 0.0,
+            fov_y: 60.0f32.to_radians(),
+            near: 0.01,
+            far: 100.0,
+            aspect: 1.0,
+            rotate_speed: 0.005,
+            move_speed: 2.0,
+            zoom_speed: 0.1,
+            input: Input::default(),
+        }
+    }
+
+    pub fn resize(&mut self, dimensions: [u32; 2]) {
+        if dimensions[0] > 0 && dimensions[1] > 0 {
+            self.aspect = dimensions[0] as f32 / dimensions[1] as f32;
+        }
+    }
+
+    // Unit vector the camera looks along.
+    pub fn forward(&self) -> Vec3 {
+        vec3(
+            -self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+            -self.pitch.cos() * self.yaw.cos(),
+        )
+    }
+
+    pub fn eye(&self) -> Vec3 {
+        match self.mode {
+            CameraMode::Orbit => self.target - self.forward() * self.distance,
+            CameraMode::Fly => self.position,
+        }
+    }
+
+    pub fn view(&self) -> Mat4 {
+        let eye = self.eye();
+        look_at(&eye, &(eye + self.forward()), &vec3(0.0, 1.0, 0.0))
+    }
+
+    // Maps depth to Vulkan's [0, 1] range and flips y, since Vulkan's clip space points down.
+    pub fn projection(&self) -> Mat4 {
+        let mut projection = perspective_rh_zo(self.aspect, self.fov_y, self.near, self.far);
+        projection[(1, 1)] *= -1.0;
+        projection
+    }
+
+    // Keeps the view unchanged while switching, so the new mode starts where the old one was.
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        if mode == self.mode {
+            return;
+        }
+
+        match mode {
+            CameraMode::Fly => self.position = self.eye(),
+            CameraMode::Orbit => self.target = self.position + self.forward() * self.distance,
+        }
+        self.mode = mode;
+    }
+
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput { input, .. } => self.handle_key(input),
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => self.input.rotating = *state == ElementState::Pressed,
+            WindowEvent::Focused(false) => self.input = Input::default(),
+            _ => {}
+        }
+    }
+
+    // Mouse motion and the wheel are taken from raw device events, which keep coming when the
+    // cursor hits the edge of the window.
+    pub fn handle_device_event(&mut self, event: &DeviceEvent) {
+        match event {
+            DeviceEvent::MouseMotion { delta } if self.input.rotating => {
+                self.rotate(delta.0 as f32, delta.1 as f32)
+            }
+            DeviceEvent::MouseWheel { delta } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+                };
+                self.zoom(lines)
+            }
+            _ => {}
+        }
+    }
+
+    // Turns by a mouse movement of `dx` pixels to the right and `dy` pixels down.
+    pub fn rotate(&mut self, dx: f32, dy: f32) {
+        self.yaw -= dx * self.rotate_speed;
+        self.pitch = (self.pitch - dy * self.rotate_speed).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    // Positive `lines` zoom in when orbiting and speed up when flying.
+    pub fn zoom(&mut self, lines: f32) {
+        let factor = (1.0 - self.zoom_speed).powf(lines);
+        match self.mode {
+            CameraMode::Orbit => self.distance = (self.distance * factor).max(self.near),
+            CameraMode::Fly => self.move_speed /= factor,
+        }
+    }
+
+    // Applies the movement keys held down during the last `dt` seconds.
+    pub fn update(&mut self, dt: f32) {
+        if self.mode != CameraMode::Fly {
+            return;
+        }
+
+        let forward = self.forward();
+        let right = forward.cross(&vec3(0.0, 1.0, 0.0)).normalize();
+        let up = vec3(0.0, 1.0, 0.0);
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+
+        let direction = forward * axis(self.input.forward, self.input.backward)
+            + right * axis(self.input.right, self.input.left)
+            + up * axis(self.input.up, self.input.down);
+        if direction.norm_squared() > 0.0 {
+            self.position += direction.normalize() * self.move_speed * dt;
+        }
+    }
+
+    fn handle_key(&mut self, input: &KeyboardInput) {
+        let Some(key) = input.virtual_keycode else {
+            return;
+        };
+        let pressed = input.state == ElementState::Pressed;
+
+        match key {
+            VirtualKeyCode::W => self.input.forward = pressed,
+            VirtualKeyCode::S => self.input.backward = pressed,
+            VirtualKeyCode::A => self.input.left = pressed,
+            VirtualKeyCode::D => self.input.right = pressed,
+            VirtualKeyCode::E => self.input.up = pressed,
+            VirtualKeyCode::Q => self.input.down = pressed,
+            VirtualKeyCode::Tab if pressed => self.set_mode(match self.mode {
+                CameraMode::Orbit => CameraMode::Fly,
+                CameraMode::Fly => CameraMode::Orbit,
+            }),
+            _ => {}
+        }
+    }
+}
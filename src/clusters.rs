@@ -0,0 +1,135 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::{
+    buffer::{
+        cpu_pool::CpuBufferPoolSubbuffer, BufferAccess, BufferUsage, CpuBufferPool,
+        DeviceLocalBuffer,
+    },
+    command_buffer::{allocator::CommandBufferAllocator, AutoCommandBufferBuilder},
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, PersistentDescriptorSet, WriteDescriptorSet,
+    },
+    device::Device,
+    memory::allocator::StandardMemoryAllocator,
+    pipeline::{ComputePipeline, Pipeline, PipelineBindPoint},
+    DeviceSize,
+};
+
+use crate::{camera::Camera, shaders::light_culling};
+
+// Tiles across and down the screen, and slices in depth, the view frustum is cut into.
+const CLUSTER_GRID: [u32; 3] = [16, 9, 24];
+
+// Must match `MAX_CLUSTER_LIGHTS` in `clusters.glsl`.
+const MAX_CLUSTER_LIGHTS: u32 = 128;
+
+// Must match the `local_size_x` of `light_culling`.
+const WORK_GROUP_SIZE: u32 = 64;
+
+// Bins the lights into clusters of the view frustum each frame, for the clustered path. Nodes are
+// then drawn as in the forward path, but each pixel only goes through the lights that can reach
+// its cluster, so scenes can have any number of lights as long as few of them overlap. A cluster
+// keeps at most `MAX_CLUSTER_LIGHTS` lights.
+pub(crate) struct LightClusters {
+    pipeline: Arc<ComputePipeline>,
+    uniform_buffer: CpuBufferPool<light_culling::ty::Cluster_Data>,
+    // The number of lights of each cluster followed by their indices.
+    clusters: Arc<DeviceLocalBuffer<[u32]>>,
+}
+
+impl LightClusters {
+    pub(crate) fn new(
+        device: &Arc<Device>,
+        memory_allocator: &Arc<StandardMemoryAllocator>,
+    ) -> Result<LightClusters> {
+        let shader = light_culling::load(device.clone())?;
+        let pipeline = ComputePipeline::new(
+            device.clone(),
+            shader.entry_point("main").unwrap(),
+            &(),
+            None,
+            |_| {},
+        )?;
+
+        let clusters = DeviceLocalBuffer::array(
+            memory_allocator.as_ref(),
+            (cluster_count() * (MAX_CLUSTER_LIGHTS + 1)) as DeviceSize,
+            BufferUsage {
+                storage_buffer: true,
+                ..BufferUsage::empty()
+            },
+            [],
+        )?;
+
+        Ok(LightClusters {
+            pipeline,
+            uniform_buffer: CpuBufferPool::uniform_buffer(memory_allocator.clone()),
+            clusters,
+        })
+    }
+
+    // Records the binning of `lights`, a storage buffer of every light, into the clusters of
+    // `camera`'s view of a target of `dimensions`. Returns the uniforms describing the grid, to
+    // pass to `writes`.
+    pub(crate) fn record<L, A>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+        descriptor_set_allocator: &StandardDescriptorSetAllocator,
+        lights: Arc<dyn BufferAccess>,
+        camera: &Camera,
+        dimensions: [u32; 2],
+    ) -> Result<Arc<CpuBufferPoolSubbuffer<light_culling::ty::Cluster_Data>>>
+    where
+        A: CommandBufferAllocator,
+    {
+        let [x, y, z] = CLUSTER_GRID;
+        let uniforms = self
+            .uniform_buffer
+            .from_data(light_culling::ty::Cluster_Data {
+                view: camera.view().into(),
+                inverse_projection: camera.projection().try_inverse().unwrap_or_default().into(),
+                grid: [x, y, z, cluster_count()],
+                screen_size: [dimensions[0] as f32, dimensions[1] as f32],
+                near: camera.near,
+                far: camera.far,
+            })?;
+
+        let set = PersistentDescriptorSet::new(
+            descriptor_set_allocator,
+            self.pipeline.layout().set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::buffer(2, lights),
+                WriteDescriptorSet::buffer(15, uniforms.clone()),
+                WriteDescriptorSet::buffer(16, self.clusters.clone()),
+            ],
+        )?;
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                set,
+            )
+            .dispatch([cluster_count().div_ceil(WORK_GROUP_SIZE), 1, 1])?;
+
+        Ok(uniforms)
+    }
+
+    // Writes of the bindings the fragment shader reads the clusters from, to add to each draw's
+    // descriptor set. `uniforms` is what `record` returned for the frame.
+    pub(crate) fn writes(
+        &self,
+        uniforms: &Arc<CpuBufferPoolSubbuffer<light_culling::ty::Cluster_Data>>,
+    ) -> [WriteDescriptorSet; 2] {
+        [
+            WriteDescriptorSet::buffer(15, uniforms.clone()),
+            WriteDescriptorSet::buffer(16, self.clusters.clone()),
+        ]
+    }
+}
+
+fn cluster_count() -> u32 {
+    CLUSTER_GRID.iter().product()
+}
@@ -0,0 +1,395 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use nalgebra_glm::TMat4;
+use vulkano::{
+    buffer::CpuBufferPool,
+    command_buffer::{
+        allocator::CommandBufferAllocator, AutoCommandBufferBuilder, RenderPassBeginInfo,
+        SubpassContents,
+    },
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, PersistentDescriptorSet, WriteDescriptorSet,
+    },
+    device::Device,
+    format::Format,
+    image::{
+        view::{ImageView, ImageViewAbstract},
+        AttachmentImage, ImageCreateFlags, ImageDimensions, ImageLayout, ImageUsage,
+    },
+    memory::allocator::StandardMemoryAllocator,
+    pipeline::{
+        graphics::{
+            depth_stencil::{CompareOp, DepthStencilState},
+            input_assembly::InputAssemblyState,
+            rasterization::{CullMode, RasterizationState},
+            vertex_input::BuffersDefinition,
+            viewport::{Viewport, ViewportState},
+        },
+        GraphicsPipeline, Pipeline, PipelineBindPoint, StateMode,
+    },
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+};
+
+use crate::{
+    gpu_image::GpuImage,
+    material::Material,
+    mesh::Mesh,
+    post::HDR_FORMAT,
+    shaders::{deferred_lighting_fs, gbuffer_fs, post_vs, vs},
+    ssao::{DepthPrepass, PrepassDraw},
+    vertex::Vertex,
+};
+
+// Formats of the G-buffer images, in the order of the `gbuffer_fs` outputs: albedo, normal,
+// material parameters and reflectance.
+const GBUFFER_FORMATS: [Format; 4] = [
+    Format::R8G8B8A8_SRGB,
+    Format::R16G16B16A16_SFLOAT,
+    Format::R16G16B16A16_SFLOAT,
+    Format::R16G16B16A16_SFLOAT,
+];
+
+// Positions are reconstructed from depth, so the most precise format the device can render to
+// and sample, for SSAO, is used. Every device supports the last one.
+const DEPTH_FORMATS: [Format; 2] = [Format::D32_SFLOAT, Format::D16_UNORM];
+
+// Draws the scene in two subpasses: the first writes the surface of each pixel into the
+// G-buffer, the second reads it back through input attachments and lights every pixel once,
+// however many nodes cover it. Only the depth outlives the pass, for SSAO, which runs before it
+// on the depth of a depth prepass. Unlike the forward path, the number of lights isn't limited
+// and multisampling isn't supported.
+pub(crate) struct Deferred {
+    render_pass: Arc<RenderPass>,
+    // The same pass keeping the depth of the depth prepass rather than clearing it.
+    keep_depth_render_pass: Arc<RenderPass>,
+    depth_prepass: DepthPrepass,
+    // Indexed by whether the material is double-sided.
+    gbuffer_pipelines: [Arc<GraphicsPipeline>; 2],
+    lighting_pipeline: Arc<GraphicsPipeline>,
+    depth_format: Format,
+    uniform_buffer: CpuBufferPool<deferred_lighting_fs::ty::Deferred_Data>,
+}
+
+// Sized like the target, see `Deferred::framebuffers`.
+pub(crate) struct DeferredFramebuffers {
+    pub(crate) scene: Arc<Framebuffer>,
+    pub(crate) depth_prepass: Arc<Framebuffer>,
+    // The G-buffer's depth, for SSAO.
+    pub(crate) depth: Arc<ImageView<GpuImage>>,
+}
+
+impl Deferred {
+    pub(crate) fn new(
+        device: &Arc<Device>,
+        memory_allocator: &Arc<StandardMemoryAllocator>,
+    ) -> Result<Deferred> {
+        let depth_format = DEPTH_FORMATS
+            .into_iter()
+            .find(|&format| {
+                device
+                    .physical_device()
+                    .format_properties(format)
+                    .is_ok_and(|properties| {
+                        let features = properties.optimal_tiling_features;
+                        features.depth_stencil_attachment && features.sampled_image
+                    })
+            })
+            .context("no supported depth format")?;
+
+        let render_pass = create_render_pass(device, depth_format, false)?;
+        let keep_depth_render_pass = create_render_pass(device, depth_format, true)?;
+
+        // Fragments at the same depth as the depth buffer pass, so nodes are still drawn over the
+        // depth of the depth prepass.
+        let mut depth_stencil_state = DepthStencilState::simple_depth_test();
+        if let Some(depth) = &mut depth_stencil_state.depth {
+            depth.compare_op = StateMode::Fixed(CompareOp::LessOrEqual);
+        }
+        let vs = vs::load(device.clone())?;
+        let gbuffer_fs = gbuffer_fs::load(device.clone())?;
+        let gbuffer_pipeline = |cull_mode| {
+            GraphicsPipeline::start()
+                .vertex_input_state(BuffersDefinition::new().vertex::<Vertex>())
+                .vertex_shader(vs.entry_point("main").unwrap(), ())
+                .input_assembly_state(InputAssemblyState::new())
+                .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+                .fragment_shader(gbuffer_fs.entry_point("main").unwrap(), ())
+                .depth_stencil_state(depth_stencil_state.clone())
+                .rasterization_state(RasterizationState::new().cull_mode(cull_mode))
+                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+        };
+        let gbuffer_pipelines = [
+            gbuffer_pipeline(CullMode::Back)?,
+            gbuffer_pipeline(CullMode::None)?,
+        ];
+
+        let post_vs = post_vs::load(device.clone())?;
+        let lighting_fs = deferred_lighting_fs::load(device.clone())?;
+        let lighting_pipeline = GraphicsPipeline::start()
+            .vertex_input_state(BuffersDefinition::new())
+            .vertex_shader(post_vs.entry_point("main").unwrap(), ())
+            .input_assembly_state(InputAssemblyState::new())
+            .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+            .fragment_shader(lighting_fs.entry_point("main").unwrap(), ())
+            .render_pass(Subpass::from(render_pass.clone(), 1).unwrap())
+            .build(device.clone())?;
+
+        Ok(Deferred {
+            render_pass,
+            keep_depth_render_pass,
+            depth_prepass: DepthPrepass::new(device, depth_format)?,
+            gbuffer_pipelines,
+            lighting_pipeline,
+            depth_format,
+            uniform_buffer: CpuBufferPool::uniform_buffer(memory_allocator.clone()),
+        })
+    }
+
+    // Allocates the G-buffer, which only lives during the pass, and the framebuffers of the
+    // pass, lighting into `hdr`, and of the depth prepass.
+    pub(crate) fn framebuffers(
+        &self,
+        memory_allocator: &StandardMemoryAllocator,
+        dimensions: [u32; 2],
+        hdr: Arc<ImageView<GpuImage>>,
+    ) -> Result<DeferredFramebuffers> {
+        let mut attachments: Vec<Arc<dyn ImageViewAbstract>> = Vec::new();
+        for format in GBUFFER_FORMATS {
+            attachments.push(ImageView::new_default(
+                AttachmentImage::transient_input_attachment(memory_allocator, dimensions, format)?,
+            )?);
+        }
+        let depth = ImageView::new_default(GpuImage::new(
+            memory_allocator,
+            ImageDimensions::Dim2d {
+                width: dimensions[0],
+                height: dimensions[1],
+                array_layers: 1,
+            },
+            1,
+            self.depth_format,
+            ImageUsage {
+                depth_stencil_attachment: true,
+                input_attachment: true,
+                sampled: true,
+                ..ImageUsage::empty()
+            },
+            ImageCreateFlags::empty(),
+        )?)?;
+        attachments.push(depth.clone());
+        attachments.push(hdr);
+
+        let scene = Framebuffer::new(
+            self.render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments,
+                ..Default::default()
+            },
+        )?;
+        let depth_prepass = self.depth_prepass.framebuffer(&depth)?;
+
+        Ok(DeferredFramebuffers {
+            scene,
+            depth_prepass,
+            depth,
+        })
+    }
+
+    // Records the depth prepass of `draws` into `framebuffer`, for SSAO.
+    pub(crate) fn record_depth<L, A>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+        descriptor_set_allocator: &StandardDescriptorSetAllocator,
+        framebuffer: &Arc<Framebuffer>,
+        draws: Vec<PrepassDraw>,
+    ) -> Result<()>
+    where
+        A: CommandBufferAllocator,
+    {
+        self.depth_prepass
+            .record(builder, descriptor_set_allocator, framebuffer, draws)
+    }
+
+    // Records the pass into `framebuffer`, keeping the depth of the depth prepass when
+    // `keep_depth` is set. `draws` holds each mesh and material with the writes of its descriptor
+    // set for `vs` and `gbuffer_fs`; blended materials are drawn as opaque. `lighting` holds the
+    // writes shared with the forward path's descriptor set, with a storage buffer of all lights
+    // in binding 2.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn record<L, A>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+        descriptor_set_allocator: &StandardDescriptorSetAllocator,
+        framebuffer: &Arc<Framebuffer>,
+        keep_depth: bool,
+        draws: Vec<(&Arc<Mesh>, &Material, Vec<WriteDescriptorSet>)>,
+        mut lighting: Vec<WriteDescriptorSet>,
+        view_projection: &TMat4<f32>,
+    ) -> Result<()>
+    where
+        A: CommandBufferAllocator,
+    {
+        let [width, height] = framebuffer.extent();
+        let mut clear_values = vec![Some([0.0; 4].into()); GBUFFER_FORMATS.len()];
+        clear_values.push((!keep_depth).then(|| 1f32.into()));
+        clear_values.push(Some([0.0, 0.0, 0.0, 1.0].into()));
+        let render_pass = if keep_depth {
+            &self.keep_depth_render_pass
+        } else {
+            &self.render_pass
+        };
+
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    render_pass: render_pass.clone(),
+                    clear_values,
+                    ..RenderPassBeginInfo::framebuffer(framebuffer.clone())
+                },
+                SubpassContents::Inline,
+            )?
+            .set_viewport(
+                0,
+                [Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [width as f32, height as f32],
+                    depth_range: 0.0..1.0,
+                }],
+            );
+
+        for (mesh, material, writes) in draws {
+            let pipeline = &self.gbuffer_pipelines[material.double_sided as usize];
+            let layout = &pipeline.layout().set_layouts()[0];
+            let set =
+                PersistentDescriptorSet::new(descriptor_set_allocator, layout.clone(), writes)?;
+            builder
+                .bind_pipeline_graphics(pipeline.clone())
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    pipeline.layout().clone(),
+                    0,
+                    set,
+                );
+            mesh.draw(builder)?;
+        }
+
+        let uniforms = self
+            .uniform_buffer
+            .from_data(deferred_lighting_fs::ty::Deferred_Data {
+                inverse_view_projection: view_projection.try_inverse().unwrap_or_default().into(),
+            })?;
+        lighting.push(WriteDescriptorSet::buffer(0, uniforms));
+        let set_layouts = self.lighting_pipeline.layout().set_layouts();
+        let frame_set = PersistentDescriptorSet::new(
+            descriptor_set_allocator,
+            set_layouts[0].clone(),
+            lighting,
+        )?;
+        let gbuffer_set = PersistentDescriptorSet::new(
+            descriptor_set_allocator,
+            set_layouts[1].clone(),
+            framebuffer.attachments()[..=GBUFFER_FORMATS.len()]
+                .iter()
+                .enumerate()
+                .map(|(binding, view)| {
+                    WriteDescriptorSet::image_view(binding as u32, view.clone())
+                }),
+        )?;
+
+        builder
+            .next_subpass(SubpassContents::Inline)?
+            .bind_pipeline_graphics(self.lighting_pipeline.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.lighting_pipeline.layout().clone(),
+                0,
+                vec![frame_set, gbuffer_set],
+            )
+            .draw(3, 1, 0, 0)?
+            .end_render_pass()?;
+
+        Ok(())
+    }
+}
+
+// The render pass of `Deferred`, clearing the depth or keeping that of the depth prepass. The
+// two are compatible, so they share pipelines and framebuffers. Only the lit image is stored; it
+// and the depth stay in the general layout outside the pass, so post-processing and SSAO can
+// sample them.
+fn create_render_pass(
+    device: &Arc<Device>,
+    depth_format: Format,
+    keep_depth: bool,
+) -> Result<Arc<RenderPass>> {
+    macro_rules! render_pass {
+        ($depth_load:ident) => {
+            vulkano::ordered_passes_renderpass!(
+                device.clone(),
+                attachments: {
+                    albedo: {
+                        load: Clear,
+                        store: DontCare,
+                        format: GBUFFER_FORMATS[0],
+                        samples: 1,
+                    },
+                    normal: {
+                        load: Clear,
+                        store: DontCare,
+                        format: GBUFFER_FORMATS[1],
+                        samples: 1,
+                    },
+                    material: {
+                        load: Clear,
+                        store: DontCare,
+                        format: GBUFFER_FORMATS[2],
+                        samples: 1,
+                    },
+                    reflectance: {
+                        load: Clear,
+                        store: DontCare,
+                        format: GBUFFER_FORMATS[3],
+                        samples: 1,
+                    },
+                    depth: {
+                        load: $depth_load,
+                        store: DontCare,
+                        format: depth_format,
+                        samples: 1,
+                        initial_layout: ImageLayout::General,
+                        final_layout: ImageLayout::General,
+                    },
+                    color: {
+                        load: Clear,
+                        store: Store,
+                        format: HDR_FORMAT,
+                        samples: 1,
+                        initial_layout: ImageLayout::General,
+                        final_layout: ImageLayout::General,
+                    }
+                },
+                passes: [
+                    {
+                        color: [albedo, normal, material, reflectance],
+                        depth_stencil: {depth},
+                        input: []
+                    },
+                    {
+                        color: [color],
+                        depth_stencil: {},
+                        input: [albedo, normal, material, reflectance, depth]
+                    }
+                ]
+            )
+        };
+    }
+
+    let render_pass = if keep_depth {
+        render_pass!(Load)?
+    } else {
+        render_pass!(Clear)?
+    };
+
+    Ok(render_pass)
+}
@@ -0,0 +1,504 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{Context, Result};
+use vulkano::{
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, BlitImageInfo,
+        ClearColorImageInfo, CommandBufferUsage, CopyBufferToImageInfo, ImageBlit,
+        PrimaryAutoCommandBuffer,
+    },
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, PersistentDescriptorSet, WriteDescriptorSet,
+    },
+    device::{Device, Queue},
+    format::Format,
+    image::{
+        view::{ImageView, ImageViewCreateInfo, ImageViewType},
+        ImageAccess, ImageCreateFlags, ImageDimensions, ImageLayout, ImageSubresourceLayers,
+        ImageSubresourceRange, ImageUsage,
+    },
+    memory::allocator::StandardMemoryAllocator,
+    pipeline::{ComputePipeline, Pipeline, PipelineBindPoint},
+    sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode},
+    shader::ShaderModule,
+    sync::{self, GpuFuture},
+};
+
+use crate::{
+    gpu_image::GpuImage,
+    renderer::Renderer,
+    shaders::{brdf_lut, equirect_to_cube, irradiance, prefilter},
+};
+
+// Resolution of the cube map the equirectangular image is converted to. It gets a full mip
+// chain, which the prefiltering reads from.
+const ENVIRONMENT_SIZE: u32 = 512;
+// Diffuse lighting varies slowly with the normal, so a small map is enough.
+const IRRADIANCE_SIZE: u32 = 32;
+// Size of the sharpest level of the prefiltered map. Each further mip level is filtered for a
+// higher roughness, up to 1 at the last one.
+const PREFILTERED_SIZE: u32 = 128;
+const PREFILTERED_MIP_LEVELS: u32 = 5;
+const BRDF_LUT_SIZE: u32 = 256;
+
+// Format of every map written by the compute shaders, matching their `rgba16f` images.
+const FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
+const USAGE: ImageUsage = ImageUsage {
+    storage: true,
+    sampled: true,
+    transfer_dst: true,
+    ..ImageUsage::empty()
+};
+
+// Threads per work group in x and y of the compute shaders.
+const GROUP_SIZE: u32 = 8;
+
+// Linear RGBA pixels of a high dynamic range image, row by row from the top.
+#[derive(Debug, Clone)]
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<f32>,
+}
+
+// Loads a Radiance `.hdr` file.
+pub fn load_hdr(path: impl AsRef<Path>) -> Result<HdrImage> {
+    let path = path.as_ref();
+    let image = image::open(path)
+        .with_context(|| format!("failed to load {}", path.display()))?
+        .into_rgba32f();
+
+    Ok(HdrImage {
+        width: image.width(),
+        height: image.height(),
+        rgba: image.into_raw(),
+    })
+}
+
+// Lighting from an environment map, precomputed for image-based lighting: the irradiance map
+// gives the diffuse lighting for each normal, the prefiltered map the specular reflection for
+// each direction and roughness, and the BRDF lookup table the scale and bias applied to the
+// reflectance at normal incidence.
+#[derive(Debug)]
+pub struct Environment {
+    pub(crate) irradiance: Arc<ImageView<GpuImage>>,
+    pub(crate) prefiltered: Arc<ImageView<GpuImage>>,
+    pub(crate) brdf_lut: Arc<ImageView<GpuImage>>,
+    pub(crate) sampler: Arc<Sampler>,
+}
+
+impl Environment {
+    pub fn load(renderer: &Renderer, path: impl AsRef<Path>) -> Result<Environment> {
+        Environment::from_equirect(renderer, &load_hdr(path)?)
+    }
+
+    // Converts an equirectangular image, whose top row looks straight up, to a cube map and
+    // precomputes the lighting maps from it on the GPU.
+    pub fn from_equirect(renderer: &Renderer, image: &HdrImage) -> Result<Environment> {
+        let precompute = Precompute::new(renderer.queue(), renderer.memory_allocator());
+        let sampler = precompute.sampler()?;
+
+        let equirect_sampler = Sampler::new(
+            precompute.device().clone(),
+            SamplerCreateInfo {
+                // Linear filtering of 32-bit floats is an optional feature.
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                address_mode: [
+                    SamplerAddressMode::Repeat,
+                    SamplerAddressMode::ClampToEdge,
+                    SamplerAddressMode::ClampToEdge,
+                ],
+                ..Default::default()
+            },
+        )?;
+        let equirect = GpuImage::new(
+            precompute.memory_allocator,
+            ImageDimensions::Dim2d {
+                width: image.width,
+                height: image.height,
+                array_layers: 1,
+            },
+            1,
+            Format::R32G32B32A32_SFLOAT,
+            USAGE,
+            ImageCreateFlags::empty(),
+        )?;
+
+        let cube_dimensions = |size| ImageDimensions::Dim2d {
+            width: size,
+            height: size,
+            array_layers: 6,
+        };
+        let cube_flags = ImageCreateFlags {
+            cube_compatible: true,
+            ..ImageCreateFlags::empty()
+        };
+        let cube = GpuImage::new(
+            precompute.memory_allocator,
+            cube_dimensions(ENVIRONMENT_SIZE),
+            ENVIRONMENT_SIZE.ilog2() + 1,
+            FORMAT,
+            ImageUsage {
+                transfer_src: true,
+                ..USAGE
+            },
+            cube_flags,
+        )?;
+        let irradiance = GpuImage::new(
+            precompute.memory_allocator,
+            cube_dimensions(IRRADIANCE_SIZE),
+            1,
+            FORMAT,
+            USAGE,
+            cube_flags,
+        )?;
+        let prefiltered = GpuImage::new(
+            precompute.memory_allocator,
+            cube_dimensions(PREFILTERED_SIZE),
+            PREFILTERED_MIP_LEVELS,
+            FORMAT,
+            USAGE,
+            cube_flags,
+        )?;
+        let brdf_lut = GpuImage::new(
+            precompute.memory_allocator,
+            ImageDimensions::Dim2d {
+                width: BRDF_LUT_SIZE,
+                height: BRDF_LUT_SIZE,
+                array_layers: 1,
+            },
+            1,
+            FORMAT,
+            USAGE,
+            ImageCreateFlags::empty(),
+        )?;
+
+        let staging = renderer.uploader().staging_buffer(&image.rgba)?;
+        let device = precompute.device();
+        let equirect_to_cube = precompute.pipeline(equirect_to_cube::load(device.clone())?)?;
+        let irradiance_pipeline = precompute.pipeline(irradiance::load(device.clone())?)?;
+        let prefilter = precompute.pipeline(prefilter::load(device.clone())?)?;
+        let brdf_lut_pipeline = precompute.pipeline(brdf_lut::load(device.clone())?)?;
+
+        precompute.submit(|builder, descriptor_set_allocator| {
+            builder.copy_buffer_to_image(CopyBufferToImageInfo {
+                dst_image_layout: ImageLayout::General,
+                ..CopyBufferToImageInfo::buffer_image(staging, equirect.clone())
+            })?;
+
+            precompute.bind(
+                builder,
+                descriptor_set_allocator,
+                &equirect_to_cube,
+                [
+                    WriteDescriptorSet::image_view_sampler(
+                        0,
+                        ImageView::new_default(equirect.clone())?,
+                        equirect_sampler,
+                    ),
+                    WriteDescriptorSet::image_view(1, array_view(&cube, 0)?),
+                ],
+            )?;
+            builder.dispatch(group_counts([ENVIRONMENT_SIZE, ENVIRONMENT_SIZE, 6]))?;
+            generate_mip_levels(builder, &cube)?;
+
+            let cube_view = cube_view(&cube)?;
+            precompute.bind(
+                builder,
+                descriptor_set_allocator,
+                &irradiance_pipeline,
+                [
+                    WriteDescriptorSet::image_view_sampler(0, cube_view.clone(), sampler.clone()),
+                    WriteDescriptorSet::image_view(1, array_view(&irradiance, 0)?),
+                ],
+            )?;
+            builder.dispatch(group_counts([IRRADIANCE_SIZE, IRRADIANCE_SIZE, 6]))?;
+
+            for mip_level in 0..PREFILTERED_MIP_LEVELS {
+                let size = PREFILTERED_SIZE >> mip_level;
+                let roughness = mip_level as f32 / (PREFILTERED_MIP_LEVELS - 1) as f32;
+                precompute.bind(
+                    builder,
+                    descriptor_set_allocator,
+                    &prefilter,
+                    [
+                        WriteDescriptorSet::image_view_sampler(
+                            0,
+                            cube_view.clone(),
+                            sampler.clone(),
+                        ),
+                        WriteDescriptorSet::image_view(1, array_view(&prefiltered, mip_level)?),
+                    ],
+                )?;
+                builder
+                    .push_constants(
+                        prefilter.layout().clone(),
+                        0,
+                        prefilter::ty::Prefilter_Data { roughness },
+                    )
+                    .dispatch(group_counts([size, size, 6]))?;
+            }
+
+            precompute.bind(
+                builder,
+                descriptor_set_allocator,
+                &brdf_lut_pipeline,
+                [WriteDescriptorSet::image_view(
+                    0,
+                    ImageView::new_default(brdf_lut.clone())?,
+                )],
+            )?;
+            builder.dispatch(group_counts([BRDF_LUT_SIZE, BRDF_LUT_SIZE, 1]))?;
+
+            Ok(())
+        })?;
+
+        Ok(Environment {
+            irradiance: cube_view(&irradiance)?,
+            prefiltered: cube_view(&prefiltered)?,
+            brdf_lut: ImageView::new_default(brdf_lut)?,
+            sampler,
+        })
+    }
+
+    // Black maps, bound in place of an environment for scenes without one so the descriptor
+    // set layout stays the same.
+    pub(crate) fn empty(
+        queue: &Arc<Queue>,
+        memory_allocator: &StandardMemoryAllocator,
+    ) -> Result<Environment> {
+        let precompute = Precompute::new(queue, memory_allocator);
+        let cube_dimensions = ImageDimensions::Dim2d {
+            width: 1,
+            height: 1,
+            array_layers: 6,
+        };
+        let cube_flags = ImageCreateFlags {
+            cube_compatible: true,
+            ..ImageCreateFlags::empty()
+        };
+        let irradiance = GpuImage::new(
+            memory_allocator,
+            cube_dimensions,
+            1,
+            FORMAT,
+            USAGE,
+            cube_flags,
+        )?;
+        let prefiltered = GpuImage::new(
+            memory_allocator,
+            cube_dimensions,
+            1,
+            FORMAT,
+            USAGE,
+            cube_flags,
+        )?;
+        let brdf_lut = GpuImage::new(
+            memory_allocator,
+            ImageDimensions::Dim2d {
+                width: 1,
+                height: 1,
+                array_layers: 1,
+            },
+            1,
+            FORMAT,
+            USAGE,
+            ImageCreateFlags::empty(),
+        )?;
+
+        precompute.submit(|builder, _| {
+            for image in [&irradiance, &prefiltered, &brdf_lut] {
+                builder.clear_color_image(ClearColorImageInfo {
+                    image_layout: ImageLayout::General,
+                    ..ClearColorImageInfo::image(image.clone())
+                })?;
+            }
+            Ok(())
+        })?;
+
+        Ok(Environment {
+            irradiance: cube_view(&irradiance)?,
+            prefiltered: cube_view(&prefiltered)?,
+            brdf_lut: ImageView::new_default(brdf_lut)?,
+            sampler: precompute.sampler()?,
+        })
+    }
+
+    // Mip level of the prefiltered map filtered for a roughness of 1.
+    pub fn max_lod(&self) -> f32 {
+        (self.prefiltered.image().mip_levels() - 1) as f32
+    }
+}
+
+// Devices and allocators used while precomputing the maps. The work runs on the graphics queue,
+// which always supports compute.
+struct Precompute<'a> {
+    queue: &'a Arc<Queue>,
+    memory_allocator: &'a StandardMemoryAllocator,
+}
+
+impl<'a> Precompute<'a> {
+    fn new(queue: &'a Arc<Queue>, memory_allocator: &'a StandardMemoryAllocator) -> Precompute<'a> {
+        Precompute {
+            queue,
+            memory_allocator,
+        }
+    }
+
+    fn device(&self) -> &Arc<Device> {
+        self.queue.device()
+    }
+
+    // Trilinear sampler used for every precomputed map.
+    fn sampler(&self) -> Result<Arc<Sampler>> {
+        let sampler = Sampler::new(
+            self.device().clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                mipmap_mode: SamplerMipmapMode::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(sampler)
+    }
+
+    // Records commands with `record`, submits them and waits until they are done.
+    fn submit(
+        &self,
+        record: impl FnOnce(
+            &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+            &StandardDescriptorSetAllocator,
+        ) -> Result<()>,
+    ) -> Result<()> {
+        let command_buffer_allocator =
+            StandardCommandBufferAllocator::new(self.device().clone(), Default::default());
+        let descriptor_set_allocator = StandardDescriptorSetAllocator::new(self.device().clone());
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            &command_buffer_allocator,
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+        record(&mut builder, &descriptor_set_allocator)?;
+        let command_buffer = builder.build()?;
+
+        sync::now(self.device().clone())
+            .then_execute(self.queue.clone(), command_buffer)?
+            .then_signal_fence_and_flush()?
+            .wait(None)?;
+
+        Ok(())
+    }
+
+    fn pipeline(&self, shader: Arc<ShaderModule>) -> Result<Arc<ComputePipeline>> {
+        let pipeline = ComputePipeline::new(
+            self.device().clone(),
+            shader.entry_point("main").unwrap(),
+            &(),
+            None,
+            |_| {},
+        )?;
+
+        Ok(pipeline)
+    }
+
+    // Binds `pipeline` with its descriptor set 0 made of `writes`.
+    fn bind(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        descriptor_set_allocator: &StandardDescriptorSetAllocator,
+        pipeline: &Arc<ComputePipeline>,
+        writes: impl IntoIterator<Item = WriteDescriptorSet>,
+    ) -> Result<()> {
+        let layout = pipeline.layout();
+        let set = PersistentDescriptorSet::new(
+            descriptor_set_allocator,
+            layout.set_layouts().first().unwrap().clone(),
+            writes,
+        )?;
+
+        builder
+            .bind_pipeline_compute(pipeline.clone())
+            .bind_descriptor_sets(PipelineBindPoint::Compute, layout.clone(), 0, set);
+
+        Ok(())
+    }
+}
+
+// Work groups covering `size` invocations, with one layer per group in z.
+fn group_counts(size: [u32; 3]) -> [u32; 3] {
+    [
+        size[0].div_ceil(GROUP_SIZE),
+        size[1].div_ceil(GROUP_SIZE),
+        size[2],
+    ]
+}
+
+// Fills every mip level of `image` after the first by blitting the level above into it.
+fn generate_mip_levels(
+    builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    image: &Arc<GpuImage>,
+) -> Result<()> {
+    let [width, height, _] = image.dimensions().width_height_depth();
+    for mip_level in 1..image.mip_levels() {
+        let extent = |level: u32| [(width >> level).max(1), (height >> level).max(1), 1];
+        builder.blit_image(BlitImageInfo {
+            src_image_layout: ImageLayout::General,
+            dst_image_layout: ImageLayout::General,
+            regions: [ImageBlit {
+                src_subresource: ImageSubresourceLayers {
+                    mip_level: mip_level - 1,
+                    ..image.subresource_layers()
+                },
+                src_offsets: [[0; 3], extent(mip_level - 1)],
+                dst_subresource: ImageSubresourceLayers {
+                    mip_level,
+                    ..image.subresource_layers()
+                },
+                dst_offsets: [[0; 3], extent(mip_level)],
+                ..Default::default()
+            }]
+            .into(),
+            filter: Filter::Linear,
+            ..BlitImageInfo::images(image.clone(), image.clone())
+        })?;
+    }
+
+    Ok(())
+}
+
+// View of all six faces of a cube image, for sampling.
+fn cube_view(image: &Arc<GpuImage>) -> Result<Arc<ImageView<GpuImage>>> {
+    let view = ImageView::new(
+        image.clone(),
+        ImageViewCreateInfo {
+            view_type: ImageViewType::Cube,
+            ..ImageViewCreateInfo::from_image(image)
+        },
+    )?;
+
+    Ok(view)
+}
+
+// View of one mip level of a cube image as an array of six layers, for writing from a compute
+// shader.
+fn array_view(image: &Arc<GpuImage>, mip_level: u32) -> Result<Arc<ImageView<GpuImage>>> {
+    let view = ImageView::new(
+        image.clone(),
+        ImageViewCreateInfo {
+            view_type: ImageViewType::Dim2dArray,
+            subresource_range: ImageSubresourceRange {
+                mip_levels: mip_level..mip_level + 1,
+                ..image.subresource_range()
+            },
+            ..ImageViewCreateInfo::from_image(image)
+        },
+    )?;
+
+    Ok(view)
+}
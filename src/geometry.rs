@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+
+use nalgebra_glm::{
+    cross, identity, mat3_to_mat4, normalize, scaling, translation, vec2, vec3, vec4, TMat3, TMat4,
+    Vec2, Vec3,
+};
+
+use crate::{mikktspace, vertex::Vertex};
+
+// How normals are generated for meshes that don't provide their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NormalMode {
+    // Every triangle gets its face normal, giving a faceted look.
+    Flat,
+    // Face normals are averaged, weighted by area, over all triangles sharing a position.
+    #[default]
+    Smooth,
+}
+
+// Returns one normal per entry of `indices`, a counter-clockwise triangle list into `positions`.
+pub fn generate_normals(
+    positions: &[[f32; 3]],
+    indices: &[u32],
+    normal_mode: NormalMode,
+) -> Vec<[f32; 3]> {
+    let face_normal = |triangle: &[u32]| -> Vec3 {
+        let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(positions[triangle[i] as usize]));
+        // Not normalized, so the length is proportional to the triangle's area.
+        cross(&(b - a), &(c - a))
+    };
+
+    match normal_mode {
+        NormalMode::Flat => indices
+            .chunks(3)
+            .flat_map(|triangle| {
+                let normal = safe_normalize(face_normal(triangle));
+                [normal; 3]
+            })
+            .collect(),
+        NormalMode::Smooth => {
+            let mut sums = vec![Vec3::zeros(); positions.len()];
+            for triangle in indices.chunks(3) {
+                let normal = face_normal(triangle);
+                for &index in triangle {
+                    sums[index as usize] += normal;
+                }
+            }
+
+            indices
+                .iter()
+                .map(|&index| safe_normalize(sums[index as usize]))
+                .collect()
+        }
+    }
+}
+
+// Sets the `tangent` of every vertex with MikkTSpace, from the texture coordinates of the
+// triangles in `indices`, a counter-clockwise triangle list. MikkTSpace works on the corners of
+// the triangles, so a vertex whose corners end up with different tangents, e.g. on a mirrored
+// seam, is split, appending copies to `vertices` and pointing `indices` at them. The bitangent is
+// `cross(normal, tangent.xyz) * tangent.w`, pointing towards decreasing v, which is up in the
+// image. Vertices MikkTSpace has no tangent for get an arbitrary one.
+pub fn generate_tangents(vertices: &mut Vec<Vertex>, indices: &mut [u32]) {
+    let corners: Vec<Vertex> = indices.iter().map(|&i| vertices[i as usize]).collect();
+    let positions: Vec<Vec3> = corners.iter().map(|v| v.position.into()).collect();
+    let normals: Vec<Vec3> = corners.iter().map(|v| v.normal.into()).collect();
+    // MikkTSpace's t axis points up the image, like the texture coordinates of the tools baking
+    // normal maps, so v is flipped.
+    let uvs: Vec<Vec2> = corners.iter().map(|v| vec2(v.uv[0], -v.uv[1])).collect();
+    let spaces = mikktspace::corner_tangent_spaces(&positions, &normals, &uvs);
+
+    // Weld the corners back onto their vertices.
+    let mut assigned = vec![None; vertices.len()];
+    let mut copies = HashMap::new();
+    for (index, space) in indices.iter_mut().zip(spaces) {
+        let vertex = vertices[*index as usize];
+        let tangent = match space {
+            Some(space) => {
+                let [x, y, z] = space.tangent.into();
+                let w = if space.orientation_preserving {
+                    1.0
+                } else {
+                    -1.0
+                };
+                [x, y, z, w]
+            }
+            None => fallback_tangent(&vertex),
+        };
+        match assigned[*index as usize] {
+            None => {
+                assigned[*index as usize] = Some(tangent);
+                vertices[*index as usize].tangent = tangent;
+            }
+            Some(existing) if existing == tangent => {}
+            Some(_) => {
+                let key = (*index, tangent.map(f32::to_bits));
+                *index = *copies.entry(key).or_insert_with(|| {
+                    vertices.push(Vertex { tangent, ..vertex });
+                    vertices.len() as u32 - 1
+                });
+            }
+        }
+    }
+    for (vertex, assigned) in vertices.iter_mut().zip(assigned) {
+        if assigned.is_none() {
+            vertex.tangent = fallback_tangent(vertex);
+        }
+    }
+}
+
+fn fallback_tangent(vertex: &Vertex) -> [f32; 4] {
+    let [x, y, z] = perpendicular(&Vec3::from(vertex.normal)).into();
+    [x, y, z, 1.0]
+}
+
+// Some unit vector perpendicular to `v`, or the x axis if `v` is zero.
+fn perpendicular(v: &Vec3) -> Vec3 {
+    let axis = if v.x.abs() < 0.9 {
+        vec3(1.0, 0.0, 0.0)
+    } else {
+        vec3(0.0, 1.0, 0.0)
+    };
+    let tangent = axis - v * v.dot(&axis);
+    if tangent.norm_squared() > 0.0 {
+        tangent.normalize()
+    } else {
+        axis
+    }
+}
+
+// Uniformly scales and centers the vertices so they fit the same [-1, 1] box as the demo cube.
+pub fn fit_to_unit_cube(vertices: &mut [Vertex]) {
+    let transform = unit_cube_transform(vertices);
+    for vertex in vertices.iter_mut() {
+        let [x, y, z] = vertex.position;
+        vertex.position = (transform * vec4(x, y, z, 1.0)).xyz().into();
+    }
+}
+
+// The transform `fit_to_unit_cube` applies, for geometry that is placed through a scene graph
+// rather than baked into its vertices.
+pub fn unit_cube_transform(vertices: &[Vertex]) -> TMat4<f32> {
+    if vertices.is_empty() {
+        return identity();
+    }
+
+    let bounds = Bounds::of_vertices(vertices).unwrap();
+    let extent = (bounds.max - bounds.min).max();
+    let scale = if extent > 0.0 { 2.0 / extent } else { 1.0 };
+    scaling(&Vec3::repeat(scale)) * translation(&-bounds.center())
+}
+
+// The inverse transpose of the upper 3x3 of `world`, which keeps normals perpendicular to the
+// surface under non-uniform scaling, in a 4x4 matrix for uploading. Its columns are the cross
+// products of those of `world`, so a transform flattening one axis still gives the normals their
+// direction; the shader normalizes them anyway.
+pub fn normal_matrix(world: &TMat4<f32>) -> TMat4<f32> {
+    let [x, y, z] = [0, 1, 2].map(|i| world.column(i).xyz());
+    let cofactors = TMat3::from_columns(&[cross(&y, &z), cross(&z, &x), cross(&x, &y)]);
+    let determinant = x.dot(&cross(&y, &z));
+    if determinant != 0.0 {
+        mat3_to_mat4(&(cofactors / determinant))
+    } else {
+        mat3_to_mat4(&cofactors)
+    }
+}
+
+// Axis-aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Bounds {
+    // `None` when there are no points.
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Bounds> {
+        points.into_iter().fold(None, |bounds, point| {
+            Some(match bounds {
+                Some(Bounds { min, max }) => Bounds {
+                    min: min.inf(&point),
+                    max: max.sup(&point),
+                },
+                None => Bounds {
+                    min: point,
+                    max: point,
+                },
+            })
+        })
+    }
+
+    pub fn of_vertices(vertices: &[Vertex]) -> Option<Bounds> {
+        Bounds::from_points(vertices.iter().map(|vertex| Vec3::from(vertex.position)))
+    }
+
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        Bounds {
+            min: self.min.inf(&other.min),
+            max: self.max.sup(&other.max),
+        }
+    }
+
+    // Box around the transformed corners of this one.
+    pub fn transform(&self, transform: &TMat4<f32>) -> Bounds {
+        Bounds::from_points(self.corners().map(|corner| {
+            let corner = transform * vec4(corner.x, corner.y, corner.z, 1.0);
+            corner.xyz() / corner.w
+        }))
+        .unwrap()
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            vec3(min.x, min.y, min.z),
+            vec3(max.x, min.y, min.z),
+            vec3(min.x, max.y, min.z),
+            vec3(max.x, max.y, min.z),
+            vec3(min.x, min.y, max.z),
+            vec3(max.x, min.y, max.z),
+            vec3(min.x, max.y, max.z),
+            vec3(max.x, max.y, max.z),
+        ]
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) / 2.0
+    }
+
+    // Radius of the sphere through the corners.
+    pub fn radius(&self) -> f32 {
+        (self.max - self.min).norm() / 2.0
+    }
+}
+
+fn safe_normalize(v: Vec3) -> [f32; 3] {
+    if v.norm_squared() > 0.0 {
+        normalize(&v).into()
+    } else {
+        [0.0, 0.0, 0.0]
+    }
+}
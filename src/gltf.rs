@@ -0,0 +1,640 @@
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use nalgebra_glm::{inverse_transpose, mat4_to_mat3, normalize, vec3, vec4, TMat4};
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    sampler::{Filter, SamplerAddressMode, SamplerMipmapMode},
+};
+
+pub use gltf::{
+    material::AlphaMode,
+    texture::{MagFilter, MinFilter, WrappingMode},
+};
+
+pub use crate::texture::ImageData;
+
+use crate::{
+    geometry::{generate_normals, generate_tangents, NormalMode},
+    material::{self, Material},
+    mesh::{check_triangle_list, Mesh},
+    renderer::Renderer,
+    scene::{Node, NodeId, Scene},
+    texture::{ColorSpace, SamplerSettings, Texture as GpuTexture},
+    upload::Uploader,
+    vertex::Vertex,
+};
+
+// A glTF 2.0 asset (`.gltf` with external or embedded buffers, or binary `.glb`). Indices into
+// `nodes`, `meshes`, `materials`, `textures` and `images` match the ones in the file.
+#[derive(Debug, Clone, Default)]
+pub struct GltfScene {
+    // Nodes of the default scene (or the first one) that have no parent.
+    pub roots: Vec<usize>,
+    pub nodes: Vec<GltfNode>,
+    pub meshes: Vec<GltfMesh>,
+    pub materials: Vec<PbrMaterial>,
+    pub textures: Vec<Texture>,
+    pub images: Vec<ImageData>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GltfNode {
+    pub name: Option<String>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    // Transform relative to the parent node.
+    pub transform: TMat4<f32>,
+    pub mesh: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GltfMesh {
+    pub name: Option<String>,
+    pub primitives: Vec<Primitive>,
+}
+
+// A triangle list sharing one material. Vertex colors hold `COLOR_0`, or white when the
+// primitive has none, texture coordinates `TEXCOORD_0`, or zero, and tangents `TANGENT`, or ones
+// generated with `geometry::generate_tangents`; material factors are kept separately in
+// `PbrMaterial`.
+#[derive(Debug, Clone)]
+pub struct Primitive {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    pub material: Option<usize>,
+}
+
+// The metallic-roughness material model of glTF. Texture fields are indices into
+// `GltfScene::textures` and are sampled with `TEXCOORD_0`.
+#[derive(Debug, Clone)]
+pub struct PbrMaterial {
+    pub name: Option<String>,
+    pub base_color_factor: [f32; 4],
+    pub base_color_texture: Option<usize>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    // Metalness in the blue channel, roughness in the green channel.
+    pub metallic_roughness_texture: Option<usize>,
+    pub normal_texture: Option<usize>,
+    pub normal_scale: f32,
+    pub occlusion_texture: Option<usize>,
+    pub occlusion_strength: f32,
+    pub emissive_factor: [f32; 3],
+    pub emissive_texture: Option<usize>,
+    pub alpha_mode: AlphaMode,
+    // Only used with `AlphaMode::Mask`.
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
+}
+
+impl Default for PbrMaterial {
+    // The values the glTF specification uses for missing properties.
+    fn default() -> Self {
+        PbrMaterial {
+            name: None,
+            base_color_factor: [1.0, 1.0, 1.0, 1.0],
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive_factor: [0.0, 0.0, 0.0],
+            emissive_texture: None,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+        }
+    }
+}
+
+impl PbrMaterial {
+    // The factors of this material as a renderer material using `ShadingModel::Pbr`.
+    pub fn to_material(&self) -> Material {
+        let [r, g, b, a] = self.base_color_factor;
+        Material {
+            alpha: a,
+            alpha_mode: match self.alpha_mode {
+                AlphaMode::Opaque => material::AlphaMode::Opaque,
+                AlphaMode::Mask => material::AlphaMode::Mask {
+                    cutoff: self.alpha_cutoff,
+                },
+                AlphaMode::Blend => material::AlphaMode::Blend,
+            },
+            double_sided: self.double_sided,
+            emissive: self.emissive_factor,
+            normal_scale: self.normal_scale,
+            occlusion_strength: self.occlusion_strength,
+            ..Material::pbr([r, g, b], self.metallic_factor, self.roughness_factor)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Texture {
+    // Index into `GltfScene::images`.
+    pub image: usize,
+    pub sampler: Sampler,
+}
+
+// Filters left as `None` are up to the renderer.
+#[derive(Debug, Clone, Copy)]
+pub struct Sampler {
+    pub mag_filter: Option<MagFilter>,
+    pub min_filter: Option<MinFilter>,
+    pub wrap_s: WrappingMode,
+    pub wrap_t: WrappingMode,
+}
+
+impl Sampler {
+    // Missing filters are linear. Textures always get a mip chain, so the filters without
+    // mipmapping pick the closest level.
+    pub fn to_settings(&self) -> SamplerSettings {
+        let mag_filter = match self.mag_filter {
+            Some(MagFilter::Nearest) => Filter::Nearest,
+            Some(MagFilter::Linear) | None => Filter::Linear,
+        };
+        let (min_filter, mipmap_mode) = match self.min_filter {
+            Some(MinFilter::Nearest) | Some(MinFilter::NearestMipmapNearest) => {
+                (Filter::Nearest, SamplerMipmapMode::Nearest)
+            }
+            Some(MinFilter::Linear) | Some(MinFilter::LinearMipmapNearest) => {
+                (Filter::Linear, SamplerMipmapMode::Nearest)
+            }
+            Some(MinFilter::NearestMipmapLinear) => (Filter::Nearest, SamplerMipmapMode::Linear),
+            Some(MinFilter::LinearMipmapLinear) | None => {
+                (Filter::Linear, SamplerMipmapMode::Linear)
+            }
+        };
+        let address_mode = |wrap| match wrap {
+            WrappingMode::ClampToEdge => SamplerAddressMode::ClampToEdge,
+            WrappingMode::MirroredRepeat => SamplerAddressMode::MirroredRepeat,
+            WrappingMode::Repeat => SamplerAddressMode::Repeat,
+        };
+
+        SamplerSettings {
+            mag_filter,
+            min_filter,
+            mipmap_mode,
+            address_mode: [address_mode(self.wrap_s), address_mode(self.wrap_t)],
+            ..Default::default()
+        }
+    }
+}
+
+// Only triangle primitives are loaded; others are logged as warnings and skipped.
+pub fn load_gltf(path: impl AsRef<Path>) -> Result<GltfScene> {
+    let path = path.as_ref();
+    let (document, buffers, images) =
+        gltf::import(path).with_context(|| format!("failed to load {}", path.display()))?;
+
+    let mut scene = GltfScene {
+        images: images
+            .into_iter()
+            .map(convert_image)
+            .collect::<Result<_>>()?,
+        ..Default::default()
+    };
+
+    scene.textures = document
+        .textures()
+        .map(|texture| {
+            let sampler = texture.sampler();
+            Texture {
+                image: texture.source().index(),
+                sampler: Sampler {
+                    mag_filter: sampler.mag_filter(),
+                    min_filter: sampler.min_filter(),
+                    wrap_s: sampler.wrap_s(),
+                    wrap_t: sampler.wrap_t(),
+                },
+            }
+        })
+        .collect();
+
+    scene.materials = document.materials().map(convert_material).collect();
+
+    for mesh in document.meshes() {
+        let mut primitives = Vec::new();
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                log::warn!(
+                    "Skipping {:?} primitive of mesh {} in {}",
+                    primitive.mode(),
+                    mesh.index(),
+                    path.display()
+                );
+                continue;
+            }
+            primitives.push(convert_primitive(&primitive, &buffers)?);
+        }
+
+        scene.meshes.push(GltfMesh {
+            name: mesh.name().map(str::to_owned),
+            primitives,
+        });
+    }
+
+    scene.nodes = document
+        .nodes()
+        .map(|node| GltfNode {
+            name: node.name().map(str::to_owned),
+            parent: None,
+            children: node.children().map(|child| child.index()).collect(),
+            transform: TMat4::from(node.transform().matrix()),
+            mesh: node.mesh().map(|mesh| mesh.index()),
+        })
+        .collect();
+    for index in 0..scene.nodes.len() {
+        for child in scene.nodes[index].children.clone() {
+            scene.nodes[child].parent = Some(index);
+        }
+    }
+
+    scene.roots = match document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        Some(gltf_scene) => gltf_scene.nodes().map(|node| node.index()).collect(),
+        None => (0..scene.nodes.len())
+            .filter(|&index| scene.nodes[index].parent.is_none())
+            .collect(),
+    };
+
+    Ok(scene)
+}
+
+impl GltfScene {
+    // World transform of every node, in the same order as `nodes`.
+    pub fn world_transforms(&self) -> Vec<TMat4<f32>> {
+        let mut transforms = vec![None; self.nodes.len()];
+        for index in 0..self.nodes.len() {
+            self.world_transform(index, &mut transforms);
+        }
+        transforms.into_iter().map(Option::unwrap).collect()
+    }
+
+    fn world_transform(&self, index: usize, transforms: &mut [Option<TMat4<f32>>]) -> TMat4<f32> {
+        if let Some(transform) = transforms[index] {
+            return transform;
+        }
+
+        let node = &self.nodes[index];
+        let transform = match node.parent {
+            Some(parent) => self.world_transform(parent, transforms) * node.transform,
+            None => node.transform,
+        };
+        transforms[index] = Some(transform);
+        transform
+    }
+
+    // Uploads every primitive as a mesh and mirrors the node hierarchy into `scene` below
+    // `parent`. Primitives become child nodes of their glTF node, shaded with the PBR factors and
+    // textures of their material. All textures are uploaded in a single submission.
+    pub fn add_to_scene(
+        &self,
+        renderer: &Renderer,
+        scene: &mut Scene,
+        parent: Option<NodeId>,
+    ) -> Result<()> {
+        let uploader = renderer.uploader();
+        let materials = uploader.submit_graphics(|builder| {
+            let mut textures = HashMap::new();
+            let mut upload = |texture: Option<usize>, color_space| {
+                texture
+                    .map(|index| {
+                        self.upload_texture(uploader, builder, index, color_space, &mut textures)
+                    })
+                    .transpose()
+            };
+            self.materials
+                .iter()
+                .map(|material| {
+                    Ok(Material {
+                        base_color_texture: upload(material.base_color_texture, ColorSpace::Srgb)?,
+                        normal_texture: upload(material.normal_texture, ColorSpace::Linear)?,
+                        metallic_roughness_texture: upload(
+                            material.metallic_roughness_texture,
+                            ColorSpace::Linear,
+                        )?,
+                        occlusion_texture: upload(material.occlusion_texture, ColorSpace::Linear)?,
+                        emissive_texture: upload(material.emissive_texture, ColorSpace::Srgb)?,
+                        ..material.to_material()
+                    })
+                })
+                .collect::<Result<Vec<_>>>()
+        })?;
+
+        let meshes = self
+            .meshes
+            .iter()
+            .map(|mesh| {
+                mesh.primitives
+                    .iter()
+                    .map(|primitive| {
+                        let mesh =
+                            Mesh::new(uploader, &primitive.vertices, primitive.indices.clone())?;
+                        Ok(Arc::new(mesh))
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut stack: Vec<(usize, Option<NodeId>)> =
+            self.roots.iter().map(|&root| (root, parent)).collect();
+        while let Some((index, parent)) = stack.pop() {
+            let node = &self.nodes[index];
+            let id = scene.add_node(Node {
+                transform: node.transform,
+                parent,
+                ..Default::default()
+            });
+            stack.extend(node.children.iter().map(|&child| (child, Some(id))));
+
+            let Some(mesh) = node.mesh else {
+                continue;
+            };
+            for (primitive, gpu_mesh) in self.meshes[mesh].primitives.iter().zip(&meshes[mesh]) {
+                let material = primitive
+                    .material
+                    .map(|material| materials[material].clone())
+                    .unwrap_or_else(|| PbrMaterial::default().to_material());
+                scene.add_node(Node {
+                    parent: Some(id),
+                    mesh: Some(gpu_mesh.clone()),
+                    material,
+                    ..Default::default()
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    // Texture `index`, recorded into `builder` the first time a material uses it.
+    fn upload_texture(
+        &self,
+        uploader: &Uploader,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        index: usize,
+        color_space: ColorSpace,
+        textures: &mut HashMap<(usize, ColorSpace), Arc<GpuTexture>>,
+    ) -> Result<Arc<GpuTexture>> {
+        if let Some(texture) = textures.get(&(index, color_space)) {
+            return Ok(texture.clone());
+        }
+
+        let texture = self.textures[index];
+        let gpu_texture = Arc::new(GpuTexture::record(
+            uploader,
+            builder,
+            &self.images[texture.image],
+            color_space,
+            &texture.sampler.to_settings(),
+        )?);
+        textures.insert((index, color_space), gpu_texture.clone());
+        Ok(gpu_texture)
+    }
+
+    // Bakes the world transform of every node reachable from `roots` into its meshes and returns
+    // them as a single triangle list. Vertex colors are multiplied by the material's base color
+    // factor; textures are not applied.
+    pub fn flatten(&self) -> Vec<Vertex> {
+        let transforms = self.world_transforms();
+        let mut vertices = Vec::new();
+
+        let mut stack = self.roots.clone();
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            stack.extend(&node.children);
+
+            let Some(mesh) = node.mesh else {
+                continue;
+            };
+
+            let transform = transforms[index];
+            let normal_matrix = mat4_to_mat3(&inverse_transpose(transform));
+            // Transforms that mirror the geometry also flip its winding.
+            let flip_winding = transform.determinant() < 0.0;
+
+            for primitive in &self.meshes[mesh].primitives {
+                let factor = primitive
+                    .material
+                    .map(|material| self.materials[material].base_color_factor)
+                    .unwrap_or([1.0; 4]);
+
+                for triangle in primitive.indices.chunks(3) {
+                    let mut triangle = [triangle[0], triangle[1], triangle[2]];
+                    if flip_winding {
+                        triangle.swap(1, 2);
+                    }
+
+                    for index in triangle {
+                        let vertex = &primitive.vertices[index as usize];
+                        let [x, y, z] = vertex.position;
+                        let [nx, ny, nz] = vertex.normal;
+                        let [tx, ty, tz, tw] = vertex.tangent;
+                        let position = transform * vec4(x, y, z, 1.0);
+                        let normal = normal_matrix * vec3(nx, ny, nz);
+                        let tangent = (transform * vec4(tx, ty, tz, 0.0)).xyz();
+                        let tangent = if tangent.norm_squared() > 0.0 {
+                            let [tx, ty, tz] = normalize(&tangent).into();
+                            // Mirroring also flips the handedness.
+                            [tx, ty, tz, if flip_winding { -tw } else { tw }]
+                        } else {
+                            [0.0; 4]
+                        };
+                        vertices.push(Vertex {
+                            position: [position.x, position.y, position.z],
+                            normal: if normal.norm_squared() > 0.0 {
+                                normalize(&normal).into()
+                            } else {
+                                [0.0, 0.0, 0.0]
+                            },
+                            color: [0, 1, 2].map(|i| vertex.color[i] * factor[i]),
+                            uv: vertex.uv,
+                            tangent,
+                        });
+                    }
+                }
+            }
+        }
+
+        vertices
+    }
+}
+
+fn convert_primitive(
+    primitive: &gltf::Primitive,
+    buffers: &[gltf::buffer::Data],
+) -> Result<Primitive> {
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+    let Some(positions) = reader.read_positions() else {
+        bail!("primitive {} has no positions", primitive.index());
+    };
+    let mut positions: Vec<[f32; 3]> = positions.collect();
+    let mut indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..positions.len() as u32).collect(),
+    };
+    check_triangle_list(&indices, positions.len())
+        .with_context(|| format!("primitive {}", primitive.index()))?;
+    let mut colors: Vec<[f32; 3]> = match reader.read_colors(0) {
+        Some(colors) => colors.into_rgb_f32().collect(),
+        None => vec![[1.0, 1.0, 1.0]; positions.len()],
+    };
+    let mut tex_coords: Vec<[f32; 2]> = match reader.read_tex_coords(0) {
+        Some(tex_coords) => tex_coords.into_f32().collect(),
+        None => vec![[0.0, 0.0]; positions.len()],
+    };
+
+    let mut tangents: Option<Vec<[f32; 4]>> = reader.read_tangents().map(Iterator::collect);
+
+    let normals: Vec<[f32; 3]> = match reader.read_normals() {
+        Some(normals) => normals.collect(),
+        None => {
+            // Tangents are only meaningful with the normals they were made for.
+            tangents = None;
+            // The specification asks for flat normals, which means vertices can no longer be
+            // shared between triangles.
+            let normals = generate_normals(&positions, &indices, NormalMode::Flat);
+            positions = indices.iter().map(|&i| positions[i as usize]).collect();
+            colors = indices.iter().map(|&i| colors[i as usize]).collect();
+            tex_coords = indices.iter().map(|&i| tex_coords[i as usize]).collect();
+            indices = (0..indices.len() as u32).collect();
+            normals
+        }
+    };
+
+    let mut vertices: Vec<Vertex> = positions
+        .iter()
+        .zip(&normals)
+        .zip(&colors)
+        .zip(&tex_coords)
+        .map(|(((&position, &normal), &color), &uv)| Vertex {
+            position,
+            normal,
+            color,
+            uv,
+            ..Default::default()
+        })
+        .collect();
+    match tangents {
+        Some(tangents) => {
+            for (vertex, tangent) in vertices.iter_mut().zip(tangents) {
+                vertex.tangent = tangent;
+            }
+        }
+        None => generate_tangents(&mut vertices, &mut indices),
+    }
+
+    Ok(Primitive {
+        vertices,
+        indices,
+        material: primitive.material().index(),
+    })
+}
+
+fn convert_material(material: gltf::Material) -> PbrMaterial {
+    let pbr = material.pbr_metallic_roughness();
+    let normal_texture = material.normal_texture();
+    let occlusion_texture = material.occlusion_texture();
+
+    PbrMaterial {
+        name: material.name().map(str::to_owned),
+        base_color_factor: pbr.base_color_factor(),
+        base_color_texture: pbr.base_color_texture().map(|info| info.texture().index()),
+        metallic_factor: pbr.metallic_factor(),
+        roughness_factor: pbr.roughness_factor(),
+        metallic_roughness_texture: pbr
+            .metallic_roughness_texture()
+            .map(|info| info.texture().index()),
+        normal_texture: normal_texture.as_ref().map(|info| info.texture().index()),
+        normal_scale: normal_texture.as_ref().map_or(1.0, |info| info.scale()),
+        occlusion_texture: occlusion_texture
+            .as_ref()
+            .map(|info| info.texture().index()),
+        occlusion_strength: occlusion_texture
+            .as_ref()
+            .map_or(1.0, |info| info.strength()),
+        emissive_factor: material.emissive_factor(),
+        emissive_texture: material
+            .emissive_texture()
+            .map(|info| info.texture().index()),
+        alpha_mode: material.alpha_mode(),
+        alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+        double_sided: material.double_sided(),
+    }
+}
+
+fn convert_image(image: gltf::image::Data) -> Result<ImageData> {
+    use gltf::image::Format;
+
+    let pixels = &image.pixels;
+    let rgba = match image.format {
+        Format::R8 => pixels.iter().flat_map(|&l| [l, l, l, 255]).collect(),
+        Format::R8G8 => pixels
+            .chunks(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        Format::R8G8B8 => pixels
+            .chunks(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        Format::R8G8B8A8 => pixels.clone(),
+        Format::R16 | Format::R16G16 | Format::R16G16B16 | Format::R16G16B16A16 => {
+            // Keep the most significant byte of every channel.
+            let channels: Vec<u8> = pixels
+                .chunks(2)
+                .map(|c| (u16::from_ne_bytes([c[0], c[1]]) >> 8) as u8)
+                .collect();
+            expand_to_rgba(&channels, image.format)
+        }
+        Format::R32G32B32FLOAT | Format::R32G32B32A32FLOAT => {
+            let channels: Vec<u8> = pixels
+                .chunks(4)
+                .map(|c| {
+                    let value = f32::from_ne_bytes([c[0], c[1], c[2], c[3]]);
+                    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+                })
+                .collect();
+            expand_to_rgba(&channels, image.format)
+        }
+    };
+
+    if rgba.len() != (image.width * image.height * 4) as usize {
+        bail!(
+            "image of {}x{} pixels has {} bytes of RGBA data",
+            image.width,
+            image.height,
+            rgba.len()
+        );
+    }
+
+    Ok(ImageData {
+        width: image.width,
+        height: image.height,
+        rgba,
+    })
+}
+
+fn expand_to_rgba(channels: &[u8], format: gltf::image::Format) -> Vec<u8> {
+    use gltf::image::Format;
+
+    match format {
+        Format::R16 => channels.iter().flat_map(|&l| [l, l, l, 255]).collect(),
+        Format::R16G16 => channels
+            .chunks(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        Format::R16G16B16 | Format::R32G32B32FLOAT => channels
+            .chunks(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        _ => channels.to_vec(),
+    }
+}
@@ -0,0 +1,121 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use anyhow::Result;
+use vulkano::{
+    device::{Device, DeviceOwned},
+    format::Format,
+    image::{
+        sys::{Image, ImageCreateInfo, RawImage},
+        ImageAccess, ImageCreateFlags, ImageDescriptorLayouts, ImageDimensions, ImageInner,
+        ImageLayout, ImageUsage,
+    },
+    memory::{
+        allocator::{
+            AllocationCreateInfo, AllocationType, MemoryAllocatePreference, MemoryAllocator,
+            MemoryUsage, StandardMemoryAllocator,
+        },
+        DedicatedAllocation,
+    },
+};
+
+// Device-local image kept in the general layout throughout, so it can be written by compute
+// shaders or render passes and sampled in the same command buffer. Unlike `StorageImage` it can
+// have more than one mip level, and depth formats can be used as attachments.
+#[derive(Debug)]
+pub(crate) struct GpuImage {
+    inner: Arc<Image>,
+    // Set once the image has left the undefined layout, so later command buffers keep its
+    // contents instead of transitioning it from undefined again.
+    layout_initialized: AtomicBool,
+}
+
+impl GpuImage {
+    pub(crate) fn new(
+        memory_allocator: &StandardMemoryAllocator,
+        dimensions: ImageDimensions,
+        mip_levels: u32,
+        format: Format,
+        usage: ImageUsage,
+        flags: ImageCreateFlags,
+    ) -> Result<Arc<GpuImage>> {
+        let raw_image = RawImage::new(
+            memory_allocator.device().clone(),
+            ImageCreateInfo {
+                flags,
+                dimensions,
+                format: Some(format),
+                mip_levels,
+                usage,
+                ..Default::default()
+            },
+        )?;
+        let requirements = raw_image.memory_requirements()[0];
+        let allocation = unsafe {
+            memory_allocator.allocate_unchecked(AllocationCreateInfo {
+                requirements,
+                allocation_type: AllocationType::NonLinear,
+                usage: MemoryUsage::GpuOnly,
+                allocate_preference: MemoryAllocatePreference::Unknown,
+                dedicated_allocation: Some(DedicatedAllocation::Image(&raw_image)),
+                ..Default::default()
+            })?
+        };
+        // The allocation was made for the requirements of this image.
+        let inner = unsafe {
+            raw_image
+                .bind_memory_unchecked([allocation])
+                .map_err(|(err, _, _)| err)?
+        };
+
+        Ok(Arc::new(GpuImage {
+            inner: Arc::new(inner),
+            layout_initialized: AtomicBool::new(false),
+        }))
+    }
+}
+
+unsafe impl DeviceOwned for GpuImage {
+    fn device(&self) -> &Arc<Device> {
+        self.inner.device()
+    }
+}
+
+unsafe impl ImageAccess for GpuImage {
+    fn inner(&self) -> ImageInner<'_> {
+        ImageInner {
+            image: &self.inner,
+            first_layer: 0,
+            num_layers: self.inner.dimensions().array_layers(),
+            first_mipmap_level: 0,
+            num_mipmap_levels: self.inner.mip_levels(),
+        }
+    }
+
+    fn initial_layout_requirement(&self) -> ImageLayout {
+        ImageLayout::General
+    }
+
+    fn final_layout_requirement(&self) -> ImageLayout {
+        ImageLayout::General
+    }
+
+    unsafe fn layout_initialized(&self) {
+        self.layout_initialized.store(true, Ordering::SeqCst);
+    }
+
+    fn is_layout_initialized(&self) -> bool {
+        self.layout_initialized.load(Ordering::SeqCst)
+    }
+
+    fn descriptor_layouts(&self) -> Option<ImageDescriptorLayouts> {
+        Some(ImageDescriptorLayouts {
+            storage_image: ImageLayout::General,
+            combined_image_sampler: ImageLayout::General,
+            sampled_image: ImageLayout::General,
+            input_attachment: ImageLayout::General,
+        })
+    }
+}
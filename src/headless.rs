@@ -0,0 +1,94 @@
+use std::{fs::File, io::BufWriter, path::Path, sync::Arc};
+
+use anyhow::{Context, Result};
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer},
+    command_buffer::{AutoCommandBufferBuilder, CopyImageToBufferInfo, PrimaryAutoCommandBuffer},
+    format::Format,
+    image::{AttachmentImage, ImageUsage},
+    memory::allocator::StandardMemoryAllocator,
+};
+
+// Offscreen images are rendered as sRGB encoded 8-bit RGBA so the bytes copied back from the GPU
+// can be written to a PNG without any conversion.
+const COLOR_FORMAT: Format = Format::R8G8B8A8_SRGB;
+
+// Color attachment used in place of swapchain images when rendering without a window, plus the
+// host-visible buffer each frame is copied into.
+pub(crate) struct OffscreenTarget {
+    image: Arc<AttachmentImage>,
+    output_buffer: Arc<CpuAccessibleBuffer<[u8]>>,
+    dimensions: [u32; 2],
+}
+
+impl OffscreenTarget {
+    pub(crate) fn new(
+        memory_allocator: &StandardMemoryAllocator,
+        dimensions: [u32; 2],
+    ) -> Result<OffscreenTarget> {
+        let image = AttachmentImage::with_usage(
+            memory_allocator,
+            dimensions,
+            COLOR_FORMAT,
+            ImageUsage {
+                transfer_src: true,
+                ..ImageUsage::empty()
+            },
+        )?;
+
+        let output_buffer = CpuAccessibleBuffer::from_iter(
+            memory_allocator,
+            BufferUsage {
+                transfer_dst: true,
+                ..BufferUsage::empty()
+            },
+            true,
+            (0..dimensions[0] * dimensions[1] * 4).map(|_| 0u8),
+        )?;
+
+        Ok(OffscreenTarget {
+            image,
+            output_buffer,
+            dimensions,
+        })
+    }
+
+    pub(crate) fn format(&self) -> Format {
+        COLOR_FORMAT
+    }
+
+    pub(crate) fn image(&self) -> &Arc<AttachmentImage> {
+        &self.image
+    }
+
+    pub(crate) fn dimensions(&self) -> [u32; 2] {
+        self.dimensions
+    }
+
+    // Records the copy of the rendered image into the output buffer. Must be recorded after the
+    // render pass has ended.
+    pub(crate) fn copy_to_buffer(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<()> {
+        builder.copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+            self.image.clone(),
+            self.output_buffer.clone(),
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) fn read(&self) -> Result<Vec<u8>> {
+        Ok(self.output_buffer.read()?.to_vec())
+    }
+}
+
+pub fn write_png(path: &Path, width: u32, height: u32, rgba: &[u8]) -> Result<()> {
+    let file =
+        File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(rgba)?;
+    Ok(())
+}
@@ -0,0 +1,27 @@
+pub mod camera;
+mod clusters;
+mod deferred;
+pub mod environment;
+pub mod geometry;
+pub mod gltf;
+mod gpu_image;
+pub mod headless;
+pub mod material;
+pub mod mesh;
+mod mikktspace;
+pub mod obj;
+pub mod post;
+pub mod renderer;
+pub mod scene;
+pub mod shaders;
+pub mod shadow;
+pub mod ssao;
+pub mod texture;
+pub mod upload;
+pub mod vertex;
+
+pub use camera::Camera;
+pub use environment::Environment;
+pub use mesh::Mesh;
+pub use renderer::{RenderPath, RenderTarget, Renderer};
+pub use scene::Scene;
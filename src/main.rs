@@ -1,684 +1,429 @@
-use std::{sync::Arc, time::Instant};
-
-use nalgebra_glm::{
-    identity, look_at, perspective, pi, rotate_normalized_axis, translate, vec3, TMat4,
-};
-use vertex::Vertex;
-use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
-use vulkano::command_buffer::RenderPassBeginInfo;
-use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
-use vulkano::memory::allocator::{GenericMemoryAllocator, StandardMemoryAllocator};
-use vulkano::swapchain::SwapchainPresentInfo;
-use vulkano::{
-    buffer::{BufferUsage, CpuAccessibleBuffer, CpuBufferPool, TypedBufferAccess},
-    command_buffer::AutoCommandBufferBuilder,
-    descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet},
-    device::{
-        physical::PhysicalDevice, Device, DeviceCreateInfo, DeviceExtensions, QueueCreateInfo,
-    },
-    format::Format,
-    image::{view::ImageView, AttachmentImage, ImageAccess, SwapchainImage},
-    instance::{Instance, InstanceCreateInfo},
-    pipeline::{
-        graphics::{
-            depth_stencil::DepthStencilState,
-            input_assembly::InputAssemblyState,
-            rasterization::{CullMode, RasterizationState},
-            vertex_input::BuffersDefinition,
-            viewport::{Viewport, ViewportState},
-        },
-        GraphicsPipeline, Pipeline, PipelineBindPoint,
-    },
-    render_pass::{Framebuffer, RenderPass, Subpass},
-    swapchain::{self, AcquireError, Swapchain, SwapchainCreateInfo, SwapchainCreationError},
-    sync::{self, FlushError, GpuFuture},
-    Version, VulkanLibrary,
+use std::{path::PathBuf, sync::Arc, time::Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use rufix::{
+    geometry::{fit_to_unit_cube, unit_cube_transform, NormalMode},
+    gltf,
+    headless::write_png,
+    material::Material,
+    obj,
+    post::{Bloom, ColorGrading, Fxaa, PostEffect, ToneMapOperator, ToneMapping, Vignette},
+    renderer::create_instance,
+    scene::{demo_transform, Node, NodeId},
+    texture::{ColorSpace, SamplerSettings, Texture},
+    Environment, Mesh, RenderPath, RenderTarget, Renderer, Scene,
 };
+use vulkano::{sampler::SamplerAddressMode, VulkanLibrary};
 use vulkano_win::VkSurfaceBuild;
 use winit::{
-    event::WindowEvent,
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::{Window, WindowBuilder},
+    window::WindowBuilder,
 };
 
-use crate::{
-    shaders::{fs, vs},
-    vertex::{AmbientLight, DirectionalLight, MVP},
-};
+// Time step between consecutive headless frames, so a run always produces the same images.
+const FRAME_TIME: f64 = 1.0 / 60.0;
+
+#[derive(Debug, Clone)]
+struct Options {
+    // OBJ or glTF file displayed instead of the demo cube.
+    model: Option<PathBuf>,
+    normal_mode: NormalMode,
+    // Equirectangular `.hdr` image lighting the scene instead of the ambient light.
+    environment: Option<PathBuf>,
+    // PNG or JPEG images applied to the demo cube or OBJ model.
+    texture: Option<PathBuf>,
+    normal_map: Option<PathBuf>,
+    // Samples per pixel for anti-aliasing, 1 turning it off.
+    samples: u32,
+    render_path: RenderPath,
+    tone_mapping: ToneMapping,
+    bloom: bool,
+    // Color lookup table, see `post::neutral_lut`.
+    lut: Option<PathBuf>,
+    vignette: bool,
+    fxaa: bool,
+    ssao: bool,
+    headless: bool,
+    output_dir: PathBuf,
+    width: u32,
+    height: u32,
+    frames: u32,
+    // Renders every frame at this point of the animation instead of advancing by `FRAME_TIME`.
+    time: Option<f64>,
+}
 
-extern crate vulkano;
-extern crate vulkano_win;
-extern crate winit;
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            model: None,
+            normal_mode: NormalMode::default(),
+            environment: None,
+            texture: None,
+            normal_map: None,
+            samples: 1,
+            render_path: RenderPath::default(),
+            tone_mapping: ToneMapping::default(),
+            bloom: false,
+            lut: None,
+            vignette: false,
+            fxaa: false,
+            ssao: false,
+            headless: false,
+            output_dir: PathBuf::from("frames"),
+            width: 800,
+            height: 600,
+            frames: 1,
+            time: None,
+        }
+    }
+}
 
-mod shaders;
-mod vertex;
+impl Options {
+    fn from_args(args: &[String]) -> Result<Options> {
+        let mut options = Options::default();
+        let mut args = args.iter();
 
-fn main() {
-    let instance = {
-        let vulkan_library = VulkanLibrary::new().unwrap();
-        let extensions = vulkano_win::required_extensions(&vulkan_library);
-        Instance::new(
-            vulkan_library,
-            InstanceCreateInfo {
-                enabled_extensions: extensions,
-                max_api_version: Some(Version::V1_1),
-                ..Default::default()
-            },
-        )
-        .unwrap()
-    };
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| anyhow!("missing value for `{}`", arg))
+            };
+            match arg.as_str() {
+                "--model" => options.model = Some(PathBuf::from(value()?)),
+                "--flat-normals" => options.normal_mode = NormalMode::Flat,
+                "--environment" => options.environment = Some(PathBuf::from(value()?)),
+                "--texture" => options.texture = Some(PathBuf::from(value()?)),
+                "--normal-map" => options.normal_map = Some(PathBuf::from(value()?)),
+                "--samples" => options.samples = value()?.parse().context("invalid `--samples`")?,
+                "--deferred" => options.render_path = RenderPath::Deferred,
+                "--clustered" => options.render_path = RenderPath::Clustered,
+                "--tone-map" => {
+                    options.tone_mapping.operator = match value()?.as_str() {
+                        "reinhard" => ToneMapOperator::Reinhard,
+                        "aces" => ToneMapOperator::Aces,
+                        other => bail!("unknown tone mapping operator `{}`", other),
+                    }
+                }
+                "--exposure" => {
+                    options.tone_mapping.exposure =
+                        value()?.parse().context("invalid `--exposure`")?
+                }
+                "--bloom" => options.bloom = true,
+                "--lut" => options.lut = Some(PathBuf::from(value()?)),
+                "--vignette" => options.vignette = true,
+                "--fxaa" => options.fxaa = true,
+                "--ssao" => options.ssao = true,
+                "--headless" => options.headless = true,
+                "--output" => options.output_dir = PathBuf::from(value()?),
+                "--width" => options.width = value()?.parse().context("invalid `--width`")?,
+                "--height" => options.height = value()?.parse().context("invalid `--height`")?,
+                "--frames" => options.frames = value()?.parse().context("invalid `--frames`")?,
+                "--time" => options.time = Some(value()?.parse().context("invalid `--time`")?),
+                // A bare path is shorthand for `--model <path>`.
+                path if !path.starts_with("--") && options.model.is_none() => {
+                    options.model = Some(PathBuf::from(path))
+                }
+                other => bail!("unknown argument `{}`", other),
+            }
+        }
 
-    let event_loop = EventLoop::new();
-    let surface = WindowBuilder::new()
-        .build_vk_surface(&event_loop, instance.clone())
-        .unwrap();
+        if options.width == 0 || options.height == 0 {
+            bail!("image dimensions must be non-zero");
+        }
 
-    let device_extensions = DeviceExtensions {
-        khr_swapchain: true,
-        ..DeviceExtensions::empty()
-    };
+        Ok(options)
+    }
+}
 
-    let (physical_device, queue_family_index) = instance
-        .enumerate_physical_devices()
-        .unwrap()
-        .filter(|p| p.supported_extensions().contains(&device_extensions))
-        .filter_map(|p| {
-            p.queue_family_properties()
-                .iter()
-                .enumerate()
-                .position(|(i, q)| {
-                    q.queue_flags.graphics && p.surface_support(i as u32, &surface).unwrap_or(false)
-                })
-                .map(|i| (p, i as u32))
-        })
-        .min_by_key(|(p, _)| match p.properties().device_type {
-            vulkano::device::physical::PhysicalDeviceType::DiscreteGpu => 0,
-            vulkano::device::physical::PhysicalDeviceType::IntegratedGpu => 1,
-            vulkano::device::physical::PhysicalDeviceType::VirtualGpu => 2,
-            vulkano::device::physical::PhysicalDeviceType::Cpu => 3,
-            vulkano::device::physical::PhysicalDeviceType::Other => 4,
-            _ => 5,
-        })
-        .expect("no device available");
+// Prints the warnings and errors of the library to stderr.
+struct StderrLogger;
 
-    println!(
-        "Using device: {} (type: {:?})",
-        physical_device.properties().device_name,
-        physical_device.properties().device_type,
-    );
-    println!(
-        "Our physical device supports Vulkan: {:?}",
-        physical_device.properties().api_version
-    );
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn
+    }
 
-    let (device, mut queues) = Device::new(
-        physical_device.clone(),
-        DeviceCreateInfo {
-            enabled_extensions: device_extensions,
-            queue_create_infos: vec![QueueCreateInfo {
-                queue_family_index,
-                ..Default::default()
-            }],
-            ..Default::default()
-        },
-    )
-    .unwrap();
-
-    let queue = queues.next().unwrap();
-
-    let (mut swapchain, images) = {
-        let caps = device
-            .physical_device()
-            .surface_capabilities(&surface, Default::default())
-            .unwrap();
-        let usage = caps.supported_usage_flags;
-        let alpha = caps.supported_composite_alpha.iter().next().unwrap();
-        let image_format = Some(
-            physical_device
-                .surface_formats(&surface, Default::default())
-                .unwrap()[0]
-                .0,
-        );
-
-        let window = surface.object().unwrap().downcast_ref::<Window>().unwrap();
-        let image_extent = window.inner_size().into();
-
-        Swapchain::new(
-            device.clone(),
-            surface.clone(),
-            SwapchainCreateInfo {
-                min_image_count: caps.min_image_count,
-                image_format,
-                image_extent,
-                image_usage: usage,
-                composite_alpha: alpha,
-                ..Default::default()
-            },
-        )
-        .unwrap()
-    };
-
-    let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
-    let descriptor_set_allocator = Arc::new(StandardDescriptorSetAllocator::new(device.clone()));
-    let command_buffer_allocator =
-        StandardCommandBufferAllocator::new(device.clone(), Default::default());
-
-    let vs = vs::load(device.clone()).unwrap();
-    let fs = fs::load(device.clone()).unwrap();
-
-    let render_pass = vulkano::single_pass_renderpass!(
-        device.clone(),
-        attachments: {
-            color: {
-                load: Clear,
-                store: Store,
-                format: swapchain.image_format(),
-                samples: 1,
-            },
-            depth: {
-                load: Clear,
-                store: DontCare,
-                format: Format::D16_UNORM,
-                samples: 1,
-            }
-        },
-        pass: {
-            color: [color],
-            depth_stencil: {depth}
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}", record.args());
         }
-    )
-    .unwrap();
-
-    // pipeline
-    let pipeline = GraphicsPipeline::start()
-        .vertex_input_state(BuffersDefinition::new().vertex::<Vertex>())
-        .vertex_shader(vs.entry_point("main").unwrap(), ())
-        .input_assembly_state(InputAssemblyState::new())
-        .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
-        .fragment_shader(fs.entry_point("main").unwrap(), ())
-        .depth_stencil_state(DepthStencilState::simple_depth_test())
-        .rasterization_state(RasterizationState::new().cull_mode(CullMode::Back))
-        .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
-        .build(device.clone())
-        .unwrap();
+    }
 
-    let vertex_buffer = get_vertex_buffer(memory_allocator.clone());
+    fn flush(&self) {}
+}
 
-    let uniform_buffer =
-        CpuBufferPool::<vs::ty::MVP_Data>::uniform_buffer(memory_allocator.clone());
+static LOGGER: StderrLogger = StderrLogger;
 
-    let ambient_buffer =
-        CpuBufferPool::<fs::ty::Ambient_Data>::uniform_buffer(memory_allocator.clone());
+fn main() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log::LevelFilter::Warn);
+    }
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = Options::from_args(&args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
 
-    let directional_buffer =
-        CpuBufferPool::<fs::ty::Directional_Light_Data>::uniform_buffer(memory_allocator.clone());
+    if options.headless {
+        if let Err(e) = run_headless(options) {
+            eprintln!("Headless rendering failed: {:?}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    let mut viewport = Viewport {
-        origin: [0.0, 0.0],
-        dimensions: [0.0, 0.0],
-        depth_range: 0.0..1.0,
+    let instance = {
+        let vulkan_library = VulkanLibrary::new().unwrap();
+        let extensions = vulkano_win::required_extensions(&vulkan_library);
+        create_instance(vulkan_library, extensions).unwrap()
     };
 
-    let mut framebuffers = window_size_dependent_setup(
-        &memory_allocator,
-        &images,
-        render_pass.clone(),
-        &mut viewport,
-    );
+    let event_loop = EventLoop::new();
+    let surface = WindowBuilder::new()
+        .build_vk_surface(&event_loop, instance)
+        .unwrap();
 
-    let mut recreate_swapchain = false;
-    let mut previous_frame_end = Some(Box::new(sync::now(device.clone())) as Box<dyn GpuFuture>);
+    let mut renderer = Renderer::new(RenderTarget::Surface(surface)).unwrap();
+    print_device(&renderer);
+    renderer.set_samples(options.samples).unwrap();
+    renderer.set_render_path(options.render_path).unwrap();
+    let (mut scene, root) = load_scene(&renderer, &options).unwrap();
+    scene.camera.resize(renderer.dimensions());
 
     let rotation_start = Instant::now();
-
-    let mut mvp = MVP::new();
-    mvp.model = translate(&identity(), &vec3(0.0, 0.0, -2.5));
-    let ambient_light = AmbientLight {
-        color: [1.0, 1.0, 1.0],
-        intensity: 0.2,
-    };
-    let directional_light = DirectionalLight {
-        position: [-4.0, -4.0, 0.0, 1.0],
-        color: [1.0, 1.0, 1.0],
-    };
+    let mut last_frame = rotation_start;
 
     event_loop.run(move |event, _, control_flow| match event {
-        winit::event::Event::WindowEvent {
+        Event::WindowEvent {
             event: WindowEvent::CloseRequested,
             ..
         } => {
             *control_flow = ControlFlow::Exit;
         }
-        winit::event::Event::WindowEvent {
-            event: WindowEvent::Resized(_),
+        Event::WindowEvent {
+            event: WindowEvent::Resized(size),
             ..
         } => {
-            recreate_swapchain = true;
+            renderer.resize(size.into()).unwrap();
+            scene.camera.resize(size.into());
         }
-        winit::event::Event::RedrawEventsCleared => {
-            // Render operations here
-
-            previous_frame_end
-                .as_mut()
-                .take()
-                .unwrap()
-                .cleanup_finished();
-
-            if recreate_swapchain {
-                let (new_swapchain, new_images) = match swapchain.recreate(SwapchainCreateInfo {
-                    image_extent: surface
-                        .object()
-                        .unwrap()
-                        .downcast_ref::<Window>()
-                        .unwrap()
-                        .inner_size()
-                        .into(),
-                    ..swapchain.create_info()
-                }) {
-                    Ok(r) => r,
-                    Err(SwapchainCreationError::ImageExtentNotSupported { .. }) => return,
-                    Err(e) => panic!("Failed to recreate swapchain: {:?}", e),
-                };
-
-                swapchain = new_swapchain;
-                framebuffers = window_size_dependent_setup(
-                    &memory_allocator,
-                    &new_images,
-                    render_pass.clone(),
-                    &mut viewport,
-                );
-                recreate_swapchain = false;
+        Event::WindowEvent { event, .. } => {
+            if let WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } = event
+            {
+                if key == VirtualKeyCode::O {
+                    scene.ssao.enabled = !scene.ssao.enabled;
+                }
+                toggle_post_effect(&mut scene, key);
             }
+            scene.camera.handle_window_event(&event)
+        }
+        Event::DeviceEvent { event, .. } => scene.camera.handle_device_event(&event),
+        Event::RedrawEventsCleared => {
+            let now = Instant::now();
+            scene.camera.update((now - last_frame).as_secs_f32());
+            last_frame = now;
+
+            let elapsed = rotation_start.elapsed().as_secs_f64();
+            scene.node_mut(root).set_transform(demo_transform(elapsed));
+            renderer.render_frame(&scene).unwrap();
+        }
+        _ => {}
+    });
+}
 
-            let uniform_subbuffer = {
-                let dimensions: [u32; 2] = surface
-                    .object()
-                    .unwrap()
-                    .downcast_ref::<Window>()
-                    .unwrap()
-                    .inner_size()
-                    .into();
-                mvp.projection = perspective(
-                    dimensions[0] as f32 / dimensions[1] as f32,
-                    180.0,
-                    0.01,
-                    100.0,
-                );
-                mvp.view = look_at(
-                    &vec3(0.0, 0.0, 0.01),
-                    &vec3(0.0, 0.0, 0.0),
-                    &vec3(0.0, -1.0, 0.0),
-                );
-
-                // Rotation animation
-                let elapsed = rotation_start.elapsed().as_secs() as f64
-                    + rotation_start.elapsed().subsec_nanos() as f64 / 1_000_000_000.0;
-                let elapsed_as_radians = elapsed * pi::<f64>() / 180.0;
-                let mut model: TMat4<f32> = rotate_normalized_axis(
-                    &identity(),
-                    elapsed_as_radians as f32 * 50.0,
-                    &vec3(0.0, 0.0, 1.0),
-                );
-                model = rotate_normalized_axis(
-                    &model,
-                    elapsed_as_radians as f32 * 30.0,
-                    &vec3(0.0, 1.0, 0.0),
-                );
-
-                model = rotate_normalized_axis(
-                    &model,
-                    elapsed_as_radians as f32 * 20.0,
-                    &vec3(1.0, 0.0, 0.0),
-                );
-                model = mvp.model * model;
-
-                let uniform_data = vs::ty::MVP_Data {
-                    world: model.into(),
-                    view: mvp.view.into(),
-                    projection: mvp.projection.into(),
-                };
-
-                uniform_buffer.from_data(uniform_data).unwrap()
-            };
-
-            let ambient_uniform_subbufer = {
-                let uniform_data = fs::ty::Ambient_Data {
-                    color: ambient_light.color.into(),
-                    intensity: ambient_light.intensity.into(),
-                };
-
-                ambient_buffer.from_data(uniform_data).unwrap()
-            };
+// Builds the demo scene: a root node animated with `demo_transform`, holding the model given on
+// the command line (fitted into the unit cube) or the demo cube, lit by the environment and
+// textured with the images if they were given. glTF models bring their own textures.
+fn load_scene(renderer: &Renderer, options: &Options) -> Result<(Scene, NodeId)> {
+    let mut scene = Scene::new();
+    scene.post_effects = post_effects(renderer, options)?;
+    scene.ssao.enabled = options.ssao;
+    let root = scene.add_node(Node::default());
+
+    if let Some(path) = &options.environment {
+        scene.environment = Some(Arc::new(Environment::load(renderer, path)?));
+    }
+    let load_texture = |path: &Option<PathBuf>, color_space| -> Result<_> {
+        match path {
+            Some(path) => Ok(Some(Arc::new(Texture::load(
+                renderer,
+                path,
+                color_space,
+                &SamplerSettings::default(),
+            )?))),
+            None => Ok(None),
+        }
+    };
+    let base_color_texture = load_texture(&options.texture, ColorSpace::Srgb)?;
+    let normal_texture = load_texture(&options.normal_map, ColorSpace::Linear)?;
+
+    let Some(path) = &options.model else {
+        scene.add_node(Node {
+            parent: Some(root),
+            mesh: Some(Arc::new(Mesh::cube(renderer.uploader())?)),
+            material: Material {
+                base_color_texture,
+                normal_texture,
+                ..Material::glossy([1.0, 1.0, 1.0], 32.0)
+            },
+            ..Default::default()
+        });
+        return Ok((scene, root));
+    };
 
-            let directional_uniform_subbuffer = {
-                let uniform_data = fs::ty::Directional_Light_Data {
-                    position: directional_light.position.into(),
-                    color: directional_light.color.into(),
-                };
+    let is_gltf = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("gltf") || extension.eq_ignore_ascii_case("glb")
+        });
+
+    if is_gltf {
+        let model = gltf::load_gltf(path)?;
+        let fit = scene.add_node(Node {
+            transform: unit_cube_transform(&model.flatten()),
+            parent: Some(root),
+            ..Default::default()
+        });
+        model.add_to_scene(renderer, &mut scene, Some(fit))?;
+    } else {
+        let mut vertices = obj::load_obj(path, options.normal_mode)?;
+        fit_to_unit_cube(&mut vertices);
+        scene.add_node(Node {
+            parent: Some(root),
+            mesh: Some(Arc::new(Mesh::from_triangles(
+                renderer.uploader(),
+                &vertices,
+            )?)),
+            material: Material {
+                base_color_texture,
+                normal_texture,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+    }
 
-                directional_buffer.from_data(uniform_data).unwrap()
-            };
+    Ok((scene, root))
+}
 
-            let layout = pipeline.layout().set_layouts().get(0).unwrap();
-            let set = PersistentDescriptorSet::new(
-                &descriptor_set_allocator,
-                layout.clone(),
-                [
-                    WriteDescriptorSet::buffer(0, uniform_subbuffer),
-                    WriteDescriptorSet::buffer(1, ambient_uniform_subbufer),
-                    WriteDescriptorSet::buffer(2, directional_uniform_subbuffer),
-                ],
-            )
-            .unwrap();
-
-            let (image_index, suboptimal, acquire_future) =
-                match swapchain::acquire_next_image(swapchain.clone(), None) {
-                    Ok(r) => r,
-                    Err(AcquireError::OutOfDate) => {
-                        recreate_swapchain = true;
-                        return;
-                    }
-                    Err(e) => panic!("Failed to acquire next image: {:?}", e),
-                };
+// The post-processing chain for the effects turned on in `options`. All of them are in the
+// chain, so they can be toggled while the window is open.
+fn post_effects(renderer: &Renderer, options: &Options) -> Result<Vec<PostEffect>> {
+    let mut effects = vec![
+        Bloom {
+            enabled: options.bloom,
+            ..Default::default()
+        }
+        .into(),
+        options.tone_mapping.into(),
+    ];
+    if let Some(path) = &options.lut {
+        // Lookup tables are read texel by texel, without mipmaps or wrapping.
+        let sampler = SamplerSettings {
+            address_mode: [SamplerAddressMode::ClampToEdge; 2],
+            anisotropy: None,
+            ..Default::default()
+        };
+        let lut = Texture::load(renderer, path, ColorSpace::Linear, &sampler)?;
+        effects.push(ColorGrading::new(Arc::new(lut))?.into());
+    }
+    effects.push(
+        Vignette {
+            enabled: options.vignette,
+            ..Default::default()
+        }
+        .into(),
+    );
+    effects.push(
+        Fxaa {
+            enabled: options.fxaa,
+            ..Default::default()
+        }
+        .into(),
+    );
 
-            if suboptimal {
-                recreate_swapchain = true;
-            }
+    Ok(effects)
+}
 
-            let clear_values = vec![Some([0.0, 0.0, 0.0, 1.0].into()), Some(1f32.into())];
-
-            let mut cmd_buffer_builder = AutoCommandBufferBuilder::primary(
-                &command_buffer_allocator,
-                queue.queue_family_index(),
-                vulkano::command_buffer::CommandBufferUsage::OneTimeSubmit,
-            )
-            .unwrap();
-
-            cmd_buffer_builder
-                .begin_render_pass(
-                    RenderPassBeginInfo {
-                        clear_values,
-                        ..RenderPassBeginInfo::framebuffer(
-                            framebuffers[image_index as usize].clone(),
-                        )
-                    },
-                    vulkano::command_buffer::SubpassContents::Inline,
-                )
-                .unwrap()
-                .set_viewport(0, [viewport.clone()])
-                .bind_pipeline_graphics(pipeline.clone())
-                .bind_descriptor_sets(
-                    PipelineBindPoint::Graphics,
-                    pipeline.layout().clone(),
-                    0,
-                    set.clone(),
-                )
-                .bind_vertex_buffers(0, vertex_buffer.clone())
-                .draw(vertex_buffer.len() as u32, 1, 0, 0)
-                .unwrap()
-                .end_render_pass()
-                .unwrap();
-
-            let command_buffer = cmd_buffer_builder.build().unwrap();
-
-            let future = previous_frame_end
-                .take()
-                .unwrap()
-                .join(acquire_future)
-                .then_execute(queue.clone(), command_buffer)
-                .unwrap()
-                .then_swapchain_present(
-                    queue.clone(),
-                    SwapchainPresentInfo::swapchain_image_index(swapchain.clone(), image_index),
-                )
-                .then_signal_fence_and_flush();
-
-            match future {
-                Ok(future) => previous_frame_end = Some(Box::new(future) as Box<_>),
-                Err(FlushError::OutOfDate) => {
-                    recreate_swapchain = true;
-                    previous_frame_end = Some(Box::new(sync::now(device.clone())) as Box<_>)
-                }
-                Err(e) => {
-                    eprintln!("Failed to flush future: {:?}", e);
-                    previous_frame_end = Some(Box::new(sync::now(device.clone())) as Box<_>)
-                }
-            }
-        }
-        _ => {}
-    });
+// Number keys turn the effect at that position of the post-processing chain on and off.
+fn toggle_post_effect(scene: &mut Scene, key: VirtualKeyCode) {
+    let number_keys = [
+        VirtualKeyCode::Key1,
+        VirtualKeyCode::Key2,
+        VirtualKeyCode::Key3,
+        VirtualKeyCode::Key4,
+        VirtualKeyCode::Key5,
+        VirtualKeyCode::Key6,
+        VirtualKeyCode::Key7,
+        VirtualKeyCode::Key8,
+        VirtualKeyCode::Key9,
+    ];
+    let Some(index) = number_keys.iter().position(|&number_key| number_key == key) else {
+        return;
+    };
+    if let Some(effect) = scene.post_effects.get_mut(index) {
+        effect.set_enabled(!effect.enabled());
+    }
 }
 
-fn window_size_dependent_setup(
-    standard_memory_allocator: &StandardMemoryAllocator,
-    images: &[Arc<SwapchainImage>],
-    render_pass: Arc<RenderPass>,
-    viewport: &mut Viewport,
-) -> Vec<Arc<Framebuffer>> {
-    let dimensions = images[0].dimensions().width_height();
-    viewport.dimensions = [dimensions[0] as f32, dimensions[1] as f32];
-    let depth_buffer = ImageView::new_default(
-        AttachmentImage::transient(standard_memory_allocator, dimensions, Format::D16_UNORM)
-            .unwrap(),
-    )
-    .unwrap();
-
-    images
-        .iter()
-        .map(|image| {
-            let view = ImageView::new_default(image.clone()).unwrap();
-            Framebuffer::new(
-                render_pass.clone(),
-                vulkano::render_pass::FramebufferCreateInfo {
-                    attachments: vec![view, depth_buffer.clone()],
-                    ..Default::default()
-                },
-            )
-            .unwrap()
-        })
-        .collect::<Vec<_>>()
+fn print_device(renderer: &Renderer) {
+    let properties = renderer.device().physical_device().properties();
+    println!(
+        "Using device: {} (type: {:?})",
+        properties.device_name, properties.device_type,
+    );
+    println!(
+        "Our physical device supports Vulkan: {:?}",
+        properties.api_version
+    );
 }
 
-fn get_vertex_buffer(
-    memory_allocator: Arc<StandardMemoryAllocator>,
-) -> Arc<CpuAccessibleBuffer<[Vertex]>> {
-    CpuAccessibleBuffer::from_iter(
-        &memory_allocator,
-        BufferUsage {
-            vertex_buffer: true,
-            ..BufferUsage::empty()
-        },
-        false,
-        [
-            // front face
-            Vertex {
-                position: [-1.000000, -1.000000, 1.000000],
-                normal: [0.0000, 0.0000, 1.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [-1.000000, 1.000000, 1.000000],
-                normal: [0.0000, 0.0000, 1.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [1.000000, 1.000000, 1.000000],
-                normal: [0.0000, 0.0000, 1.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [-1.000000, -1.000000, 1.000000],
-                normal: [0.0000, 0.0000, 1.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [1.000000, 1.000000, 1.000000],
-                normal: [0.0000, 0.0000, 1.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [1.000000, -1.000000, 1.000000],
-                normal: [0.0000, 0.0000, 1.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            // back face
-            Vertex {
-                position: [1.000000, -1.000000, -1.000000],
-                normal: [0.0000, 0.0000, -1.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [1.000000, 1.000000, -1.000000],
-                normal: [0.0000, 0.0000, -1.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [-1.000000, 1.000000, -1.000000],
-                normal: [0.0000, 0.0000, -1.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [1.000000, -1.000000, -1.000000],
-                normal: [0.0000, 0.0000, -1.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [-1.000000, 1.000000, -1.000000],
-                normal: [0.0000, 0.0000, -1.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [-1.000000, -1.000000, -1.000000],
-                normal: [0.0000, 0.0000, -1.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            // top face
-            Vertex {
-                position: [-1.000000, -1.000000, 1.000000],
-                normal: [0.0000, -1.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [1.000000, -1.000000, 1.000000],
-                normal: [0.0000, -1.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [1.000000, -1.000000, -1.000000],
-                normal: [0.0000, -1.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [-1.000000, -1.000000, 1.000000],
-                normal: [0.0000, -1.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [1.000000, -1.000000, -1.000000],
-                normal: [0.0000, -1.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [-1.000000, -1.000000, -1.000000],
-                normal: [0.0000, -1.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            // bottom face
-            Vertex {
-                position: [1.000000, 1.000000, 1.000000],
-                normal: [0.0000, 1.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [-1.000000, 1.000000, 1.000000],
-                normal: [0.0000, 1.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [-1.000000, 1.000000, -1.000000],
-                normal: [0.0000, 1.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [1.000000, 1.000000, 1.000000],
-                normal: [0.0000, 1.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [-1.000000, 1.000000, -1.000000],
-                normal: [0.0000, 1.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [1.000000, 1.000000, -1.000000],
-                normal: [0.0000, 1.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            // left face
-            Vertex {
-                position: [-1.000000, -1.000000, -1.000000],
-                normal: [-1.0000, 0.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [-1.000000, 1.000000, -1.000000],
-                normal: [-1.0000, 0.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [-1.000000, 1.000000, 1.000000],
-                normal: [-1.0000, 0.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [-1.000000, -1.000000, -1.000000],
-                normal: [-1.0000, 0.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [-1.000000, 1.000000, 1.000000],
-                normal: [-1.0000, 0.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [-1.000000, -1.000000, 1.000000],
-                normal: [-1.0000, 0.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            // right face
-            Vertex {
-                position: [1.000000, -1.000000, 1.000000],
-                normal: [1.0000, 0.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [1.000000, 1.000000, 1.000000],
-                normal: [1.0000, 0.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [1.000000, 1.000000, -1.000000],
-                normal: [1.0000, 0.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [1.000000, -1.000000, 1.000000],
-                normal: [1.0000, 0.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [1.000000, 1.000000, -1.000000],
-                normal: [1.0000, 0.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-            Vertex {
-                position: [1.000000, -1.000000, -1.000000],
-                normal: [1.0000, 0.0000, 0.0000],
-                color: [1.0, 0.35, 0.137],
-            },
-        ]
-        .iter()
-        .cloned(),
-    )
-    .unwrap()
+// Renders `options.frames` frames offscreen and writes each one to
+// `<output_dir>/frame_<n>.png`. No window or surface extension is needed, so this also works
+// with software drivers such as lavapipe.
+fn run_headless(options: Options) -> Result<()> {
+    let mut renderer = Renderer::new(RenderTarget::Headless {
+        width: options.width,
+        height: options.height,
+    })?;
+    print_device(&renderer);
+    renderer.set_samples(options.samples)?;
+    renderer.set_render_path(options.render_path)?;
+    let (mut scene, root) = load_scene(&renderer, &options)?;
+    scene.camera.resize(renderer.dimensions());
+
+    std::fs::create_dir_all(&options.output_dir).with_context(|| {
+        format!(
+            "failed to create output directory {}",
+            options.output_dir.display()
+        )
+    })?;
+
+    for frame in 0..options.frames {
+        let elapsed = options.time.unwrap_or(frame as f64 * FRAME_TIME);
+        scene.node_mut(root).set_transform(demo_transform(elapsed));
+        renderer.render_frame(&scene)?;
+
+        let path = options.output_dir.join(format!("frame_{:04}.png", frame));
+        write_png(
+            &path,
+            options.width,
+            options.height,
+            &renderer.read_pixels()?,
+        )?;
+        println!("Wrote {}", path.display());
+    }
+
+    Ok(())
 }
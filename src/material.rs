@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use crate::texture::Texture;
+
+// How a material is lit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShadingModel {
+    // Lambert diffuse plus Blinn-Phong highlights, using `specular_color` and `shininess`.
+    #[default]
+    BlinnPhong,
+    // Metallic-roughness PBR with a Cook-Torrance BRDF (GGX distribution, Smith geometry and
+    // Schlick Fresnel terms), using `metallic`, `roughness`, `occlusion` and `emissive` with
+    // their textures. Light intensities are in physical units, so the result is tone mapped.
+    Pbr,
+}
+
+// How the alpha of a material's base color is used, as in glTF.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AlphaMode {
+    // The alpha is ignored and the surface is fully opaque.
+    #[default]
+    Opaque,
+    // Fragments whose alpha is below `cutoff` are discarded, the others are opaque.
+    Mask {
+        cutoff: f32,
+    },
+    // Blended over what is behind the surface. Nodes with these materials are drawn after the
+    // others, from back to front, and don't occlude anything; the deferred path draws them as
+    // opaque.
+    Blend,
+}
+
+// Surface properties of the nodes drawn with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    pub shading: ShadingModel,
+    // Multiplied with the vertex colors.
+    pub base_color: [f32; 3],
+    // Multiplied with the base color, sampled at the vertices' texture coordinates.
+    pub base_color_texture: Option<Arc<Texture>>,
+    // Multiplied with the alpha of `base_color_texture`, and used as `alpha_mode` says.
+    pub alpha: f32,
+    pub alpha_mode: AlphaMode,
+    // Back faces are drawn too, lit with their normals flipped, rather than culled.
+    pub double_sided: bool,
+    // Tangent-space normals in a linear texture, perturbing the surface normal of vertices that
+    // have tangents.
+    pub normal_texture: Option<Arc<Texture>>,
+    // Scales the x and y components of the normal map; 0 flattens it.
+    pub normal_scale: f32,
+    // Color of the Blinn-Phong highlights; black turns them off.
+    pub specular_color: [f32; 3],
+    // Exponent of the highlights: higher values give smaller, sharper highlights.
+    pub shininess: f32,
+    // 0 for dielectrics, 1 for metals.
+    pub metallic: f32,
+    // From 0 for a perfect mirror to 1 for a fully rough surface.
+    pub roughness: f32,
+    // Linear texture scaling `roughness` by its green channel and `metallic` by its blue one, as
+    // in glTF.
+    pub metallic_roughness_texture: Option<Arc<Texture>>,
+    // Fraction of the ambient light reaching the surface.
+    pub occlusion: f32,
+    // Linear texture whose red channel scales `occlusion`, by `occlusion_strength`: 0 ignores
+    // the texture, 1 applies it fully.
+    pub occlusion_texture: Option<Arc<Texture>>,
+    pub occlusion_strength: f32,
+    // Light emitted by the surface itself.
+    pub emissive: [f32; 3],
+    // Multiplied with `emissive`.
+    pub emissive_texture: Option<Arc<Texture>>,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            shading: ShadingModel::BlinnPhong,
+            base_color: [1.0, 1.0, 1.0],
+            base_color_texture: None,
+            alpha: 1.0,
+            alpha_mode: AlphaMode::Opaque,
+            double_sided: false,
+            normal_texture: None,
+            normal_scale: 1.0,
+            specular_color: [0.0, 0.0, 0.0],
+            shininess: 32.0,
+            metallic: 0.0,
+            roughness: 1.0,
+            metallic_roughness_texture: None,
+            occlusion: 1.0,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive: [0.0, 0.0, 0.0],
+            emissive_texture: None,
+        }
+    }
+}
+
+impl Material {
+    // White highlights of the given sharpness on top of a diffuse `base_color`.
+    pub fn glossy(base_color: [f32; 3], shininess: f32) -> Material {
+        Material {
+            base_color,
+            specular_color: [0.5, 0.5, 0.5],
+            shininess,
+            ..Default::default()
+        }
+    }
+
+    pub fn pbr(base_color: [f32; 3], metallic: f32, roughness: f32) -> Material {
+        Material {
+            shading: ShadingModel::Pbr,
+            base_color,
+            metallic,
+            roughness,
+            ..Default::default()
+        }
+    }
+}
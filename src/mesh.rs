@@ -0,0 +1,296 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{bail, Result};
+use vulkano::{
+    buffer::{BufferUsage, DeviceLocalBuffer},
+    command_buffer::{allocator::CommandBufferAllocator, AutoCommandBufferBuilder},
+};
+
+use crate::{
+    geometry::{generate_tangents, Bounds},
+    upload::Uploader,
+    vertex::Vertex,
+};
+
+const CUBE_COLOR: [f32; 3] = [1.0, 0.35, 0.137];
+
+// Index data in the narrowest type able to address every vertex.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Indices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    pub fn new(indices: Vec<u32>, vertex_count: usize) -> Indices {
+        if vertex_count <= u16::MAX as usize + 1 {
+            Indices::U16(indices.into_iter().map(|index| index as u16).collect())
+        } else {
+            Indices::U32(indices)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Indices::U16(indices) => indices.len(),
+            Indices::U32(indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+enum IndexBuffer {
+    U16(Arc<DeviceLocalBuffer<[u16]>>),
+    U32(Arc<DeviceLocalBuffer<[u32]>>),
+}
+
+// Indexed triangle list stored in device-local buffers.
+pub struct Mesh {
+    vertex_buffer: Arc<DeviceLocalBuffer<[Vertex]>>,
+    index_buffer: IndexBuffer,
+    vertex_count: usize,
+    index_count: u32,
+    bounds: Bounds,
+}
+
+impl std::fmt::Debug for Mesh {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Mesh")
+            .field("vertex_count", &self.vertex_count)
+            .field("index_count", &self.index_count)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Mesh {
+    // Builds a mesh from a triangle list where vertices may be repeated, sharing every vertex
+    // that occurs more than once.
+    pub fn from_triangles(uploader: &Uploader, vertices: &[Vertex]) -> Result<Mesh> {
+        let (vertices, indices) = deduplicate(vertices);
+        Mesh::new(uploader, &vertices, indices)
+    }
+
+    // Builds a mesh from an indexed triangle list, e.g. a glTF primitive.
+    pub fn new(uploader: &Uploader, vertices: &[Vertex], indices: Vec<u32>) -> Result<Mesh> {
+        if vertices.is_empty() || indices.is_empty() {
+            bail!("cannot draw a mesh without vertices");
+        }
+        check_triangle_list(&indices, vertices.len())?;
+
+        let bounds = Bounds::of_vertices(vertices).unwrap();
+        let vertex_count = vertices.len();
+        let index_count = indices.len() as u32;
+        let (vertex_buffer, index_buffer) = uploader.submit(|builder| {
+            let vertex_buffer = uploader.buffer(
+                builder,
+                vertices,
+                BufferUsage {
+                    vertex_buffer: true,
+                    ..BufferUsage::empty()
+                },
+            )?;
+
+            let index_usage = BufferUsage {
+                index_buffer: true,
+                ..BufferUsage::empty()
+            };
+            let index_buffer = match Indices::new(indices, vertex_count) {
+                Indices::U16(indices) => {
+                    IndexBuffer::U16(uploader.buffer(builder, &indices, index_usage)?)
+                }
+                Indices::U32(indices) => {
+                    IndexBuffer::U32(uploader.buffer(builder, &indices, index_usage)?)
+                }
+            };
+
+            Ok((vertex_buffer, index_buffer))
+        })?;
+
+        Ok(Mesh {
+            vertex_buffer,
+            index_buffer,
+            vertex_count,
+            index_count,
+            bounds,
+        })
+    }
+
+    // The demo cube, with 4 vertices per face so each face keeps its own normal.
+    pub fn cube(uploader: &Uploader) -> Result<Mesh> {
+        let (vertices, indices) = cube_geometry();
+        Mesh::new(uploader, &vertices, indices)
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertex_count
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.index_count
+    }
+
+    // Bounds of the vertex positions, in the mesh's local space.
+    pub fn bounds(&self) -> Bounds {
+        self.bounds
+    }
+
+    // Binds the vertex and index buffers and records an indexed draw. The pipeline and its
+    // descriptor sets must already be bound.
+    pub fn draw<L, A>(&self, builder: &mut AutoCommandBufferBuilder<L, A>) -> Result<()>
+    where
+        A: CommandBufferAllocator,
+    {
+        builder.bind_vertex_buffers(0, self.vertex_buffer.clone());
+        match &self.index_buffer {
+            IndexBuffer::U16(buffer) => builder.bind_index_buffer(buffer.clone()),
+            IndexBuffer::U32(buffer) => builder.bind_index_buffer(buffer.clone()),
+        };
+        builder.draw_indexed(self.index_count, 1, 0, 0, 0)?;
+
+        Ok(())
+    }
+}
+
+// Fails unless `indices` is a whole number of triangles, each index addressing one of
+// `vertex_count` vertices.
+pub fn check_triangle_list(indices: &[u32], vertex_count: usize) -> Result<()> {
+    if !indices.len().is_multiple_of(3) {
+        bail!(
+            "{} indices don't make a whole number of triangles",
+            indices.len()
+        );
+    }
+    if let Some(&index) = indices.iter().find(|&&i| i as usize >= vertex_count) {
+        bail!(
+            "index {} is out of range for a mesh with {} vertices",
+            index,
+            vertex_count
+        );
+    }
+
+    Ok(())
+}
+
+// Splits a triangle list into unique vertices and indices into them, in order of first use.
+// Vertices are only merged when every attribute is identical; `0.0` and `-0.0` count as equal.
+pub fn deduplicate(vertices: &[Vertex]) -> (Vec<Vertex>, Vec<u32>) {
+    let mut unique = Vec::new();
+    let mut indices = Vec::with_capacity(vertices.len());
+    let mut lookup = HashMap::new();
+
+    for vertex in vertices {
+        let key: [u32; 15] = bytemuck::cast(canonical(*vertex));
+        let index = *lookup.entry(key).or_insert_with(|| {
+            unique.push(*vertex);
+            unique.len() as u32 - 1
+        });
+        indices.push(index);
+    }
+
+    (unique, indices)
+}
+
+fn canonical(mut vertex: Vertex) -> Vertex {
+    for value in vertex
+        .position
+        .iter_mut()
+        .chain(&mut vertex.normal)
+        .chain(&mut vertex.color)
+        .chain(&mut vertex.uv)
+        .chain(&mut vertex.tangent)
+    {
+        if *value == 0.0 {
+            *value = 0.0;
+        }
+    }
+    vertex
+}
+
+fn cube_geometry() -> (Vec<Vertex>, Vec<u32>) {
+    // Corners of each face in clockwise order as seen from outside the cube, starting at the
+    // bottom left; the indices wind each triangle counter-clockwise. Every face shows the whole
+    // texture, upright for the side faces.
+    let faces = [
+        // front face
+        (
+            [0.0, 0.0, 1.0],
+            [
+                [-1.0, -1.0, 1.0],
+                [-1.0, 1.0, 1.0],
+                [1.0, 1.0, 1.0],
+                [1.0, -1.0, 1.0],
+            ],
+        ),
+        // back face
+        (
+            [0.0, 0.0, -1.0],
+            [
+                [1.0, -1.0, -1.0],
+                [1.0, 1.0, -1.0],
+                [-1.0, 1.0, -1.0],
+                [-1.0, -1.0, -1.0],
+            ],
+        ),
+        // bottom face
+        (
+            [0.0, -1.0, 0.0],
+            [
+                [-1.0, -1.0, 1.0],
+                [1.0, -1.0, 1.0],
+                [1.0, -1.0, -1.0],
+                [-1.0, -1.0, -1.0],
+            ],
+        ),
+        // top face
+        (
+            [0.0, 1.0, 0.0],
+            [
+                [1.0, 1.0, 1.0],
+                [-1.0, 1.0, 1.0],
+                [-1.0, 1.0, -1.0],
+                [1.0, 1.0, -1.0],
+            ],
+        ),
+        // left face
+        (
+            [-1.0, 0.0, 0.0],
+            [
+                [-1.0, -1.0, -1.0],
+                [-1.0, 1.0, -1.0],
+                [-1.0, 1.0, 1.0],
+                [-1.0, -1.0, 1.0],
+            ],
+        ),
+        // right face
+        (
+            [1.0, 0.0, 0.0],
+            [
+                [1.0, -1.0, 1.0],
+                [1.0, 1.0, 1.0],
+                [1.0, 1.0, -1.0],
+                [1.0, -1.0, -1.0],
+            ],
+        ),
+    ];
+
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for (normal, corners) in faces {
+        let first = vertices.len() as u32;
+        let uvs = [[0.0, 1.0], [0.0, 0.0], [1.0, 0.0], [1.0, 1.0]];
+        vertices.extend(corners.iter().zip(uvs).map(|(&position, uv)| Vertex {
+            position,
+            normal,
+            color: CUBE_COLOR,
+            uv,
+            ..Default::default()
+        }));
+        indices.extend([0, 2, 1, 0, 3, 2].map(|corner| first + corner));
+    }
+    generate_tangents(&mut vertices, &mut indices);
+
+    (vertices, indices)
+}
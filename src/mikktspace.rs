@@ -0,0 +1,328 @@
+// A port of Morten S. Mikkelsen's MikkTSpace (mikktspace.c), the tangent space glTF asks for and
+// most normal map bakers use, restricted to triangle lists. The steps and their order follow the
+// reference implementation, since the order in which triangles are grouped decides the
+// handedness of triangles without usable texture coordinates.
+
+use std::collections::HashMap;
+
+use nalgebra_glm::{Vec2, Vec3};
+
+// The cosine of the largest angle between two triangles' tangents that still lets them share a
+// tangent space at a vertex. The reference implementation defaults to 180 degrees, merging all
+// triangles of a group.
+const THRESHOLD_COS: f32 = -1.0;
+
+// Tangent space of one corner: the direction of increasing s, and whether the s and t
+// directions and the normal form a right-handed basis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct TangentSpace {
+    pub tangent: Vec3,
+    pub orientation_preserving: bool,
+}
+
+struct Triangle {
+    // Welded corner indices.
+    corners: [usize; 3],
+    // The triangle sharing the edge from corner i to the next one, in the opposite direction.
+    neighbors: [Option<usize>; 3],
+    // Group of each corner.
+    groups: [Option<usize>; 3],
+    // Normalized directions of increasing s and t.
+    os: Vec3,
+    ot: Vec3,
+    orientation_preserving: bool,
+    // The texture coordinates give no direction, so the triangle joins whichever group reaches
+    // it first.
+    group_with_any: bool,
+}
+
+// Triangles sharing a vertex whose tangent spaces are merged.
+struct Group {
+    vertex: usize,
+    orientation_preserving: bool,
+    triangles: Vec<usize>,
+}
+
+// Returns the tangent space of each corner of the triangles given by three consecutive entries
+// of `positions`, `normals` and `uvs`, or `None` for corners MikkTSpace leaves without one.
+// Normals must be normalized.
+pub(crate) fn corner_tangent_spaces(
+    positions: &[Vec3],
+    normals: &[Vec3],
+    uvs: &[Vec2],
+) -> Vec<Option<TangentSpace>> {
+    let corner_count = positions.len() / 3 * 3;
+    let welded = weld(&positions[..corner_count], normals, uvs);
+
+    // Triangles with two corners at the same position are left out, and take their tangent
+    // spaces from the other triangles at the end.
+    let (good, degenerate): (Vec<usize>, Vec<usize>) = (0..corner_count / 3).partition(|&t| {
+        let [a, b, c] = [0, 1, 2].map(|i| positions[welded[3 * t + i]]);
+        a != b && a != c && b != c
+    });
+
+    let mut triangles: Vec<Triangle> = good
+        .iter()
+        .map(|&t| triangle(positions, uvs, [0, 1, 2].map(|i| welded[3 * t + i])))
+        .collect();
+    find_neighbors(&mut triangles);
+    let groups = build_groups(&mut triangles);
+
+    let mut spaces = vec![None; corner_count];
+    for (g, group) in groups.iter().enumerate() {
+        for &t in &group.triangles {
+            let corner = corner_of(&triangles[t], group.vertex);
+            debug_assert_eq!(triangles[t].groups[corner], Some(g));
+            let normal = normals[group.vertex];
+            let (os, ot) = projected_directions(&triangles[t], &normal);
+
+            let members: Vec<usize> = group
+                .triangles
+                .iter()
+                .copied()
+                .filter(|&other| {
+                    let (other_os, other_ot) = projected_directions(&triangles[other], &normal);
+                    triangles[t].group_with_any
+                        || triangles[other].group_with_any
+                        || other == t
+                        || (os.dot(&other_os) > THRESHOLD_COS && ot.dot(&other_ot) > THRESHOLD_COS)
+                })
+                .collect();
+
+            spaces[3 * good[t] + corner] = Some(TangentSpace {
+                tangent: merged_tangent(positions, normals, &triangles, &members, group.vertex),
+                orientation_preserving: group.orientation_preserving,
+            });
+        }
+    }
+
+    // Corners of degenerate triangles copy the first good corner at the same welded vertex.
+    let mut first_use = HashMap::new();
+    for (t, triangle) in triangles.iter().enumerate() {
+        for (i, &vertex) in triangle.corners.iter().enumerate() {
+            first_use.entry(vertex).or_insert(3 * good[t] + i);
+        }
+    }
+    for t in degenerate {
+        for i in 0..3 {
+            if let Some(&source) = first_use.get(&welded[3 * t + i]) {
+                spaces[3 * t + i] = spaces[source];
+            }
+        }
+    }
+
+    spaces
+}
+
+// Maps every corner to the first corner with the same position, normal and texture
+// coordinates.
+fn weld(positions: &[Vec3], normals: &[Vec3], uvs: &[Vec2]) -> Vec<usize> {
+    let mut first = HashMap::new();
+    (0..positions.len())
+        .map(|corner| {
+            let mut key = [0; 8];
+            let values = positions[corner]
+                .iter()
+                .chain(normals[corner].iter())
+                .chain(uvs[corner].iter());
+            for (bits, value) in key.iter_mut().zip(values) {
+                // `0.0` and `-0.0` count as equal.
+                *bits = (value + 0.0).to_bits();
+            }
+            *first.entry(key).or_insert(corner)
+        })
+        .collect()
+}
+
+fn triangle(positions: &[Vec3], uvs: &[Vec2], corners: [usize; 3]) -> Triangle {
+    let [p1, p2, p3] = corners.map(|c| positions[c]);
+    let [t1, t2, t3] = corners.map(|c| uvs[c]);
+    let (t21, t31) = (t2 - t1, t3 - t1);
+    let (d1, d2) = (p2 - p1, p3 - p1);
+
+    let signed_area = t21.x * t31.y - t21.y * t31.x;
+    let os = d1 * t31.y - d2 * t21.y;
+    let ot = d2 * t21.x - d1 * t31.x;
+    let orientation_preserving = signed_area > 0.0;
+
+    let mut triangle = Triangle {
+        corners,
+        neighbors: [None; 3],
+        groups: [None; 3],
+        os: Vec3::zeros(),
+        ot: Vec3::zeros(),
+        orientation_preserving,
+        group_with_any: true,
+    };
+    if not_zero(signed_area) {
+        let sign = if orientation_preserving { 1.0 } else { -1.0 };
+        let (length_s, length_t) = (os.norm(), ot.norm());
+        if not_zero(length_s) {
+            triangle.os = os * (sign / length_s);
+        }
+        if not_zero(length_t) {
+            triangle.ot = ot * (sign / length_t);
+        }
+        // How fast s and t increase along their directions.
+        let (mag_s, mag_t) = (length_s / signed_area.abs(), length_t / signed_area.abs());
+        triangle.group_with_any = !(not_zero(mag_s) && not_zero(mag_t));
+    }
+    triangle
+}
+
+// Pairs every edge with an edge of another triangle running between the same welded vertices
+// in the opposite direction, if there is one that isn't paired yet.
+fn find_neighbors(triangles: &mut [Triangle]) {
+    let mut edges: Vec<(usize, usize, usize)> = triangles
+        .iter()
+        .enumerate()
+        .flat_map(|(t, triangle)| {
+            (0..3).map(move |i| {
+                let (a, b) = (triangle.corners[i], triangle.corners[(i + 1) % 3]);
+                (a.min(b), a.max(b), t)
+            })
+        })
+        .collect();
+    edges.sort_unstable();
+
+    for (i, &(low, high, t)) in edges.iter().enumerate() {
+        let (from, to, edge) = directed_edge(&triangles[t], low, high);
+        if triangles[t].neighbors[edge].is_some() {
+            continue;
+        }
+        let neighbor = edges[i + 1..]
+            .iter()
+            .take_while(|&&(other_low, other_high, _)| (other_low, other_high) == (low, high))
+            .find_map(|&(_, _, other)| {
+                let (other_from, other_to, other_edge) =
+                    directed_edge(&triangles[other], low, high);
+                let unpaired = triangles[other].neighbors[other_edge].is_none();
+                (other_to == from && other_from == to && unpaired).then_some((other, other_edge))
+            });
+        if let Some((other, other_edge)) = neighbor {
+            triangles[t].neighbors[edge] = Some(other);
+            triangles[other].neighbors[other_edge] = Some(t);
+        }
+    }
+}
+
+// The edge of `triangle` between vertices `a` and `b`, as its start, end and number.
+fn directed_edge(triangle: &Triangle, a: usize, b: usize) -> (usize, usize, usize) {
+    let [c0, c1, c2] = triangle.corners;
+    if c0 == a || c0 == b {
+        if c1 == a || c1 == b {
+            (c0, c1, 0)
+        } else {
+            (c2, c0, 2)
+        }
+    } else {
+        (c1, c2, 1)
+    }
+}
+
+// Grows a group from every corner of a triangle with usable texture coordinates that isn't in
+// one yet, spreading to neighbors around the corner's vertex as long as they have the same
+// orientation.
+fn build_groups(triangles: &mut [Triangle]) -> Vec<Group> {
+    let mut groups = Vec::new();
+    for t in 0..triangles.len() {
+        for corner in 0..3 {
+            if triangles[t].group_with_any || triangles[t].groups[corner].is_some() {
+                continue;
+            }
+            let g = groups.len();
+            let mut group = Group {
+                vertex: triangles[t].corners[corner],
+                orientation_preserving: triangles[t].orientation_preserving,
+                triangles: vec![t],
+            };
+            triangles[t].groups[corner] = Some(g);
+
+            // Depth first, left neighbor before right, like the recursion of the reference.
+            let mut pending = neighbors_around(&triangles[t], corner);
+            while let Some(next) = pending.pop() {
+                let triangle = &mut triangles[next];
+                let corner = corner_of(triangle, group.vertex);
+                if triangle.groups[corner].is_some() {
+                    continue;
+                }
+                if triangle.group_with_any && triangle.groups.iter().all(Option::is_none) {
+                    triangle.orientation_preserving = group.orientation_preserving;
+                }
+                if triangle.orientation_preserving != group.orientation_preserving {
+                    continue;
+                }
+                triangle.groups[corner] = Some(g);
+                group.triangles.push(next);
+                pending.extend(neighbors_around(triangle, corner));
+            }
+            groups.push(group);
+        }
+    }
+    groups
+}
+
+// The neighbors across the two edges at `corner`, the one visited first last.
+fn neighbors_around(triangle: &Triangle, corner: usize) -> Vec<usize> {
+    let left = triangle.neighbors[corner];
+    let right = triangle.neighbors[(corner + 2) % 3];
+    right.into_iter().chain(left).collect()
+}
+
+fn corner_of(triangle: &Triangle, vertex: usize) -> usize {
+    triangle
+        .corners
+        .iter()
+        .position(|&c| c == vertex)
+        .expect("grouped triangles contain the group's vertex")
+}
+
+// The triangle's s and t directions, projected onto the plane of `normal` and normalized.
+fn projected_directions(triangle: &Triangle, normal: &Vec3) -> (Vec3, Vec3) {
+    let os = normalize_nonzero(triangle.os - normal * normal.dot(&triangle.os));
+    let ot = normalize_nonzero(triangle.ot - normal * normal.dot(&triangle.ot));
+    (os, ot)
+}
+
+// Averages the projected s directions of `members` at `vertex`, weighted by the angle of each
+// triangle's corner there.
+fn merged_tangent(
+    positions: &[Vec3],
+    normals: &[Vec3],
+    triangles: &[Triangle],
+    members: &[usize],
+    vertex: usize,
+) -> Vec3 {
+    let mut sum = Vec3::zeros();
+    for &t in members {
+        let triangle = &triangles[t];
+        if triangle.group_with_any {
+            continue;
+        }
+        let corner = corner_of(triangle, vertex);
+        let normal = normals[vertex];
+        let (os, _) = projected_directions(triangle, &normal);
+
+        let here = positions[vertex];
+        let previous = positions[triangle.corners[(corner + 2) % 3]];
+        let next = positions[triangle.corners[(corner + 1) % 3]];
+        let v1 = normalize_nonzero(previous - here - normal * normal.dot(&(previous - here)));
+        let v2 = normalize_nonzero(next - here - normal * normal.dot(&(next - here)));
+        let angle = v1.dot(&v2).clamp(-1.0, 1.0).acos();
+
+        sum += os * angle;
+    }
+    normalize_nonzero(sum)
+}
+
+fn not_zero(x: f32) -> bool {
+    x.abs() > f32::MIN_POSITIVE
+}
+
+fn normalize_nonzero(v: Vec3) -> Vec3 {
+    if v.iter().any(|&x| not_zero(x)) {
+        v * (1.0 / v.norm())
+    } else {
+        v
+    }
+}
@@ -0,0 +1,109 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::{
+    geometry::{generate_normals, generate_tangents, NormalMode},
+    vertex::Vertex,
+};
+
+// Used for meshes without a material diffuse color or vertex colors.
+const DEFAULT_COLOR: [f32; 3] = [0.8, 0.8, 0.8];
+
+// Loads every mesh of a Wavefront OBJ file as a flat triangle list. Vertex colors come from the
+// diffuse color (`Kd`) of the mesh's material in the referenced `.mtl` file, then from OBJ
+// vertex colors, then `DEFAULT_COLOR`. Tangents are generated from the texture coordinates. A
+// material library that can't be loaded is logged as a warning and ignored.
+pub fn load_obj(path: impl AsRef<Path>, normal_mode: NormalMode) -> Result<Vec<Vertex>> {
+    let path = path.as_ref();
+    let (models, materials) = tobj::load_obj(
+        path,
+        &tobj::LoadOptions {
+            triangulate: true,
+            ignore_points: true,
+            ignore_lines: true,
+            ..Default::default()
+        },
+    )
+    .with_context(|| format!("failed to load {}", path.display()))?;
+
+    // A missing or broken material library only costs us the colors.
+    let materials = materials.unwrap_or_else(|e| {
+        log::warn!("Ignoring materials of {}: {}", path.display(), e);
+        Vec::new()
+    });
+
+    let mut vertices = Vec::new();
+    for model in &models {
+        let mesh = &model.mesh;
+        let material_color = mesh
+            .material_id
+            .and_then(|id| materials.get(id))
+            .and_then(|material| material.diffuse);
+
+        let positions: Vec<[f32; 3]> = (0..mesh.positions.len() / 3)
+            .map(|index| vec3_at(&mesh.positions, index))
+            .collect();
+
+        let generated_normals = if mesh.normals.is_empty() {
+            Some(generate_normals(&positions, &mesh.indices, normal_mode))
+        } else {
+            None
+        };
+
+        for (corner, &index) in mesh.indices.iter().enumerate() {
+            let index = index as usize;
+
+            let normal = match &generated_normals {
+                Some(normals) => normals[corner],
+                None => {
+                    let normal_index = if mesh.normal_indices.is_empty() {
+                        index
+                    } else {
+                        mesh.normal_indices[corner] as usize
+                    };
+                    vec3_at(&mesh.normals, normal_index)
+                }
+            };
+
+            let color = match material_color {
+                Some(color) => color,
+                None if !mesh.vertex_color.is_empty() => vec3_at(&mesh.vertex_color, index),
+                None => DEFAULT_COLOR,
+            };
+
+            // OBJ puts v = 0 at the bottom of the image.
+            let uv = if mesh.texcoords.is_empty() {
+                [0.0, 0.0]
+            } else {
+                let uv_index = if mesh.texcoord_indices.is_empty() {
+                    index
+                } else {
+                    mesh.texcoord_indices[corner] as usize
+                };
+                let [u, v] = [
+                    mesh.texcoords[2 * uv_index],
+                    mesh.texcoords[2 * uv_index + 1],
+                ];
+                [u, 1.0 - v]
+            };
+
+            vertices.push(Vertex {
+                position: positions[index],
+                normal,
+                color,
+                uv,
+                ..Default::default()
+            });
+        }
+    }
+
+    let mut indices: Vec<u32> = (0..vertices.len() as u32).collect();
+    generate_tangents(&mut vertices, &mut indices);
+
+    Ok(vertices)
+}
+
+fn vec3_at(data: &[f32], index: usize) -> [f32; 3] {
+    [data[3 * index], data[3 * index + 1], data[3 * index + 2]]
+}
@@ -0,0 +1,824 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use vulkano::{
+    command_buffer::{
+        allocator::CommandBufferAllocator, AutoCommandBufferBuilder, RenderPassBeginInfo,
+        SubpassContents,
+    },
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, PersistentDescriptorSet, WriteDescriptorSet,
+    },
+    device::Device,
+    format::{Format, NumericType},
+    image::{
+        view::ImageView, ImageAccess, ImageCreateFlags, ImageDimensions, ImageLayout, ImageUsage,
+    },
+    memory::allocator::StandardMemoryAllocator,
+    pipeline::{
+        graphics::{
+            color_blend::ColorBlendState,
+            input_assembly::InputAssemblyState,
+            vertex_input::BuffersDefinition,
+            viewport::{Viewport, ViewportState},
+        },
+        GraphicsPipeline, Pipeline, PipelineBindPoint,
+    },
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+    sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+    shader::ShaderModule,
+};
+
+use crate::{
+    gpu_image::GpuImage,
+    shaders::{
+        bloom_composite_fs, bloom_downsample_fs, bloom_prefilter_fs, bloom_upsample_fs,
+        color_grading_fs, fxaa_fs, output_fs, post_vs, tone_map_fs, vignette_fs,
+    },
+    texture::{ImageData, Texture},
+};
+
+// The scene is lit and shaded in linear values that can go well above 1, stored at half
+// precision until tone mapping brings them into the range of the output. Every step of the
+// post-processing chain keeps this format.
+pub const HDR_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
+// Images in the bloom chain, the first at half the size of the screen and each of the others at
+// half the size of the previous one.
+pub const MAX_BLOOM_LEVELS: u32 = 6;
+
+// One step of the post-processing chain in `Scene::post_effects`. Steps run in the order of the
+// list, each on the result of the previous one, and are skipped while they're disabled.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PostEffect {
+    Bloom(Bloom),
+    ToneMapping(ToneMapping),
+    ColorGrading(ColorGrading),
+    Vignette(Vignette),
+    Fxaa(Fxaa),
+}
+
+impl PostEffect {
+    pub fn enabled(&self) -> bool {
+        match self {
+            PostEffect::Bloom(effect) => effect.enabled,
+            PostEffect::ToneMapping(effect) => effect.enabled,
+            PostEffect::ColorGrading(effect) => effect.enabled,
+            PostEffect::Vignette(effect) => effect.enabled,
+            PostEffect::Fxaa(effect) => effect.enabled,
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        match self {
+            PostEffect::Bloom(effect) => effect.enabled = enabled,
+            PostEffect::ToneMapping(effect) => effect.enabled = enabled,
+            PostEffect::ColorGrading(effect) => effect.enabled = enabled,
+            PostEffect::Vignette(effect) => effect.enabled = enabled,
+            PostEffect::Fxaa(effect) => effect.enabled = enabled,
+        }
+    }
+}
+
+impl From<Bloom> for PostEffect {
+    fn from(effect: Bloom) -> Self {
+        PostEffect::Bloom(effect)
+    }
+}
+
+impl From<ToneMapping> for PostEffect {
+    fn from(effect: ToneMapping) -> Self {
+        PostEffect::ToneMapping(effect)
+    }
+}
+
+impl From<ColorGrading> for PostEffect {
+    fn from(effect: ColorGrading) -> Self {
+        PostEffect::ColorGrading(effect)
+    }
+}
+
+impl From<Vignette> for PostEffect {
+    fn from(effect: Vignette) -> Self {
+        PostEffect::Vignette(effect)
+    }
+}
+
+impl From<Fxaa> for PostEffect {
+    fn from(effect: Fxaa) -> Self {
+        PostEffect::Fxaa(effect)
+    }
+}
+
+// The chain of a new scene: everything off but tone mapping, in the order that suits them best.
+// Bloom works on HDR values, so it goes before tone mapping; the other effects expect colors
+// between 0 and 1, so they go after it.
+pub fn default_post_effects() -> Vec<PostEffect> {
+    vec![
+        Bloom {
+            enabled: false,
+            ..Default::default()
+        }
+        .into(),
+        ToneMapping::default().into(),
+        Vignette {
+            enabled: false,
+            ..Default::default()
+        }
+        .into(),
+        Fxaa {
+            enabled: false,
+            ..Default::default()
+        }
+        .into(),
+    ]
+}
+
+// Glow around bright areas: the parts above the threshold are blurred by downsampling them
+// through a chain of ever smaller images, adding the levels back up on the way to full size, and
+// adding the result to the image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bloom {
+    pub enabled: bool,
+    // Brightness, in HDR values, above which pixels glow.
+    pub threshold: f32,
+    // Range below the threshold over which the glow fades in, so it doesn't start abruptly.
+    pub knee: f32,
+    // Scales the glow added to the image.
+    pub intensity: f32,
+    // Number of images in the chain, up to `MAX_BLOOM_LEVELS`; more levels spread the glow
+    // wider.
+    pub levels: u32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Bloom {
+            enabled: true,
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.1,
+            levels: 5,
+        }
+    }
+}
+
+// Curve compressing HDR values into the displayable range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToneMapOperator {
+    // `c / (1 + c)` per channel: never clips, but washes out bright colors.
+    Reinhard,
+    // Fit of the ACES filmic curve, with more contrast and a soft shoulder.
+    #[default]
+    Aces,
+}
+
+// Maps HDR values to the range between 0 and 1. Without it they are clipped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneMapping {
+    pub enabled: bool,
+    pub operator: ToneMapOperator,
+    // Scales the image before the curve is applied; 2 is one stop brighter.
+    pub exposure: f32,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        ToneMapping {
+            enabled: true,
+            operator: ToneMapOperator::default(),
+            exposure: 1.0,
+        }
+    }
+}
+
+// Remaps colors through a 3D lookup table, as exported by most image editors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorGrading {
+    pub enabled: bool,
+    // `size` slices of `size` x `size` texels side by side, see `neutral_lut`. Loaded with
+    // `ColorSpace::Linear`, as it holds sRGB encoded colors which are used as stored.
+    pub lut: Arc<Texture>,
+    // Blends between the original (0) and the graded colors (1).
+    pub strength: f32,
+}
+
+impl ColorGrading {
+    // Fails unless the table is `size * size` texels wide and `size` high.
+    pub fn new(lut: Arc<Texture>) -> Result<ColorGrading> {
+        let [width, height] = lut.view.image().dimensions().width_height();
+        if width != height * height {
+            bail!(
+                "a {}x{} image is not a color lookup table, which is size * size texels wide \
+                 and size high",
+                width,
+                height
+            );
+        }
+
+        Ok(ColorGrading {
+            enabled: true,
+            lut,
+            strength: 1.0,
+        })
+    }
+}
+
+// Darkens the image towards its corners.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vignette {
+    pub enabled: bool,
+    // Fraction of the brightness taken away at full darkening.
+    pub intensity: f32,
+    // Distance from the center, where 1 is a corner, at which the darkening is complete.
+    pub radius: f32,
+    // Distance over which the darkening fades in, inwards from `radius`.
+    pub softness: f32,
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Vignette {
+            enabled: true,
+            intensity: 0.4,
+            radius: 1.0,
+            softness: 0.6,
+        }
+    }
+}
+
+// Fast approximate anti-aliasing: smooths edges found by their contrast in the final image,
+// which is cheaper than multisampling and also catches edges within surfaces.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fxaa {
+    pub enabled: bool,
+    // Maximum distance, in pixels, that is blurred along an edge.
+    pub span_max: f32,
+}
+
+impl Default for Fxaa {
+    fn default() -> Self {
+        Fxaa {
+            enabled: true,
+            span_max: 8.0,
+        }
+    }
+}
+
+// A lookup table leaving colors unchanged, `size` slices of `size` x `size` texels side by
+// side: red grows to the right within a slice, green downwards and blue from slice to slice.
+// Color grading tools can edit a screenshot with it pasted in to produce a new table.
+pub fn neutral_lut(size: u32) -> ImageData {
+    let value = |i: u32| (i * 255 + (size - 1) / 2) / (size - 1).max(1);
+    let rgba = (0..size)
+        .flat_map(|green| {
+            (0..size * size).flat_map(move |x| {
+                let (blue, red) = (x / size, x % size);
+                [value(red) as u8, value(green) as u8, value(blue) as u8, 255]
+            })
+        })
+        .collect();
+
+    ImageData {
+        width: size * size,
+        height: size,
+        rgba,
+    }
+}
+
+// Renders the post-processing chain from the HDR image into the swapchain or offscreen image.
+pub(crate) struct PostProcessing {
+    // Every pixel of an image in the chain is written, so previous contents aren't loaded.
+    render_pass: Arc<RenderPass>,
+    // Adds onto the previous contents, for the way back up the bloom chain.
+    blend_render_pass: Arc<RenderPass>,
+    output_render_pass: Arc<RenderPass>,
+    bloom_prefilter: Arc<GraphicsPipeline>,
+    bloom_downsample: Arc<GraphicsPipeline>,
+    bloom_upsample: Arc<GraphicsPipeline>,
+    bloom_composite: Arc<GraphicsPipeline>,
+    tone_map: Arc<GraphicsPipeline>,
+    color_grading: Arc<GraphicsPipeline>,
+    vignette: Arc<GraphicsPipeline>,
+    fxaa: Arc<GraphicsPipeline>,
+    output: Arc<GraphicsPipeline>,
+    sampler: Arc<Sampler>,
+    // Outputs without an sRGB format get the encoding applied in the shader instead.
+    encode_srgb: bool,
+}
+
+// Images sized like the target that the chain renders through.
+pub(crate) struct PostTargets {
+    // Steps alternate between the two, reading one and writing the other.
+    ping_pong: [PostImage; 2],
+    bloom_levels: Vec<BloomLevel>,
+    // One per target image.
+    output: Vec<Arc<Framebuffer>>,
+}
+
+struct PostImage {
+    view: Arc<ImageView<GpuImage>>,
+    framebuffer: Arc<Framebuffer>,
+}
+
+struct BloomLevel {
+    image: PostImage,
+    // Same image, for blending onto it.
+    blend_framebuffer: Arc<Framebuffer>,
+}
+
+impl PostProcessing {
+    pub(crate) fn new(device: &Arc<Device>, output_format: Format) -> Result<PostProcessing> {
+        // The images stay in the general layout outside the passes, so the next step can sample
+        // them without further transitions.
+        let render_pass = vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                color: {
+                    load: DontCare,
+                    store: Store,
+                    format: HDR_FORMAT,
+                    samples: 1,
+                    initial_layout: ImageLayout::General,
+                    final_layout: ImageLayout::General,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {}
+            }
+        )?;
+        let blend_render_pass = vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                color: {
+                    load: Load,
+                    store: Store,
+                    format: HDR_FORMAT,
+                    samples: 1,
+                    initial_layout: ImageLayout::General,
+                    final_layout: ImageLayout::General,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {}
+            }
+        )?;
+        let output_render_pass = vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                color: {
+                    load: DontCare,
+                    store: Store,
+                    format: output_format,
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {}
+            }
+        )?;
+
+        let vs = post_vs::load(device.clone())?;
+        let pipeline = |fs: Arc<ShaderModule>, render_pass: &Arc<RenderPass>, blend: bool| {
+            let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
+            let mut color_blend_state = ColorBlendState::new(1);
+            if blend {
+                color_blend_state = color_blend_state.blend_additive();
+            }
+            GraphicsPipeline::start()
+                .vertex_input_state(BuffersDefinition::new())
+                .vertex_shader(vs.entry_point("main").unwrap(), ())
+                .input_assembly_state(InputAssemblyState::new())
+                .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+                .fragment_shader(fs.entry_point("main").unwrap(), ())
+                .color_blend_state(color_blend_state)
+                .render_pass(subpass)
+                .build(device.clone())
+        };
+
+        // Bilinear filtering does part of the averaging in the bloom chain; everywhere else
+        // pixels line up with texels.
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(PostProcessing {
+            bloom_prefilter: pipeline(
+                bloom_prefilter_fs::load(device.clone())?,
+                &render_pass,
+                false,
+            )?,
+            bloom_downsample: pipeline(
+                bloom_downsample_fs::load(device.clone())?,
+                &render_pass,
+                false,
+            )?,
+            bloom_upsample: pipeline(
+                bloom_upsample_fs::load(device.clone())?,
+                &blend_render_pass,
+                true,
+            )?,
+            bloom_composite: pipeline(
+                bloom_composite_fs::load(device.clone())?,
+                &render_pass,
+                false,
+            )?,
+            tone_map: pipeline(tone_map_fs::load(device.clone())?, &render_pass, false)?,
+            color_grading: pipeline(color_grading_fs::load(device.clone())?, &render_pass, false)?,
+            vignette: pipeline(vignette_fs::load(device.clone())?, &render_pass, false)?,
+            fxaa: pipeline(fxaa_fs::load(device.clone())?, &render_pass, false)?,
+            output: pipeline(output_fs::load(device.clone())?, &output_render_pass, false)?,
+            render_pass,
+            blend_render_pass,
+            output_render_pass,
+            sampler,
+            encode_srgb: output_format.type_color() != Some(NumericType::SRGB),
+        })
+    }
+
+    // Allocates the images of the chain for a target of `dimensions`, writing into `images`.
+    pub(crate) fn targets<I>(
+        &self,
+        memory_allocator: &StandardMemoryAllocator,
+        dimensions: [u32; 2],
+        images: &[Arc<I>],
+    ) -> Result<PostTargets>
+    where
+        I: ImageAccess + std::fmt::Debug + 'static,
+    {
+        let ping_pong = [
+            self.image(memory_allocator, dimensions)?,
+            self.image(memory_allocator, dimensions)?,
+        ];
+
+        let mut bloom_levels = Vec::new();
+        let mut size = dimensions;
+        while bloom_levels.len() < MAX_BLOOM_LEVELS as usize && size[0] > 1 && size[1] > 1 {
+            size = size.map(|extent| extent / 2);
+            let image = self.image(memory_allocator, size)?;
+            let blend_framebuffer = Framebuffer::new(
+                self.blend_render_pass.clone(),
+                FramebufferCreateInfo {
+                    attachments: vec![image.view.clone()],
+                    ..Default::default()
+                },
+            )?;
+            bloom_levels.push(BloomLevel {
+                image,
+                blend_framebuffer,
+            });
+        }
+
+        let output = images
+            .iter()
+            .map(|image| {
+                let framebuffer = Framebuffer::new(
+                    self.output_render_pass.clone(),
+                    FramebufferCreateInfo {
+                        attachments: vec![ImageView::new_default(image.clone())?],
+                        ..Default::default()
+                    },
+                )?;
+                Ok(framebuffer)
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(PostTargets {
+            ping_pong,
+            bloom_levels,
+            output,
+        })
+    }
+
+    fn image(
+        &self,
+        memory_allocator: &StandardMemoryAllocator,
+        dimensions: [u32; 2],
+    ) -> Result<PostImage> {
+        let view = ImageView::new_default(GpuImage::new(
+            memory_allocator,
+            ImageDimensions::Dim2d {
+                width: dimensions[0],
+                height: dimensions[1],
+                array_layers: 1,
+            },
+            1,
+            HDR_FORMAT,
+            ImageUsage {
+                color_attachment: true,
+                sampled: true,
+                ..ImageUsage::empty()
+            },
+            ImageCreateFlags::empty(),
+        )?)?;
+        let framebuffer = Framebuffer::new(
+            self.render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: vec![view.clone()],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(PostImage { view, framebuffer })
+    }
+
+    // Records the enabled `effects` in order, starting from `hdr`, and the copy of the result
+    // into output image `output_index`. Must be recorded after the scene has been rendered into
+    // `hdr`.
+    pub(crate) fn record<L, A>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+        descriptor_set_allocator: &StandardDescriptorSetAllocator,
+        hdr: &Arc<ImageView<GpuImage>>,
+        targets: &PostTargets,
+        output_index: usize,
+        effects: &[PostEffect],
+    ) -> Result<()>
+    where
+        A: CommandBufferAllocator,
+    {
+        let mut source = hdr.clone();
+        let mut next = 0;
+
+        for effect in effects.iter().filter(|effect| effect.enabled()) {
+            let target = &targets.ping_pong[next];
+            let set = |pipeline: &Arc<GraphicsPipeline>, extra: Option<WriteDescriptorSet>| {
+                self.descriptor_set(descriptor_set_allocator, pipeline, &source, extra)
+            };
+
+            match effect {
+                PostEffect::Bloom(bloom) => {
+                    // Targets of a single pixel are too small for a chain.
+                    let levels = &targets.bloom_levels;
+                    if levels.is_empty() {
+                        continue;
+                    }
+                    let count = (bloom.levels as usize).clamp(1, levels.len());
+                    self.record_bloom_chain(
+                        builder,
+                        descriptor_set_allocator,
+                        &source,
+                        &levels[..count],
+                        bloom,
+                    )?;
+
+                    let bloom_image = WriteDescriptorSet::image_view_sampler(
+                        1,
+                        levels[0].image.view.clone(),
+                        self.sampler.clone(),
+                    );
+                    self.begin(
+                        builder,
+                        &self.bloom_composite,
+                        target.framebuffer.clone(),
+                        set(&self.bloom_composite, Some(bloom_image))?,
+                    )?
+                    .push_constants(
+                        self.bloom_composite.layout().clone(),
+                        0,
+                        bloom_composite_fs::ty::Bloom_Composite {
+                            intensity: bloom.intensity,
+                        },
+                    );
+                }
+                PostEffect::ToneMapping(tone_mapping) => {
+                    self.begin(
+                        builder,
+                        &self.tone_map,
+                        target.framebuffer.clone(),
+                        set(&self.tone_map, None)?,
+                    )?
+                    .push_constants(
+                        self.tone_map.layout().clone(),
+                        0,
+                        tone_map_fs::ty::Tone_Mapping {
+                            exposure: tone_mapping.exposure,
+                            operator: match tone_mapping.operator {
+                                ToneMapOperator::Reinhard => 0,
+                                ToneMapOperator::Aces => 1,
+                            },
+                        },
+                    );
+                }
+                PostEffect::ColorGrading(grading) => {
+                    let lut = WriteDescriptorSet::image_view_sampler(
+                        1,
+                        grading.lut.view.clone(),
+                        grading.lut.sampler.clone(),
+                    );
+                    self.begin(
+                        builder,
+                        &self.color_grading,
+                        target.framebuffer.clone(),
+                        set(&self.color_grading, Some(lut))?,
+                    )?
+                    .push_constants(
+                        self.color_grading.layout().clone(),
+                        0,
+                        color_grading_fs::ty::Color_Grading {
+                            strength: grading.strength,
+                        },
+                    );
+                }
+                PostEffect::Vignette(vignette) => {
+                    self.begin(
+                        builder,
+                        &self.vignette,
+                        target.framebuffer.clone(),
+                        set(&self.vignette, None)?,
+                    )?
+                    .push_constants(
+                        self.vignette.layout().clone(),
+                        0,
+                        vignette_fs::ty::Vignette {
+                            intensity: vignette.intensity,
+                            radius: vignette.radius,
+                            softness: vignette.softness,
+                        },
+                    );
+                }
+                PostEffect::Fxaa(fxaa) => {
+                    self.begin(
+                        builder,
+                        &self.fxaa,
+                        target.framebuffer.clone(),
+                        set(&self.fxaa, None)?,
+                    )?
+                    .push_constants(
+                        self.fxaa.layout().clone(),
+                        0,
+                        fxaa_fs::ty::Fxaa {
+                            span_max: fxaa.span_max,
+                        },
+                    );
+                }
+            }
+            finish(builder)?;
+
+            source = target.view.clone();
+            next = 1 - next;
+        }
+
+        let set = self.descriptor_set(descriptor_set_allocator, &self.output, &source, None)?;
+        self.begin(
+            builder,
+            &self.output,
+            targets.output[output_index].clone(),
+            set,
+        )?
+        .push_constants(
+            self.output.layout().clone(),
+            0,
+            output_fs::ty::Output {
+                encode_srgb: self.encode_srgb as i32,
+            },
+        );
+        finish(builder)
+    }
+
+    // Thresholds `source` into the first level, blurs it down the chain and adds the levels back
+    // up into the first one.
+    fn record_bloom_chain<L, A>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+        descriptor_set_allocator: &StandardDescriptorSetAllocator,
+        source: &Arc<ImageView<GpuImage>>,
+        levels: &[BloomLevel],
+        bloom: &Bloom,
+    ) -> Result<()>
+    where
+        A: CommandBufferAllocator,
+    {
+        let set = self.descriptor_set(
+            descriptor_set_allocator,
+            &self.bloom_prefilter,
+            source,
+            None,
+        )?;
+        self.begin(
+            builder,
+            &self.bloom_prefilter,
+            levels[0].image.framebuffer.clone(),
+            set,
+        )?
+        .push_constants(
+            self.bloom_prefilter.layout().clone(),
+            0,
+            bloom_prefilter_fs::ty::Bloom_Prefilter {
+                threshold: bloom.threshold,
+                knee: bloom.knee,
+            },
+        );
+        finish(builder)?;
+
+        for pair in levels.windows(2) {
+            let set = self.descriptor_set(
+                descriptor_set_allocator,
+                &self.bloom_downsample,
+                &pair[0].image.view,
+                None,
+            )?;
+            self.begin(
+                builder,
+                &self.bloom_downsample,
+                pair[1].image.framebuffer.clone(),
+                set,
+            )?;
+            finish(builder)?;
+        }
+
+        for pair in levels.windows(2).rev() {
+            let set = self.descriptor_set(
+                descriptor_set_allocator,
+                &self.bloom_upsample,
+                &pair[1].image.view,
+                None,
+            )?;
+            self.begin(
+                builder,
+                &self.bloom_upsample,
+                pair[0].blend_framebuffer.clone(),
+                set,
+            )?;
+            finish(builder)?;
+        }
+
+        Ok(())
+    }
+
+    // `source` at binding 0, plus `extra` for the steps reading a second image.
+    fn descriptor_set(
+        &self,
+        descriptor_set_allocator: &StandardDescriptorSetAllocator,
+        pipeline: &Arc<GraphicsPipeline>,
+        source: &Arc<ImageView<GpuImage>>,
+        extra: Option<WriteDescriptorSet>,
+    ) -> Result<Arc<PersistentDescriptorSet>> {
+        let source =
+            WriteDescriptorSet::image_view_sampler(0, source.clone(), self.sampler.clone());
+        let set = PersistentDescriptorSet::new(
+            descriptor_set_allocator,
+            pipeline.layout().set_layouts()[0].clone(),
+            std::iter::once(source).chain(extra),
+        )?;
+
+        Ok(set)
+    }
+
+    // Begins a pass drawing into `framebuffer` with `pipeline`. Push constants can be set on
+    // the returned builder before `finish` draws the full-screen triangle.
+    fn begin<'b, L, A>(
+        &self,
+        builder: &'b mut AutoCommandBufferBuilder<L, A>,
+        pipeline: &Arc<GraphicsPipeline>,
+        framebuffer: Arc<Framebuffer>,
+        set: Arc<PersistentDescriptorSet>,
+    ) -> Result<&'b mut AutoCommandBufferBuilder<L, A>>
+    where
+        A: CommandBufferAllocator,
+    {
+        let [width, height] = framebuffer.extent();
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![None],
+                    ..RenderPassBeginInfo::framebuffer(framebuffer)
+                },
+                SubpassContents::Inline,
+            )?
+            .set_viewport(
+                0,
+                [Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [width as f32, height as f32],
+                    depth_range: 0.0..1.0,
+                }],
+            )
+            .bind_pipeline_graphics(pipeline.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                pipeline.layout().clone(),
+                0,
+                set,
+            );
+
+        Ok(builder)
+    }
+}
+
+fn finish<L, A>(builder: &mut AutoCommandBufferBuilder<L, A>) -> Result<()>
+where
+    A: CommandBufferAllocator,
+{
+    builder.draw(3, 1, 0, 0)?.end_render_pass()?;
+    Ok(())
+}
@@ -0,0 +1,1570 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use nalgebra_glm::TMat4;
+use vulkano::{
+    buffer::{cpu_pool::CpuBufferPoolSubbuffer, BufferAccess, BufferUsage, CpuBufferPool},
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
+        RenderPassBeginInfo, SubpassContents,
+    },
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, PersistentDescriptorSet, WriteDescriptorSet,
+    },
+    device::{
+        physical::{PhysicalDevice, PhysicalDeviceType},
+        Device, DeviceCreateInfo, DeviceExtensions, Features, Queue, QueueCreateInfo,
+    },
+    format::{Format, NumericType},
+    image::{
+        view::{ImageView, ImageViewAbstract},
+        AttachmentImage, ImageAccess, ImageCreateFlags, ImageDimensions, ImageLayout, ImageUsage,
+        SampleCount, SwapchainImage,
+    },
+    instance::{Instance, InstanceCreateInfo, InstanceExtensions},
+    memory::allocator::{MemoryUsage, StandardMemoryAllocator},
+    pipeline::{
+        graphics::{
+            color_blend::ColorBlendState,
+            depth_stencil::{CompareOp, DepthStencilState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::{CullMode, RasterizationState},
+            vertex_input::BuffersDefinition,
+            viewport::{Viewport, ViewportState},
+        },
+        GraphicsPipeline, Pipeline, PipelineBindPoint, StateMode,
+    },
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+    sampler::Sampler,
+    shader::ShaderModule,
+    swapchain::{
+        self, AcquireError, ColorSpace as SurfaceColorSpace, Surface, Swapchain,
+        SwapchainCreateInfo, SwapchainCreationError, SwapchainPresentInfo,
+    },
+    sync::{self, FlushError, GpuFuture},
+    Version, VulkanLibrary,
+};
+use winit::window::Window;
+
+use crate::{
+    clusters::LightClusters,
+    deferred::{Deferred, DeferredFramebuffers},
+    environment::Environment,
+    geometry::normal_matrix,
+    gpu_image::GpuImage,
+    headless::OffscreenTarget,
+    material::{AlphaMode, Material, ShadingModel},
+    post::{PostProcessing, PostTargets, HDR_FORMAT},
+    scene::Scene,
+    shaders::{clustered_fs, fs, vs},
+    shadow::{
+        cascade_splits, point_shadow_casters, shadow_casters, PointShadowCaster, ShadowCaster,
+        ShadowMaps, ShadowSettings, MAX_CASCADES, MAX_POINT_SHADOWS, MAX_SHADOW_MAPS,
+    },
+    ssao::{Ssao, SsaoDepth, SsaoTargets},
+    texture::{ColorSpace, Texture},
+    upload::Uploader,
+    vertex::{Light, Vertex, MAX_LIGHTS},
+};
+
+// Samples per pixel until `Renderer::set_samples` is called, which turns multisampling on.
+const DEFAULT_SAMPLES: u32 = 1;
+
+// How the scene is drawn into the HDR image, see `Renderer::set_render_path`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderPath {
+    // Each node is lit as it is drawn, by at most `vertex::MAX_LIGHTS` lights.
+    #[default]
+    Forward,
+    // Nodes are drawn into a G-buffer first, then each pixel is lit once, by any number of
+    // lights. Multisampling doesn't apply, and blended materials are drawn as opaque.
+    Deferred,
+    // Like the forward path, but the lights are first binned into clusters of the view frustum
+    // and each pixel is lit by those of its cluster, so any number of lights is supported.
+    Clustered,
+}
+
+// Render pass and pipelines of the chosen `RenderPath`.
+enum ScenePass {
+    Forward {
+        render_pass: Arc<RenderPass>,
+        // Without multisampling, the same pass keeping the depth SSAO's depth pass rendered
+        // instead of clearing it.
+        keep_depth_render_pass: Option<Arc<RenderPass>>,
+        pipelines: ScenePipelines,
+    },
+    Deferred(Deferred),
+    // The forward render passes, with their own pipelines.
+    Clustered {
+        render_pass: Arc<RenderPass>,
+        keep_depth_render_pass: Option<Arc<RenderPass>>,
+        pipelines: ScenePipelines,
+        clusters: LightClusters,
+    },
+}
+
+impl ScenePass {
+    fn new(
+        device: &Arc<Device>,
+        memory_allocator: &Arc<StandardMemoryAllocator>,
+        path: RenderPath,
+        samples: SampleCount,
+    ) -> Result<ScenePass> {
+        let keep_depth_render_pass = || {
+            anyhow::Ok(match samples {
+                SampleCount::Sample1 => Some(create_keep_depth_render_pass(device.clone())?),
+                _ => None,
+            })
+        };
+
+        Ok(match path {
+            RenderPath::Forward => {
+                let render_pass = create_render_pass(device.clone(), samples)?;
+                let pipelines =
+                    ScenePipelines::new(device, &render_pass, fs::load(device.clone())?)?;
+                ScenePass::Forward {
+                    render_pass,
+                    keep_depth_render_pass: keep_depth_render_pass()?,
+                    pipelines,
+                }
+            }
+            RenderPath::Deferred => ScenePass::Deferred(Deferred::new(device, memory_allocator)?),
+            RenderPath::Clustered => {
+                let render_pass = create_render_pass(device.clone(), samples)?;
+                let pipelines =
+                    ScenePipelines::new(device, &render_pass, clustered_fs::load(device.clone())?)?;
+                ScenePass::Clustered {
+                    render_pass,
+                    keep_depth_render_pass: keep_depth_render_pass()?,
+                    pipelines,
+                    clusters: LightClusters::new(device, memory_allocator)?,
+                }
+            }
+        })
+    }
+
+    fn path(&self) -> RenderPath {
+        match self {
+            ScenePass::Forward { .. } => RenderPath::Forward,
+            ScenePass::Deferred(_) => RenderPath::Deferred,
+            ScenePass::Clustered { .. } => RenderPath::Clustered,
+        }
+    }
+}
+
+// Pipelines of the forward and clustered paths, one for each combination of the material
+// properties that change fixed-function state.
+struct ScenePipelines {
+    // Indexed by whether the material is double-sided.
+    opaque: [Arc<GraphicsPipeline>; 2],
+    blended: [Arc<GraphicsPipeline>; 2],
+}
+
+impl ScenePipelines {
+    fn new(
+        device: &Arc<Device>,
+        render_pass: &Arc<RenderPass>,
+        fs: Arc<ShaderModule>,
+    ) -> Result<ScenePipelines> {
+        let pipeline = |cull_mode, blended| {
+            create_pipeline(
+                device.clone(),
+                render_pass.clone(),
+                fs.clone(),
+                cull_mode,
+                blended,
+            )
+        };
+        Ok(ScenePipelines {
+            opaque: [
+                pipeline(CullMode::Back, false)?,
+                pipeline(CullMode::None, false)?,
+            ],
+            blended: [
+                pipeline(CullMode::Back, true)?,
+                pipeline(CullMode::None, true)?,
+            ],
+        })
+    }
+
+    fn get(&self, material: &Material) -> &Arc<GraphicsPipeline> {
+        let pipelines = match material.alpha_mode {
+            AlphaMode::Opaque | AlphaMode::Mask { .. } => &self.opaque,
+            AlphaMode::Blend => &self.blended,
+        };
+        &pipelines[material.double_sided as usize]
+    }
+}
+
+// What the renderer draws into: the swapchain of a window surface, or an offscreen image whose
+// contents can be read back with `Renderer::read_pixels`.
+pub enum RenderTarget {
+    Surface(Arc<Surface>),
+    Headless { width: u32, height: u32 },
+}
+
+enum Target {
+    Window {
+        surface: Arc<Surface>,
+        swapchain: Arc<Swapchain>,
+        images: Vec<Arc<SwapchainImage>>,
+        recreate_swapchain: bool,
+        previous_frame_end: Option<Box<dyn GpuFuture>>,
+    },
+    Offscreen(OffscreenTarget),
+}
+
+impl Target {
+    fn output_format(&self) -> Format {
+        match self {
+            Target::Window { swapchain, .. } => swapchain.image_format(),
+            Target::Offscreen(offscreen) => offscreen.format(),
+        }
+    }
+}
+
+pub struct Renderer {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: StandardDescriptorSetAllocator,
+    command_buffer_allocator: StandardCommandBufferAllocator,
+    uploader: Uploader,
+    scene_pass: ScenePass,
+    samples: SampleCount,
+    post_processing: PostProcessing,
+    ssao: Ssao,
+    uniform_buffer: CpuBufferPool<vs::ty::MVP_Data>,
+    ambient_buffer: CpuBufferPool<fs::ty::Ambient_Data>,
+    light_buffer: CpuBufferPool<fs::ty::Light_Data>,
+    // All lights, for the deferred and clustered paths.
+    light_list_buffer: CpuBufferPool<fs::ty::Light>,
+    material_buffer: CpuBufferPool<fs::ty::Material_Data>,
+    camera_buffer: CpuBufferPool<fs::ty::Camera_Data>,
+    environment_buffer: CpuBufferPool<fs::ty::Environment_Data>,
+    // Bound for scenes without an environment.
+    empty_environment: Arc<Environment>,
+    white_texture: Arc<Texture>,
+    flat_normal_texture: Arc<Texture>,
+    shadow_buffer: CpuBufferPool<fs::ty::Shadow_Data>,
+    shadow_maps: ShadowMaps,
+    viewport: Viewport,
+    framebuffers: Framebuffers,
+    target: Target,
+}
+
+// Everything sized like the target.
+struct Framebuffers {
+    // The scene is rendered into `hdr`, through a multisampled image when multisampling is on
+    // in the forward and clustered paths, or through the G-buffer in the deferred one.
+    scene: Arc<Framebuffer>,
+    hdr: Arc<ImageView<GpuImage>>,
+    // Occlusion sampled by the lighting, and the depth prepass it is computed from: the deferred
+    // path's own in `deferred_depth_prepass`, the one in `ssao` otherwise.
+    ssao: SsaoTargets,
+    deferred_depth_prepass: Option<Arc<Framebuffer>>,
+    // Post-processing starts from `hdr` and ends in the target images.
+    post: PostTargets,
+}
+
+impl Renderer {
+    pub fn new(target: RenderTarget) -> Result<Renderer> {
+        let (instance, surface) = match target {
+            RenderTarget::Surface(ref surface) => (surface.instance().clone(), Some(surface)),
+            RenderTarget::Headless { .. } => {
+                let vulkan_library =
+                    VulkanLibrary::new().context("failed to load the Vulkan library")?;
+                (
+                    create_instance(vulkan_library, InstanceExtensions::empty())?,
+                    None,
+                )
+            }
+        };
+
+        let device_extensions = DeviceExtensions {
+            khr_swapchain: surface.is_some(),
+            ..DeviceExtensions::empty()
+        };
+
+        let (physical_device, queue_family_index) =
+            select_physical_device(&instance, &device_extensions, surface.map(|s| &**s))?;
+
+        // Families with transfer but no graphics support are usually backed by dedicated copy
+        // engines, which lets uploads run alongside rendering.
+        let transfer_queue_family_index = physical_device
+            .queue_family_properties()
+            .iter()
+            .position(|q| q.queue_flags.transfer && !q.queue_flags.graphics)
+            .map(|i| i as u32);
+
+        // Anisotropic filtering is used by textures whenever the device has it.
+        let enabled_features = Features {
+            sampler_anisotropy: physical_device.supported_features().sampler_anisotropy,
+            ..Features::empty()
+        };
+
+        let (device, mut queues) = Device::new(
+            physical_device,
+            DeviceCreateInfo {
+                enabled_extensions: device_extensions,
+                enabled_features,
+                queue_create_infos: std::iter::once(queue_family_index)
+                    .chain(transfer_queue_family_index)
+                    .map(|queue_family_index| QueueCreateInfo {
+                        queue_family_index,
+                        ..Default::default()
+                    })
+                    .collect(),
+                ..Default::default()
+            },
+        )?;
+
+        let queue = queues.next().unwrap();
+        let transfer_queue = queues.next().unwrap_or_else(|| queue.clone());
+
+        let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+        let uploader = Uploader::new(memory_allocator.clone(), transfer_queue, queue.clone());
+        let descriptor_set_allocator = StandardDescriptorSetAllocator::new(device.clone());
+        let command_buffer_allocator =
+            StandardCommandBufferAllocator::new(device.clone(), Default::default());
+
+        let mut viewport = Viewport {
+            origin: [0.0, 0.0],
+            dimensions: [0.0, 0.0],
+            depth_range: 0.0..1.0,
+        };
+
+        let target = match target {
+            RenderTarget::Surface(surface) => {
+                let (swapchain, images) = create_swapchain(device.clone(), surface.clone())?;
+                Target::Window {
+                    surface,
+                    swapchain,
+                    images,
+                    recreate_swapchain: false,
+                    previous_frame_end: Some(sync::now(device.clone()).boxed()),
+                }
+            }
+            RenderTarget::Headless { width, height } => {
+                Target::Offscreen(OffscreenTarget::new(&memory_allocator, [width, height])?)
+            }
+        };
+
+        let samples = supported_sample_count(device.physical_device(), DEFAULT_SAMPLES)?;
+        let scene_pass = ScenePass::new(&device, &memory_allocator, RenderPath::Forward, samples)?;
+        let post_processing = PostProcessing::new(&device, target.output_format())?;
+        let ssao = Ssao::new(&uploader)?;
+        let framebuffers = window_size_dependent_setup(
+            &memory_allocator,
+            &target,
+            &scene_pass,
+            &post_processing,
+            &ssao,
+            &mut viewport,
+        )?;
+
+        let uniform_buffer =
+            CpuBufferPool::<vs::ty::MVP_Data>::uniform_buffer(memory_allocator.clone());
+
+        let ambient_buffer =
+            CpuBufferPool::<fs::ty::Ambient_Data>::uniform_buffer(memory_allocator.clone());
+
+        let light_buffer =
+            CpuBufferPool::<fs::ty::Light_Data>::uniform_buffer(memory_allocator.clone());
+
+        let light_list_buffer = CpuBufferPool::<fs::ty::Light>::new(
+            memory_allocator.clone(),
+            BufferUsage {
+                storage_buffer: true,
+                ..BufferUsage::empty()
+            },
+            MemoryUsage::Upload,
+        );
+
+        let material_buffer =
+            CpuBufferPool::<fs::ty::Material_Data>::uniform_buffer(memory_allocator.clone());
+
+        let camera_buffer =
+            CpuBufferPool::<fs::ty::Camera_Data>::uniform_buffer(memory_allocator.clone());
+
+        let environment_buffer =
+            CpuBufferPool::<fs::ty::Environment_Data>::uniform_buffer(memory_allocator.clone());
+
+        let empty_environment = Arc::new(Environment::empty(&queue, &memory_allocator)?);
+        let (white_texture, flat_normal_texture) = uploader.submit_graphics(|builder| {
+            let white = Texture::solid(&uploader, builder, [255; 4], ColorSpace::Srgb)?;
+            // Points straight along the interpolated normal.
+            let flat_normal =
+                Texture::solid(&uploader, builder, [128, 128, 255, 255], ColorSpace::Linear)?;
+            Ok((Arc::new(white), Arc::new(flat_normal)))
+        })?;
+
+        let shadow_buffer =
+            CpuBufferPool::<fs::ty::Shadow_Data>::uniform_buffer(memory_allocator.clone());
+
+        let shadow_maps = ShadowMaps::new(&device, &memory_allocator, &ShadowSettings::default())?;
+
+        let renderer = Renderer {
+            device,
+            queue,
+            memory_allocator,
+            descriptor_set_allocator,
+            command_buffer_allocator,
+            uploader,
+            scene_pass,
+            samples,
+            post_processing,
+            ssao,
+            uniform_buffer,
+            ambient_buffer,
+            light_buffer,
+            light_list_buffer,
+            material_buffer,
+            camera_buffer,
+            environment_buffer,
+            empty_environment,
+            white_texture,
+            flat_normal_texture,
+            shadow_buffer,
+            shadow_maps,
+            viewport,
+            framebuffers,
+            target,
+        };
+
+        Ok(renderer)
+    }
+
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &Arc<Queue> {
+        &self.queue
+    }
+
+    pub fn dimensions(&self) -> [u32; 2] {
+        [
+            self.viewport.dimensions[0] as u32,
+            self.viewport.dimensions[1] as u32,
+        ]
+    }
+
+    pub fn memory_allocator(&self) -> &Arc<StandardMemoryAllocator> {
+        &self.memory_allocator
+    }
+
+    // Uploads meshes and other resources into device-local memory.
+    pub fn uploader(&self) -> &Uploader {
+        &self.uploader
+    }
+
+    // Windows pick up the new size when the next frame is rendered; offscreen targets are
+    // reallocated right away.
+    pub fn resize(&mut self, dimensions: [u32; 2]) -> Result<()> {
+        match &mut self.target {
+            Target::Window {
+                recreate_swapchain, ..
+            } => *recreate_swapchain = true,
+            Target::Offscreen(offscreen) => {
+                if dimensions == offscreen.dimensions() {
+                    return Ok(());
+                }
+                *offscreen = OffscreenTarget::new(&self.memory_allocator, dimensions)?;
+                self.framebuffers = window_size_dependent_setup(
+                    &self.memory_allocator,
+                    &self.target,
+                    &self.scene_pass,
+                    &self.post_processing,
+                    &self.ssao,
+                    &mut self.viewport,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples as u32
+    }
+
+    // Sets the number of samples per pixel used to smooth edges, 1 turning multisampling off.
+    // Fails if the device doesn't support the count for color and depth attachments. The
+    // deferred path doesn't use it.
+    pub fn set_samples(&mut self, samples: u32) -> Result<()> {
+        let samples = supported_sample_count(self.device.physical_device(), samples)?;
+        if samples == self.samples {
+            return Ok(());
+        }
+
+        self.samples = samples;
+        let path = self.scene_pass.path();
+        if path != RenderPath::Deferred {
+            self.recreate_scene_pass(path)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn render_path(&self) -> RenderPath {
+        self.scene_pass.path()
+    }
+
+    pub fn set_render_path(&mut self, path: RenderPath) -> Result<()> {
+        if path == self.scene_pass.path() {
+            return Ok(());
+        }
+
+        self.recreate_scene_pass(path)
+    }
+
+    fn recreate_scene_pass(&mut self, path: RenderPath) -> Result<()> {
+        self.scene_pass = ScenePass::new(&self.device, &self.memory_allocator, path, self.samples)?;
+        self.framebuffers = window_size_dependent_setup(
+            &self.memory_allocator,
+            &self.target,
+            &self.scene_pass,
+            &self.post_processing,
+            &self.ssao,
+            &mut self.viewport,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn render_frame(&mut self, scene: &Scene) -> Result<()> {
+        if let Target::Window {
+            previous_frame_end, ..
+        } = &mut self.target
+        {
+            previous_frame_end.as_mut().unwrap().cleanup_finished();
+        }
+
+        self.recreate_swapchain_if_needed()?;
+
+        let image_index = match &mut self.target {
+            Target::Window {
+                swapchain,
+                recreate_swapchain,
+                ..
+            } => {
+                let (image_index, suboptimal, acquire_future) =
+                    match swapchain::acquire_next_image(swapchain.clone(), None) {
+                        Ok(r) => r,
+                        Err(AcquireError::OutOfDate) => {
+                            *recreate_swapchain = true;
+                            return Ok(());
+                        }
+                        Err(e) => bail!("failed to acquire next image: {:?}", e),
+                    };
+
+                if suboptimal {
+                    *recreate_swapchain = true;
+                }
+
+                Some((image_index, acquire_future))
+            }
+            Target::Offscreen(_) => None,
+        };
+
+        let output_index = match &image_index {
+            Some((image_index, _)) => *image_index as usize,
+            None => 0,
+        };
+
+        let mut cmd_buffer_builder = AutoCommandBufferBuilder::primary(
+            &self.command_buffer_allocator,
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        // Shadow maps are rendered first, each in its own pass, and sampled by the main pass.
+        let draws = scene.draws();
+        let bounds = scene.bounds();
+        let casters = shadow_casters(&scene.lights, &scene.camera, &scene.shadows, bounds);
+        let point_casters = point_shadow_casters(&scene.lights, bounds);
+        self.shadow_maps
+            .resize(&self.memory_allocator, &scene.shadows)?;
+        for caster in &casters {
+            for (i, light_space) in caster.light_spaces.iter().enumerate() {
+                self.shadow_maps.record(
+                    &mut cmd_buffer_builder,
+                    caster.first_layer + i,
+                    light_space,
+                    &draws,
+                )?;
+            }
+        }
+        for caster in &point_casters {
+            self.shadow_maps
+                .record_point(&mut cmd_buffer_builder, caster, &draws)?;
+        }
+
+        let frame = self.frame_uniforms(scene, &casters, &point_casters)?;
+        let transforms = draws
+            .iter()
+            .map(|(_, _, world)| self.transform_uniforms(world, &frame))
+            .collect::<Result<Vec<_>>>()?;
+
+        // SSAO needs the depth of the scene before it is lit, so a depth prepass renders it first.
+        // Without multisampling that is the depth buffer itself, which the main pass then keeps.
+        let depth_prepass = scene.ssao.enabled;
+        if depth_prepass {
+            let prepass_draws = draws
+                .iter()
+                .zip(&transforms)
+                .map(|((node, mesh, _), transform)| {
+                    let writes = self.draw_writes(transform.clone(), &node.material)?;
+                    Ok((*mesh, &node.material, writes))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            match (&self.scene_pass, &self.framebuffers.deferred_depth_prepass) {
+                (ScenePass::Deferred(deferred), Some(framebuffer)) => deferred.record_depth(
+                    &mut cmd_buffer_builder,
+                    &self.descriptor_set_allocator,
+                    framebuffer,
+                    prepass_draws,
+                )?,
+                _ => self.ssao.record_depth(
+                    &mut cmd_buffer_builder,
+                    &self.descriptor_set_allocator,
+                    &self.framebuffers.ssao,
+                    prepass_draws,
+                )?,
+            }
+            self.ssao.record(
+                &mut cmd_buffer_builder,
+                &self.descriptor_set_allocator,
+                &self.framebuffers.ssao,
+                &frame.projection,
+                &scene.ssao,
+            )?;
+        }
+
+        // Only the clustered path reads the clusters, binned once the lights are uploaded.
+        let clusters = match &self.scene_pass {
+            ScenePass::Clustered { clusters, .. } => {
+                let uniforms = clusters.record(
+                    &mut cmd_buffer_builder,
+                    &self.descriptor_set_allocator,
+                    frame.lights.clone(),
+                    &scene.camera,
+                    self.framebuffers.scene.extent(),
+                )?;
+                Some((clusters, uniforms))
+            }
+            _ => None,
+        };
+
+        match &self.scene_pass {
+            ScenePass::Forward {
+                keep_depth_render_pass,
+                pipelines,
+                ..
+            }
+            | ScenePass::Clustered {
+                keep_depth_render_pass,
+                pipelines,
+                ..
+            } => {
+                let mut begin_info = RenderPassBeginInfo {
+                    clear_values: vec![Some([0.0, 0.0, 0.0, 1.0].into()), Some(1f32.into())],
+                    ..RenderPassBeginInfo::framebuffer(self.framebuffers.scene.clone())
+                };
+                match keep_depth_render_pass {
+                    Some(render_pass) if depth_prepass => {
+                        begin_info.render_pass = render_pass.clone();
+                        begin_info.clear_values[1] = None;
+                    }
+                    Some(_) => {}
+                    // Multisampled, and the resolved image is overwritten rather than cleared.
+                    None => begin_info.clear_values.push(None),
+                }
+
+                cmd_buffer_builder
+                    .begin_render_pass(begin_info, SubpassContents::Inline)?
+                    .set_viewport(0, [self.viewport.clone()]);
+
+                // Blended nodes come last in `draws`, over the opaque ones.
+                for ((node, mesh, _), transform) in draws.iter().zip(transforms) {
+                    let pipeline = pipelines.get(&node.material);
+                    let layout = pipeline.layout().set_layouts().first().unwrap();
+                    let mut writes = self.draw_writes(transform, &node.material)?;
+                    writes.extend(self.frame_writes(&frame));
+                    if let Some((clusters, uniforms)) = &clusters {
+                        writes.extend(clusters.writes(uniforms));
+                    }
+                    let set = PersistentDescriptorSet::new(
+                        &self.descriptor_set_allocator,
+                        layout.clone(),
+                        writes,
+                    )?;
+                    cmd_buffer_builder
+                        .bind_pipeline_graphics(pipeline.clone())
+                        .bind_descriptor_sets(
+                            PipelineBindPoint::Graphics,
+                            pipeline.layout().clone(),
+                            0,
+                            set,
+                        );
+                    mesh.draw(&mut cmd_buffer_builder)?;
+                }
+
+                cmd_buffer_builder.end_render_pass()?;
+            }
+            ScenePass::Deferred(deferred) => {
+                let draws = draws
+                    .iter()
+                    .zip(transforms)
+                    .map(|((node, mesh, _), transform)| {
+                        let writes = self.draw_writes(transform, &node.material)?;
+                        Ok((*mesh, &node.material, writes))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                deferred.record(
+                    &mut cmd_buffer_builder,
+                    &self.descriptor_set_allocator,
+                    &self.framebuffers.scene,
+                    depth_prepass,
+                    draws,
+                    self.frame_writes(&frame),
+                    &(frame.projection * frame.view),
+                )?;
+            }
+        }
+
+        self.post_processing.record(
+            &mut cmd_buffer_builder,
+            &self.descriptor_set_allocator,
+            &self.framebuffers.hdr,
+            &self.framebuffers.post,
+            output_index,
+            &scene.post_effects,
+        )?;
+
+        match (&mut self.target, image_index) {
+            (
+                Target::Window {
+                    swapchain,
+                    recreate_swapchain,
+                    previous_frame_end,
+                    ..
+                },
+                Some((image_index, acquire_future)),
+            ) => {
+                let command_buffer = cmd_buffer_builder.build()?;
+
+                let future = previous_frame_end
+                    .take()
+                    .unwrap()
+                    .join(acquire_future)
+                    .then_execute(self.queue.clone(), command_buffer)?
+                    .then_swapchain_present(
+                        self.queue.clone(),
+                        SwapchainPresentInfo::swapchain_image_index(swapchain.clone(), image_index),
+                    )
+                    .then_signal_fence_and_flush();
+
+                match future {
+                    Ok(future) => *previous_frame_end = Some(future.boxed()),
+                    Err(FlushError::OutOfDate) => {
+                        *recreate_swapchain = true;
+                        *previous_frame_end = Some(sync::now(self.device.clone()).boxed());
+                    }
+                    Err(e) => {
+                        log::error!("Failed to flush future: {:?}", e);
+                        *previous_frame_end = Some(sync::now(self.device.clone()).boxed());
+                    }
+                }
+            }
+            (Target::Offscreen(offscreen), _) => {
+                offscreen.copy_to_buffer(&mut cmd_buffer_builder)?;
+                let command_buffer = cmd_buffer_builder.build()?;
+
+                sync::now(self.device.clone())
+                    .then_execute(self.queue.clone(), command_buffer)?
+                    .then_signal_fence_and_flush()?
+                    .wait(None)?;
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    // Returns the RGBA8 pixels of the last frame rendered by a headless renderer.
+    pub fn read_pixels(&self) -> Result<Vec<u8>> {
+        match &self.target {
+            Target::Offscreen(offscreen) => offscreen.read(),
+            Target::Window { .. } => bail!("pixels can only be read back from headless renderers"),
+        }
+    }
+
+    fn recreate_swapchain_if_needed(&mut self) -> Result<()> {
+        let Target::Window {
+            surface,
+            swapchain,
+            images,
+            recreate_swapchain,
+            ..
+        } = &mut self.target
+        else {
+            return Ok(());
+        };
+
+        if !*recreate_swapchain {
+            return Ok(());
+        }
+
+        let (new_swapchain, new_images) = match swapchain.recreate(SwapchainCreateInfo {
+            image_extent: window_size(surface),
+            ..swapchain.create_info()
+        }) {
+            Ok(r) => r,
+            Err(SwapchainCreationError::ImageExtentNotSupported { .. }) => return Ok(()),
+            Err(e) => bail!("failed to recreate swapchain: {:?}", e),
+        };
+
+        *swapchain = new_swapchain;
+        *images = new_images;
+        *recreate_swapchain = false;
+        self.framebuffers = window_size_dependent_setup(
+            &self.memory_allocator,
+            &self.target,
+            &self.scene_pass,
+            &self.post_processing,
+            &self.ssao,
+            &mut self.viewport,
+        )?;
+
+        Ok(())
+    }
+
+    // Camera and light uniforms are shared by every draw of a frame. `casters` and
+    // `point_casters` are the lights with shadow maps, as returned by `shadow_casters` and
+    // `point_shadow_casters`.
+    fn frame_uniforms(
+        &self,
+        scene: &Scene,
+        casters: &[ShadowCaster],
+        point_casters: &[PointShadowCaster],
+    ) -> Result<FrameUniforms> {
+        let ambient = self.ambient_buffer.from_data(fs::ty::Ambient_Data {
+            color: scene.ambient_light.color,
+            intensity: scene.ambient_light.intensity,
+        })?;
+
+        let light_list = scene.lights.iter().enumerate().map(|(i, light)| {
+            let shadow_layer = casters
+                .iter()
+                .find(|caster| caster.light == i)
+                .map(|caster| caster.first_layer)
+                .or_else(|| {
+                    point_casters
+                        .iter()
+                        .find(|caster| caster.light == i)
+                        .map(|caster| caster.slot)
+                });
+            self::light_data(Some(light), shadow_layer)
+        });
+        let lights: Arc<dyn BufferAccess> = match self.scene_pass.path() {
+            RenderPath::Forward => {
+                if scene.lights.len() > MAX_LIGHTS {
+                    bail!(
+                        "the scene has {} lights, at most {} are supported by the forward path",
+                        scene.lights.len(),
+                        MAX_LIGHTS
+                    );
+                }
+                let mut light_data = fs::ty::Light_Data {
+                    lights: [light_data(None, None); MAX_LIGHTS],
+                    count: scene.lights.len() as i32,
+                };
+                for (data, light) in light_data.lights.iter_mut().zip(light_list) {
+                    *data = light;
+                }
+                self.light_buffer.from_data(light_data)?
+            }
+            // Buffers can't be empty, and an unused slot adds no light.
+            RenderPath::Deferred | RenderPath::Clustered => {
+                let mut lights: Vec<_> = light_list.collect();
+                if lights.is_empty() {
+                    lights.push(light_data(None, None));
+                }
+                self.light_list_buffer.from_iter(lights)?
+            }
+        };
+
+        let mut light_space = [[[0.0; 4]; 4]; MAX_SHADOW_MAPS];
+        for caster in casters {
+            for (i, matrix) in caster.light_spaces.iter().enumerate() {
+                light_space[caster.first_layer + i] = (*matrix).into();
+            }
+        }
+        let splits = cascade_splits(&scene.camera, &scene.shadows);
+        let mut split_data = [0.0; MAX_CASCADES];
+        split_data[..splits.len()].copy_from_slice(&splits);
+        let mut point_planes = [[0.0; 4]; MAX_POINT_SHADOWS];
+        for caster in point_casters {
+            point_planes[caster.slot] = [caster.near, caster.far, 0.0, 0.0];
+        }
+        let forward = scene.camera.forward();
+        let shadows = self.shadow_buffer.from_data(fs::ty::Shadow_Data {
+            light_space,
+            cascade_splits: split_data,
+            point_planes,
+            camera_forward: [forward.x, forward.y, forward.z, 0.0],
+            cascade_count: splits.len() as i32,
+            cascade_blend: scene.shadows.cascade_blend,
+            debug_cascades: scene.shadows.debug_cascades as i32,
+            depth_bias: scene.shadows.depth_bias,
+            normal_bias: scene.shadows.normal_bias,
+            pcf_radius: scene.shadows.pcf_radius as i32,
+        })?;
+
+        let camera = self.camera_buffer.from_data(fs::ty::Camera_Data {
+            position: scene.camera.eye().into(),
+        })?;
+
+        let (environment, environment_maps) = match &scene.environment {
+            Some(environment_maps) => (
+                fs::ty::Environment_Data {
+                    intensity: scene.environment_intensity,
+                    max_lod: environment_maps.max_lod(),
+                    enabled: 1,
+                },
+                environment_maps.clone(),
+            ),
+            None => (
+                fs::ty::Environment_Data {
+                    intensity: 0.0,
+                    max_lod: 0.0,
+                    enabled: 0,
+                },
+                self.empty_environment.clone(),
+            ),
+        };
+        let environment = self.environment_buffer.from_data(environment)?;
+
+        // White leaves the ambient light as it is.
+        let ambient_occlusion: (Arc<dyn ImageViewAbstract>, _) = if scene.ssao.enabled {
+            (
+                self.ssao.occlusion(&self.framebuffers.ssao),
+                self.ssao.sampler().clone(),
+            )
+        } else {
+            (
+                self.white_texture.view.clone(),
+                self.white_texture.sampler.clone(),
+            )
+        };
+
+        Ok(FrameUniforms {
+            view: scene.camera.view(),
+            projection: scene.camera.projection(),
+            ambient,
+            lights,
+            camera,
+            environment,
+            environment_maps,
+            shadows,
+            ambient_occlusion,
+        })
+    }
+
+    // Transforms of a node placed at `world`, for `vs`.
+    fn transform_uniforms(
+        &self,
+        world: &TMat4<f32>,
+        frame: &FrameUniforms,
+    ) -> Result<Arc<CpuBufferPoolSubbuffer<vs::ty::MVP_Data>>> {
+        Ok(self.uniform_buffer.from_data(vs::ty::MVP_Data {
+            world: (*world).into(),
+            normal_matrix: normal_matrix(world).into(),
+            view: frame.view.into(),
+            projection: frame.projection.into(),
+        })?)
+    }
+
+    // Writes of the bindings that change with each node: its transforms, as returned by
+    // `transform_uniforms`, material and textures.
+    fn draw_writes(
+        &self,
+        transform: Arc<CpuBufferPoolSubbuffer<vs::ty::MVP_Data>>,
+        material: &Material,
+    ) -> Result<Vec<WriteDescriptorSet>> {
+        // `gbuffer_fs` declares the same block as `fs`.
+        let material_subbuffer = self.material_buffer.from_data(fs::ty::Material_Data {
+            base_color: material.base_color,
+            shininess: material.shininess,
+            specular_color: material.specular_color,
+            metallic: material.metallic,
+            emissive: material.emissive,
+            roughness: material.roughness,
+            occlusion: material.occlusion,
+            occlusion_strength: material.occlusion_strength,
+            normal_scale: material.normal_scale,
+            shading: match material.shading {
+                ShadingModel::BlinnPhong => 0,
+                ShadingModel::Pbr => 1,
+            },
+            alpha: material.alpha,
+            alpha_cutoff: match material.alpha_mode {
+                AlphaMode::Mask { cutoff } => cutoff,
+                _ => 0.0,
+            },
+            alpha_mode: match material.alpha_mode {
+                AlphaMode::Opaque => 0,
+                AlphaMode::Mask { .. } => 1,
+                AlphaMode::Blend => 2,
+            },
+        })?;
+
+        // Textures a material leaves out are replaced by ones that don't change its factors.
+        let textures = [
+            (12, &material.base_color_texture, &self.white_texture),
+            (13, &material.normal_texture, &self.flat_normal_texture),
+            (
+                17,
+                &material.metallic_roughness_texture,
+                &self.white_texture,
+            ),
+            (18, &material.occlusion_texture, &self.white_texture),
+            (19, &material.emissive_texture, &self.white_texture),
+        ];
+
+        let mut writes = vec![
+            WriteDescriptorSet::buffer(0, transform),
+            WriteDescriptorSet::buffer(3, material_subbuffer),
+        ];
+        for (binding, texture, fallback) in textures {
+            let texture = texture.as_ref().unwrap_or(fallback);
+            writes.push(WriteDescriptorSet::image_view_sampler(
+                binding,
+                texture.view.clone(),
+                texture.sampler.clone(),
+            ));
+        }
+
+        Ok(writes)
+    }
+
+    // Writes of the bindings shared by every draw of a frame: lights, shadows, the environment
+    // and the ambient occlusion, declared by `lighting.glsl` except for the lights.
+    fn frame_writes(&self, frame: &FrameUniforms) -> Vec<WriteDescriptorSet> {
+        vec![
+            WriteDescriptorSet::buffer(1, frame.ambient.clone()),
+            WriteDescriptorSet::buffer(2, frame.lights.clone()),
+            WriteDescriptorSet::buffer(4, frame.camera.clone()),
+            WriteDescriptorSet::image_view_sampler(
+                5,
+                frame.environment_maps.irradiance.clone(),
+                frame.environment_maps.sampler.clone(),
+            ),
+            WriteDescriptorSet::image_view_sampler(
+                6,
+                frame.environment_maps.prefiltered.clone(),
+                frame.environment_maps.sampler.clone(),
+            ),
+            WriteDescriptorSet::image_view_sampler(
+                7,
+                frame.environment_maps.brdf_lut.clone(),
+                frame.environment_maps.sampler.clone(),
+            ),
+            WriteDescriptorSet::buffer(8, frame.environment.clone()),
+            WriteDescriptorSet::buffer(9, frame.shadows.clone()),
+            WriteDescriptorSet::image_view_sampler(
+                10,
+                self.shadow_maps.view().clone(),
+                self.shadow_maps.sampler().clone(),
+            ),
+            WriteDescriptorSet::image_view_sampler_array(
+                11,
+                0,
+                self.shadow_maps.point_views().iter().map(|view| {
+                    (
+                        view.clone() as Arc<dyn ImageViewAbstract>,
+                        self.shadow_maps.sampler().clone(),
+                    )
+                }),
+            ),
+            WriteDescriptorSet::image_view_sampler(
+                14,
+                frame.ambient_occlusion.0.clone(),
+                frame.ambient_occlusion.1.clone(),
+            ),
+        ]
+    }
+}
+
+struct FrameUniforms {
+    view: TMat4<f32>,
+    projection: TMat4<f32>,
+    ambient: Arc<CpuBufferPoolSubbuffer<fs::ty::Ambient_Data>>,
+    // Uniform buffer for the forward path, storage buffer of every light for the others.
+    lights: Arc<dyn BufferAccess>,
+    camera: Arc<CpuBufferPoolSubbuffer<fs::ty::Camera_Data>>,
+    environment: Arc<CpuBufferPoolSubbuffer<fs::ty::Environment_Data>>,
+    environment_maps: Arc<Environment>,
+    shadows: Arc<CpuBufferPoolSubbuffer<fs::ty::Shadow_Data>>,
+    // The SSAO result, or a white texture when it is off.
+    ambient_occlusion: (Arc<dyn ImageViewAbstract>, Arc<Sampler>),
+}
+
+// Packs a light in the layout of the fragment shader's `Light` struct. `None` gives an unused
+// slot. `shadow_layer` is the light's first layer in the shadow map array, or the slot of its
+// cube map for point lights, if it has one.
+fn light_data(light: Option<&Light>, shadow_layer: Option<usize>) -> fs::ty::Light {
+    const DIRECTIONAL: f32 = 0.0;
+    const POINT: f32 = 1.0;
+    const SPOT: f32 = 2.0;
+
+    let (position, direction, color, [inner_cone, outer_cone]) = match light {
+        None => ([0.0; 4], [0.0; 4], [0.0; 4], [0.0; 2]),
+        Some(Light::Directional(light)) => {
+            let [dx, dy, dz] = light.direction;
+            let [r, g, b] = light.color;
+            (
+                [0.0, 0.0, 0.0, DIRECTIONAL],
+                [dx, dy, dz, 0.0],
+                [r, g, b, light.intensity],
+                [0.0; 2],
+            )
+        }
+        Some(Light::Point(light)) => {
+            let [px, py, pz] = light.position;
+            let [r, g, b] = light.color;
+            (
+                [px, py, pz, POINT],
+                [0.0, 0.0, 0.0, light.range],
+                [r, g, b, light.intensity],
+                [0.0; 2],
+            )
+        }
+        Some(Light::Spot(light)) => {
+            let [px, py, pz] = light.position;
+            let [dx, dy, dz] = light.direction;
+            let [r, g, b] = light.color;
+            (
+                [px, py, pz, SPOT],
+                [dx, dy, dz, light.range],
+                [r, g, b, light.intensity],
+                [light.inner_cone_angle.cos(), light.outer_cone_angle.cos()],
+            )
+        }
+    };
+
+    let shadow_layer = shadow_layer.map_or(-1.0, |layer| layer as f32);
+
+    fs::ty::Light {
+        position,
+        direction,
+        color,
+        cone: [inner_cone, outer_cone, shadow_layer, 0.0],
+    }
+}
+
+pub fn create_instance(
+    vulkan_library: Arc<VulkanLibrary>,
+    extensions: InstanceExtensions,
+) -> Result<Arc<Instance>> {
+    let instance = Instance::new(
+        vulkan_library,
+        InstanceCreateInfo {
+            enabled_extensions: extensions,
+            max_api_version: Some(Version::V1_1),
+            ..Default::default()
+        },
+    )?;
+
+    Ok(instance)
+}
+
+// Picks the most capable device with a graphics queue. When a surface is given the queue must
+// also be able to present to it.
+fn select_physical_device(
+    instance: &Arc<Instance>,
+    device_extensions: &DeviceExtensions,
+    surface: Option<&Surface>,
+) -> Result<(Arc<PhysicalDevice>, u32)> {
+    let (physical_device, queue_family_index) = instance
+        .enumerate_physical_devices()?
+        .filter(|p| p.supported_extensions().contains(device_extensions))
+        .filter_map(|p| {
+            p.queue_family_properties()
+                .iter()
+                .enumerate()
+                .position(|(i, q)| {
+                    q.queue_flags.graphics
+                        && surface.is_none_or(|surface| {
+                            p.surface_support(i as u32, surface).unwrap_or(false)
+                        })
+                })
+                .map(|i| (p, i as u32))
+        })
+        .min_by_key(|(p, _)| match p.properties().device_type {
+            PhysicalDeviceType::DiscreteGpu => 0,
+            PhysicalDeviceType::IntegratedGpu => 1,
+            PhysicalDeviceType::VirtualGpu => 2,
+            PhysicalDeviceType::Cpu => 3,
+            PhysicalDeviceType::Other => 4,
+            _ => 5,
+        })
+        .context("no device available")?;
+
+    Ok((physical_device, queue_family_index))
+}
+
+fn window_size(surface: &Surface) -> [u32; 2] {
+    surface
+        .object()
+        .unwrap()
+        .downcast_ref::<Window>()
+        .unwrap()
+        .inner_size()
+        .into()
+}
+
+fn create_swapchain(
+    device: Arc<Device>,
+    surface: Arc<Surface>,
+) -> Result<(Arc<Swapchain>, Vec<Arc<SwapchainImage>>)> {
+    let caps = device
+        .physical_device()
+        .surface_capabilities(&surface, Default::default())?;
+    let usage = caps.supported_usage_flags;
+    let alpha = caps.supported_composite_alpha.iter().next().unwrap();
+    // Prefer a format that encodes sRGB on write; the tone mapping pass encodes it itself for
+    // the others.
+    let formats = device
+        .physical_device()
+        .surface_formats(&surface, Default::default())?;
+    let (image_format, image_color_space) = formats
+        .iter()
+        .find(|(format, color_space)| {
+            format.type_color() == Some(NumericType::SRGB)
+                && *color_space == SurfaceColorSpace::SrgbNonLinear
+        })
+        .unwrap_or(&formats[0])
+        .to_owned();
+    let image_extent = window_size(&surface);
+
+    let swapchain = Swapchain::new(
+        device,
+        surface,
+        SwapchainCreateInfo {
+            min_image_count: caps.min_image_count,
+            image_format: Some(image_format),
+            image_color_space,
+            image_extent,
+            image_usage: usage,
+            composite_alpha: alpha,
+            ..Default::default()
+        },
+    )?;
+
+    Ok(swapchain)
+}
+
+// Renders into the HDR image, which stays in the general layout outside the pass so
+// post-processing can sample it. With a single sample so does the depth buffer, which SSAO's
+// depth pass renders into. With more than one sample, color and depth are rendered into
+// multisampled images and the color is resolved into the HDR image at the end of the pass.
+fn create_render_pass(device: Arc<Device>, samples: SampleCount) -> Result<Arc<RenderPass>> {
+    if samples == SampleCount::Sample1 {
+        let render_pass = vulkano::single_pass_renderpass!(
+            device,
+            attachments: {
+                color: {
+                    load: Clear,
+                    store: Store,
+                    format: HDR_FORMAT,
+                    samples: 1,
+                    initial_layout: ImageLayout::General,
+                    final_layout: ImageLayout::General,
+                },
+                depth: {
+                    load: Clear,
+                    store: DontCare,
+                    format: Format::D16_UNORM,
+                    samples: 1,
+                    initial_layout: ImageLayout::General,
+                    final_layout: ImageLayout::General,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {depth}
+            }
+        )?;
+        return Ok(render_pass);
+    }
+
+    let render_pass = vulkano::single_pass_renderpass!(
+        device,
+        attachments: {
+            multisampled_color: {
+                load: Clear,
+                store: DontCare,
+                format: HDR_FORMAT,
+                samples: samples as u32,
+            },
+            depth: {
+                load: Clear,
+                store: DontCare,
+                format: Format::D16_UNORM,
+                samples: samples as u32,
+            },
+            color: {
+                load: DontCare,
+                store: Store,
+                format: HDR_FORMAT,
+                samples: 1,
+                initial_layout: ImageLayout::General,
+                final_layout: ImageLayout::General,
+            }
+        },
+        pass: {
+            color: [multisampled_color],
+            depth_stencil: {depth},
+            resolve: [color]
+        }
+    )?;
+
+    Ok(render_pass)
+}
+
+// The single-sample pass of `create_render_pass`, keeping the contents of the depth buffer
+// rather than clearing it. Compatible with it, so they share pipelines and framebuffers.
+fn create_keep_depth_render_pass(device: Arc<Device>) -> Result<Arc<RenderPass>> {
+    let render_pass = vulkano::single_pass_renderpass!(
+        device,
+        attachments: {
+            color: {
+                load: Clear,
+                store: Store,
+                format: HDR_FORMAT,
+                samples: 1,
+                initial_layout: ImageLayout::General,
+                final_layout: ImageLayout::General,
+            },
+            depth: {
+                load: Load,
+                store: DontCare,
+                format: Format::D16_UNORM,
+                samples: 1,
+                initial_layout: ImageLayout::General,
+                final_layout: ImageLayout::General,
+            }
+        },
+        pass: {
+            color: [color],
+            depth_stencil: {depth}
+        }
+    )?;
+
+    Ok(render_pass)
+}
+
+// Checks `samples` against the sample counts the device supports for color and depth
+// attachments.
+fn supported_sample_count(physical_device: &PhysicalDevice, samples: u32) -> Result<SampleCount> {
+    let properties = physical_device.properties();
+    let supported =
+        properties.framebuffer_color_sample_counts & properties.framebuffer_depth_sample_counts;
+
+    SampleCount::try_from(samples)
+        .ok()
+        .filter(|&count| supported.contains_count(count))
+        .with_context(|| {
+            let counts: Vec<_> = [1, 2, 4, 8, 16, 32, 64]
+                .into_iter()
+                .filter(|&count| supported.contains_count(SampleCount::try_from(count).unwrap()))
+                .map(|count| count.to_string())
+                .collect();
+            format!(
+                "{} samples per pixel are not supported, the device supports {}",
+                samples,
+                counts.join(", ")
+            )
+        })
+}
+
+// Pipeline drawing nodes with `vs` and `fs`, the fragment shader of the forward or clustered
+// path. Fragments at the same depth as the depth buffer pass, so nodes are still drawn over the
+// depth of SSAO's depth pass. Blended nodes are mixed with the color behind them by their alpha
+// and don't write depth, so they don't hide blended nodes further back.
+fn create_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPass>,
+    fs: Arc<ShaderModule>,
+    cull_mode: CullMode,
+    blended: bool,
+) -> Result<Arc<GraphicsPipeline>> {
+    let vs = vs::load(device.clone())?;
+    let subpass = Subpass::from(render_pass, 0).unwrap();
+    let multisample_state = MultisampleState {
+        rasterization_samples: subpass.num_samples().unwrap(),
+        ..Default::default()
+    };
+    let mut depth_stencil_state = DepthStencilState::simple_depth_test();
+    if let Some(depth) = &mut depth_stencil_state.depth {
+        depth.compare_op = StateMode::Fixed(CompareOp::LessOrEqual);
+        depth.write_enable = StateMode::Fixed(!blended);
+    }
+    let color_blend_state = if blended {
+        ColorBlendState::new(1).blend_alpha()
+    } else {
+        ColorBlendState::new(1)
+    };
+
+    let pipeline = GraphicsPipeline::start()
+        .vertex_input_state(BuffersDefinition::new().vertex::<Vertex>())
+        .vertex_shader(vs.entry_point("main").unwrap(), ())
+        .input_assembly_state(InputAssemblyState::new())
+        .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+        .fragment_shader(fs.entry_point("main").unwrap(), ())
+        .depth_stencil_state(depth_stencil_state)
+        .color_blend_state(color_blend_state)
+        .rasterization_state(RasterizationState::new().cull_mode(cull_mode))
+        .multisample_state(multisample_state)
+        .render_pass(subpass)
+        .build(device)?;
+
+    Ok(pipeline)
+}
+
+// Allocates the HDR image the scene is rendered into with the other attachments of the scene
+// pass, along with the images of SSAO and of the post-processing chain. The chain ends in the
+// target images: the swapchain images when presenting to a window, or the offscreen attachment
+// in headless mode.
+fn window_size_dependent_setup(
+    standard_memory_allocator: &StandardMemoryAllocator,
+    target: &Target,
+    scene_pass: &ScenePass,
+    post_processing: &PostProcessing,
+    ssao: &Ssao,
+    viewport: &mut Viewport,
+) -> Result<Framebuffers> {
+    let (dimensions, post) = match target {
+        Target::Window { images, .. } => {
+            let dimensions = images[0].dimensions().width_height();
+            let post = post_processing.targets(standard_memory_allocator, dimensions, images)?;
+            (dimensions, post)
+        }
+        Target::Offscreen(offscreen) => {
+            let dimensions = offscreen.dimensions();
+            let images = std::slice::from_ref(offscreen.image());
+            let post = post_processing.targets(standard_memory_allocator, dimensions, images)?;
+            (dimensions, post)
+        }
+    };
+    viewport.dimensions = [dimensions[0] as f32, dimensions[1] as f32];
+
+    let hdr = ImageView::new_default(GpuImage::new(
+        standard_memory_allocator,
+        ImageDimensions::Dim2d {
+            width: dimensions[0],
+            height: dimensions[1],
+            array_layers: 1,
+        },
+        1,
+        HDR_FORMAT,
+        ImageUsage {
+            color_attachment: true,
+            sampled: true,
+            ..ImageUsage::empty()
+        },
+        ImageCreateFlags::empty(),
+    )?)?;
+    let (scene, deferred_depth_prepass, ssao_depth) = match scene_pass {
+        ScenePass::Forward { render_pass, .. } | ScenePass::Clustered { render_pass, .. } => {
+            let (attachments, ssao_depth) =
+                forward_attachments(standard_memory_allocator, render_pass, dimensions, &hdr)?;
+            let scene = Framebuffer::new(
+                render_pass.clone(),
+                FramebufferCreateInfo {
+                    attachments,
+                    ..Default::default()
+                },
+            )?;
+            (scene, None, ssao_depth)
+        }
+        ScenePass::Deferred(deferred) => {
+            let DeferredFramebuffers {
+                scene,
+                depth_prepass,
+                depth,
+            } = deferred.framebuffers(standard_memory_allocator, dimensions, hdr.clone())?;
+            (scene, Some(depth_prepass), SsaoDepth::GBuffer(depth))
+        }
+    };
+
+    let ssao = ssao.targets(standard_memory_allocator, dimensions, ssao_depth)?;
+
+    Ok(Framebuffers {
+        scene,
+        hdr,
+        ssao,
+        deferred_depth_prepass,
+        post,
+    })
+}
+
+// The depth buffer and, when the render pass is multisampled, the multisampled color image
+// resolved into `hdr`, in the order of the attachments of `create_render_pass`. Also returns
+// where SSAO gets the depth: the depth buffer, unless it is multisampled.
+fn forward_attachments(
+    memory_allocator: &StandardMemoryAllocator,
+    render_pass: &Arc<RenderPass>,
+    dimensions: [u32; 2],
+    hdr: &Arc<ImageView<GpuImage>>,
+) -> Result<(Vec<Arc<dyn ImageViewAbstract>>, SsaoDepth)> {
+    let samples = Subpass::from(render_pass.clone(), 0)
+        .unwrap()
+        .num_samples()
+        .unwrap();
+
+    if samples == SampleCount::Sample1 {
+        let depth_buffer = ImageView::new_default(GpuImage::new(
+            memory_allocator,
+            ImageDimensions::Dim2d {
+                width: dimensions[0],
+                height: dimensions[1],
+                array_layers: 1,
+            },
+            1,
+            Format::D16_UNORM,
+            ImageUsage {
+                depth_stencil_attachment: true,
+                sampled: true,
+                ..ImageUsage::empty()
+            },
+            ImageCreateFlags::empty(),
+        )?)?;
+        return Ok((
+            vec![hdr.clone(), depth_buffer.clone()],
+            SsaoDepth::DepthBuffer(depth_buffer),
+        ));
+    }
+
+    let depth_buffer = ImageView::new_default(AttachmentImage::transient_multisampled(
+        memory_allocator,
+        dimensions,
+        samples,
+        Format::D16_UNORM,
+    )?)?;
+
+    let multisampled_color = ImageView::new_default(AttachmentImage::transient_multisampled(
+        memory_allocator,
+        dimensions,
+        samples,
+        HDR_FORMAT,
+    )?)?;
+    Ok((
+        vec![multisampled_color, depth_buffer, hdr.clone()],
+        SsaoDepth::Separate,
+    ))
+}
@@ -0,0 +1,255 @@
+use std::sync::Arc;
+
+use nalgebra_glm::{identity, pi, rotate_normalized_axis, translate, vec3, TMat4};
+
+use crate::{
+    camera::Camera,
+    environment::Environment,
+    geometry::Bounds,
+    material::{AlphaMode, Material},
+    mesh::Mesh,
+    post::{default_post_effects, PostEffect},
+    shadow::ShadowSettings,
+    ssao::SsaoSettings,
+    vertex::{AmbientLight, DirectionalLight, Light},
+};
+
+// How far in front of the origin `demo_transform` places the model.
+const DEMO_DISTANCE: f32 = 2.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+// An element of the scene graph. Nodes without a mesh only group and transform their children.
+#[derive(Debug, Clone)]
+pub struct Node {
+    // Transform relative to the parent node, or to the world for root nodes.
+    pub transform: TMat4<f32>,
+    pub parent: Option<NodeId>,
+    pub mesh: Option<Arc<Mesh>>,
+    pub material: Material,
+    // Hidden nodes are not drawn, and neither are their descendants.
+    pub visible: bool,
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Node {
+            transform: identity(),
+            parent: None,
+            mesh: None,
+            material: Material::default(),
+            visible: true,
+        }
+    }
+}
+
+// Everything that changes from frame to frame and is handed to `Renderer::render_frame`. Every
+// visible node with a mesh is drawn once, with its world transform.
+#[derive(Debug, Clone)]
+pub struct Scene {
+    nodes: Vec<Node>,
+    pub camera: Camera,
+    // Used instead of `ambient_light` when set.
+    pub environment: Option<Arc<Environment>>,
+    // Scales the light from `environment`.
+    pub environment_intensity: f32,
+    pub ambient_light: AmbientLight,
+    // At most `vertex::MAX_LIGHTS` lights are supported by the forward render path.
+    pub lights: Vec<Light>,
+    pub shadows: ShadowSettings,
+    // Darkens the ambient light where geometry is close together.
+    pub ssao: SsaoSettings,
+    // Applied in order to the rendered image, see `post::PostEffect`.
+    pub post_effects: Vec<PostEffect>,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Scene::new()
+    }
+}
+
+impl Scene {
+    // An empty scene lit by a white directional and ambient light, seen from a camera at the origin
+    // orbiting the point where `demo_transform` places the model.
+    pub fn new() -> Scene {
+        Scene {
+            nodes: Vec::new(),
+            camera: Camera::orbit(vec3(0.0, 0.0, -DEMO_DISTANCE), DEMO_DISTANCE),
+            environment: None,
+            environment_intensity: 1.0,
+            ambient_light: AmbientLight {
+                color: [1.0, 1.0, 1.0],
+                intensity: 0.2,
+            },
+            // Shines from the upper left onto the model.
+            lights: vec![DirectionalLight {
+                direction: [4.0, -4.0, -DEMO_DISTANCE],
+                color: [1.0, 1.0, 1.0],
+                intensity: 1.0,
+                cast_shadows: false,
+            }
+            .into()],
+            shadows: ShadowSettings::default(),
+            ssao: SsaoSettings::default(),
+            post_effects: default_post_effects(),
+        }
+    }
+
+    // The demo scene: `mesh` spinning around all three axes, `elapsed` seconds into the
+    // animation.
+    pub fn demo(elapsed: f64, mesh: Arc<Mesh>) -> Scene {
+        let mut scene = Scene::new();
+        scene.add_node(Node {
+            transform: demo_transform(elapsed),
+            mesh: Some(mesh),
+            material: Material::glossy([1.0, 1.0, 1.0], 32.0),
+            ..Default::default()
+        });
+        scene
+    }
+
+    // Parents have to be added before their children, which keeps the graph free of cycles.
+    pub fn add_node(&mut self, node: Node) -> NodeId {
+        if let Some(parent) = node.parent {
+            assert!(
+                parent.0 < self.nodes.len(),
+                "parent {:?} is not part of the scene",
+                parent
+            );
+        }
+
+        self.nodes.push(node);
+        NodeId(self.nodes.len() - 1)
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    // The parent of a node can't be changed, since that could break the ordering required by
+    // `add_node`.
+    pub fn node_mut(&mut self, id: NodeId) -> NodeMut<'_> {
+        NodeMut(&mut self.nodes[id.0])
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (NodeId(i), node))
+    }
+
+    // World transform of every node, indexed like `nodes`.
+    pub fn world_transforms(&self) -> Vec<TMat4<f32>> {
+        let mut transforms: Vec<TMat4<f32>> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let transform = match node.parent {
+                Some(parent) => transforms[parent.0] * node.transform,
+                None => node.transform,
+            };
+            transforms.push(transform);
+        }
+        transforms
+    }
+
+    // Whether each node is drawn, indexed like `nodes`: a node is visible when it and all of its
+    // ancestors are.
+    pub fn visibility(&self) -> Vec<bool> {
+        let mut visible: Vec<bool> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let is_visible = node.visible && node.parent.is_none_or(|parent| visible[parent.0]);
+            visible.push(is_visible);
+        }
+        visible
+    }
+
+    // World-space bounds of every node that is drawn, or `None` when nothing is.
+    pub fn bounds(&self) -> Option<Bounds> {
+        self.draws()
+            .into_iter()
+            .map(|(_, mesh, transform)| mesh.bounds().transform(&transform))
+            .reduce(|a, b| a.union(&b))
+    }
+
+    // Nodes to draw this frame with their world transforms. Those with blended materials come
+    // last, from the farthest to the nearest to the camera by the center of their bounds, so
+    // each is blended over what is behind it.
+    pub fn draws(&self) -> Vec<(&Node, &Arc<Mesh>, TMat4<f32>)> {
+        let (mut draws, mut blended): (Vec<_>, Vec<_>) = self
+            .nodes
+            .iter()
+            .zip(self.world_transforms())
+            .zip(self.visibility())
+            .filter_map(|((node, transform), visible)| match &node.mesh {
+                Some(mesh) if visible => Some((node, mesh, transform)),
+                _ => None,
+            })
+            .partition(|(node, _, _)| node.material.alpha_mode != AlphaMode::Blend);
+
+        // The camera looks down the negative z axis of view space.
+        let view = self.camera.view();
+        let view_depth = |mesh: &Mesh, transform: &TMat4<f32>| {
+            let center = mesh.bounds().transform(transform).center();
+            -(view * center.push(1.0)).z
+        };
+        blended.sort_by(|(_, a, a_transform), (_, b, b_transform)| {
+            view_depth(b, b_transform).total_cmp(&view_depth(a, a_transform))
+        });
+        draws.extend(blended);
+        draws
+    }
+}
+
+// Mutable access to a node's fields except its parent.
+pub struct NodeMut<'a>(&'a mut Node);
+
+impl NodeMut<'_> {
+    pub fn set_transform(&mut self, transform: TMat4<f32>) -> &mut Self {
+        self.0.transform = transform;
+        self
+    }
+
+    pub fn set_mesh(&mut self, mesh: Option<Arc<Mesh>>) -> &mut Self {
+        self.0.mesh = mesh;
+        self
+    }
+
+    pub fn set_material(&mut self, material: Material) -> &mut Self {
+        self.0.material = material;
+        self
+    }
+
+    pub fn set_visible(&mut self, visible: bool) -> &mut Self {
+        self.0.visible = visible;
+        self
+    }
+}
+
+// Places the model in front of the camera, spinning around all three axes `elapsed` seconds
+// into the animation.
+pub fn demo_transform(elapsed: f64) -> TMat4<f32> {
+    translate(&identity(), &vec3(0.0, 0.0, -DEMO_DISTANCE)) * rotation(elapsed)
+}
+
+// Rotation animation
+fn rotation(elapsed: f64) -> TMat4<f32> {
+    let elapsed_as_radians = elapsed * pi::<f64>() / 180.0;
+    let mut model: TMat4<f32> = rotate_normalized_axis(
+        &identity(),
+        elapsed_as_radians as f32 * 50.0,
+        &vec3(0.0, 0.0, 1.0),
+    );
+    model = rotate_normalized_axis(
+        &model,
+        elapsed_as_radians as f32 * 30.0,
+        &vec3(0.0, 1.0, 0.0),
+    );
+
+    rotate_normalized_axis(
+        &model,
+        elapsed_as_radians as f32 * 20.0,
+        &vec3(1.0, 0.0, 0.0),
+    )
+}
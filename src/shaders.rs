@@ -7,13 +7,23 @@ pub mod vs {
             layout(location = 0) in vec3 position;
             layout(location = 1) in vec3 normal;
             layout(location = 2) in vec3 color;
+            layout(location = 3) in vec2 uv;
+            layout(location = 4) in vec4 tangent;
 
             layout(location = 0) out vec3 out_color;
             layout(location = 1) out vec3 out_normal;
             layout(location = 2) out vec3 frag_pos;
+            layout(location = 3) out vec2 out_uv;
+            layout(location = 4) out vec4 out_tangent;
+
+            // SSAO's depth pass draws with this shader too, and the forward path tests against
+            // the depth it left, so both must compute exactly the same positions.
+            invariant gl_Position;
 
             layout(set = 0, binding = 0) uniform MVP_Data {
                 mat4 world;
+                // See `geometry::normal_matrix`.
+                mat4 normal_matrix;
                 mat4 view;
                 mat4 projection;
             } uniforms;
@@ -22,8 +32,13 @@ pub mod vs {
                 mat4 worldview = uniforms.view * uniforms.world;
                 gl_Position = uniforms.projection * worldview * vec4(position, 1.0);
                 out_color = color;
-                out_normal = mat3(uniforms.world) * normal;
+                // Tangents lie in the surface and move with it, unlike normals.
+                mat3 world = mat3(uniforms.world);
+                out_normal = mat3(uniforms.normal_matrix) * normal;
                 frag_pos = vec3(uniforms.world * vec4(position, 1.0));
+                out_uv = uv;
+                // Mirroring flips the handedness of the tangent space.
+                out_tangent = vec4(world * tangent.xyz, tangent.w * sign(determinant(world)));
             }
             ",
             types_meta: {
@@ -37,31 +52,35 @@ pub mod vs {
 pub mod fs {
     vulkano_shaders::shader! {
         ty: "fragment",
+        include: ["src/shaders"],
         src: "
             #version 450
-            layout(location = 0) in vec3 in_color;
-            layout(location = 1) in vec3 in_normal;
-            layout(location = 2) in vec3 frag_pos;
 
-            layout(location = 0) out vec4 f_color;
+            #include <lighting.glsl>
+            #include <material.glsl>
 
-            layout(set = 0, binding = 1) uniform Ambient_Data {
-                vec3 color;
-                float intensity;
-            } ambient;
+            // Must match `vertex::MAX_LIGHTS`.
+            #define MAX_LIGHTS 16
 
-            layout(set = 0, binding = 2) uniform Directional_Light_Data {
-                vec4 position;
-                vec3 color;
-            } directional;
+            layout(location = 0) out vec4 f_color;
+
+            layout(set = 0, binding = 2) uniform Light_Data {
+                Light lights[MAX_LIGHTS];
+                int count;
+            } lighting;
 
             void main() {
-                vec3 ambient_color = ambient.intensity * ambient.color;
-                vec3 light_direction = normalize(directional.position.xyz - frag_pos);
-                float directional_intensity = max(dot(in_normal, light_direction), 0.0);
-                vec3 directional_color = directional_intensity * directional.color;
-                vec3 combined_color = (ambient_color + directional_color) * in_color;
-                f_color = vec4(combined_color, 1.0);
+                float alpha = surface_alpha();
+                Surface surface = material_surface();
+                vec3 view_direction = normalize(camera.position - frag_pos);
+
+                // Linear and unbounded; the tone mapping pass maps it to the output.
+                vec3 color = indirect_light(surface, view_direction);
+                for (int i = 0; i < lighting.count; i++) {
+                    color += direct_light(lighting.lights[i], surface, view_direction);
+                }
+
+                f_color = vec4(debug_cascade_tint(color, frag_pos), alpha);
             }
             ",
             types_meta: {
@@ -71,3 +90,1165 @@ pub mod fs {
             }
     }
 }
+
+// Deferred path, see `deferred::Deferred`. Nodes are drawn with `vs` and `gbuffer_fs` into the
+// G-buffer, which `deferred_lighting_fs` then lights with a full-screen triangle from `post_vs`.
+
+pub mod gbuffer_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        include: ["src/shaders"],
+        src: "
+            #version 450
+
+            #include <lighting.glsl>
+            #include <material.glsl>
+
+            // Must match the formats in `deferred::GBUFFER_FORMATS`.
+            // rgb: albedo, a: material occlusion
+            layout(location = 0) out vec4 f_albedo;
+            // xyz: normal, w: shading model
+            layout(location = 1) out vec4 f_normal;
+            // x: metallic, y: roughness, z: shininess
+            layout(location = 2) out vec4 f_material;
+            // rgb: specular color for Blinn-Phong, emission for PBR
+            layout(location = 3) out vec4 f_reflectance;
+
+            void main() {
+                // Only masked fragments are left out; blended materials are drawn as opaque.
+                surface_alpha();
+                Surface surface = material_surface();
+                f_albedo = vec4(surface.albedo, surface.occlusion);
+                f_normal = vec4(surface.normal, float(surface.shading));
+                f_material = vec4(surface.metallic, surface.roughness, surface.shininess, 0.0);
+                vec3 reflectance = surface.shading == PBR
+                    ? surface.emissive
+                    : surface.specular_color;
+                f_reflectance = vec4(reflectance, 0.0);
+            }
+            ",
+    }
+}
+
+pub mod deferred_lighting_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        include: ["src/shaders"],
+        src: "
+            #version 450
+
+            #include <lighting.glsl>
+
+            layout(location = 0) in vec2 in_uv;
+
+            layout(location = 0) out vec4 f_color;
+
+            layout(set = 0, binding = 0) uniform Deferred_Data {
+                // From clip space back to world space.
+                mat4 inverse_view_projection;
+            } frame;
+
+            // Every light of the scene, with at least one unused slot.
+            layout(set = 0, binding = 2) readonly buffer Light_List {
+                Light lights[];
+            } light_list;
+
+            // Written by `gbuffer_fs`.
+            layout(input_attachment_index = 0, set = 1, binding = 0) uniform subpassInput g_albedo;
+            layout(input_attachment_index = 1, set = 1, binding = 1) uniform subpassInput g_normal;
+            layout(input_attachment_index = 2, set = 1, binding = 2) uniform subpassInput g_material;
+            layout(input_attachment_index = 3, set = 1, binding = 3)
+                uniform subpassInput g_reflectance;
+            layout(input_attachment_index = 4, set = 1, binding = 4) uniform subpassInput g_depth;
+
+            void main() {
+                float depth = subpassLoad(g_depth).r;
+                // Nothing was drawn here, so the cleared background stays.
+                if (depth >= 1.0) {
+                    discard;
+                }
+
+                vec4 position = frame.inverse_view_projection * vec4(in_uv * 2.0 - 1.0, depth, 1.0);
+                vec4 albedo = subpassLoad(g_albedo);
+                vec4 normal = subpassLoad(g_normal);
+                vec4 material = subpassLoad(g_material);
+                vec3 reflectance = subpassLoad(g_reflectance).rgb;
+                int shading = int(round(normal.w));
+
+                Surface surface = Surface(
+                    position.xyz / position.w,
+                    normalize(normal.xyz),
+                    albedo.rgb,
+                    shading == PBR ? vec3(0.0) : reflectance,
+                    material.z,
+                    material.x,
+                    material.y,
+                    albedo.a,
+                    shading == PBR ? reflectance : vec3(0.0),
+                    shading
+                );
+                vec3 view_direction = normalize(camera.position - surface.position);
+
+                vec3 color = indirect_light(surface, view_direction);
+                for (int i = 0; i < light_list.lights.length(); i++) {
+                    color += direct_light(light_list.lights[i], surface, view_direction);
+                }
+
+                f_color = vec4(debug_cascade_tint(color, surface.position), 1.0);
+            }
+            ",
+        types_meta: {
+            use bytemuck::{Pod, Zeroable};
+
+            #[derive(Clone, Copy, Zeroable, Pod)]
+        }
+    }
+}
+
+// Clustered path, see `clusters::LightClusters`. `light_culling` bins the lights into clusters
+// of the view frustum, then nodes are drawn with `vs` and `clustered_fs`, which only goes
+// through the lights of the cluster each pixel falls in.
+
+pub mod light_culling {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        include: ["src/shaders"],
+        src: "
+            #version 450
+
+            #include <lights.glsl>
+            #include <clusters.glsl>
+
+            // Must match `clusters::WORK_GROUP_SIZE`.
+            layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+
+            // Every light of the scene, with at least one unused slot.
+            layout(set = 0, binding = 2) readonly buffer Light_List {
+                Light lights[];
+            } light_list;
+
+            layout(set = 0, binding = 16) writeonly buffer Cluster_List {
+                Cluster clusters[];
+            } cluster_list;
+
+            // View space point `depth` in front of the camera, on the ray through `ndc`.
+            vec3 view_point(vec2 ndc, float depth) {
+                vec4 point = cluster_grid.inverse_projection * vec4(ndc, 0.0, 1.0);
+                vec3 ray = point.xyz / point.w;
+                return ray * (depth / -ray.z);
+            }
+
+            // Whether `light` can reach a point of the box from `box_min` to `box_max`, in view
+            // space. Spot lights are treated as point lights.
+            bool reaches(Light light, vec3 box_min, vec3 box_max) {
+                float range = light.direction.w;
+                if (int(light.position.w) == DIRECTIONAL_LIGHT || range <= 0.0) {
+                    return true;
+                }
+
+                vec3 center = (cluster_grid.view * vec4(light.position.xyz, 1.0)).xyz;
+                vec3 offset = clamp(center, box_min, box_max) - center;
+                return dot(offset, offset) <= range * range;
+            }
+
+            void main() {
+                uint index = gl_GlobalInvocationID.x;
+                if (index >= cluster_grid.grid.w) {
+                    return;
+                }
+
+                uvec3 grid = cluster_grid.grid.xyz;
+                uvec3 cell = uvec3(
+                    index % grid.x,
+                    index / grid.x % grid.y,
+                    index / (grid.x * grid.y)
+                );
+                vec2 ndc_min = vec2(cell.xy) / vec2(grid.xy) * 2.0 - 1.0;
+                vec2 ndc_max = vec2(cell.xy + 1) / vec2(grid.xy) * 2.0 - 1.0;
+                float depths[2] = float[](slice_depth(cell.z), slice_depth(cell.z + 1));
+
+                // The box around the corners of the cluster, whose sides bulge as the tile
+                // widens with depth.
+                vec3 box_min = vec3(1e30);
+                vec3 box_max = vec3(-1e30);
+                for (int corner = 0; corner < 8; corner++) {
+                    vec2 ndc = vec2(
+                        (corner & 1) == 0 ? ndc_min.x : ndc_max.x,
+                        (corner & 2) == 0 ? ndc_min.y : ndc_max.y
+                    );
+                    vec3 point = view_point(ndc, depths[corner >> 2]);
+                    box_min = min(box_min, point);
+                    box_max = max(box_max, point);
+                }
+
+                // Lights past `MAX_CLUSTER_LIGHTS` are left out.
+                uint count = 0;
+                uint light_count = light_list.lights.length();
+                for (uint i = 0; i < light_count && count < MAX_CLUSTER_LIGHTS; i++) {
+                    if (reaches(light_list.lights[i], box_min, box_max)) {
+                        cluster_list.clusters[index].lights[count] = i;
+                        count++;
+                    }
+                }
+                cluster_list.clusters[index].count = count;
+            }
+            ",
+        types_meta: {
+            use bytemuck::{Pod, Zeroable};
+
+            #[derive(Clone, Copy, Zeroable, Pod)]
+        }
+    }
+}
+
+pub mod clustered_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        include: ["src/shaders"],
+        src: "
+            #version 450
+
+            #include <lighting.glsl>
+            #include <material.glsl>
+            #include <clusters.glsl>
+
+            layout(location = 0) out vec4 f_color;
+
+            // Every light of the scene, with at least one unused slot.
+            layout(set = 0, binding = 2) readonly buffer Light_List {
+                Light lights[];
+            } light_list;
+
+            // Written by `light_culling`.
+            layout(set = 0, binding = 16) readonly buffer Cluster_List {
+                Cluster clusters[];
+            } cluster_list;
+
+            void main() {
+                float alpha = surface_alpha();
+                Surface surface = material_surface();
+                vec3 view_direction = normalize(camera.position - frag_pos);
+
+                vec3 color = indirect_light(surface, view_direction);
+                uint cluster = cluster_index(gl_FragCoord.xy, view_depth(frag_pos));
+                uint count = cluster_list.clusters[cluster].count;
+                for (uint i = 0; i < count; i++) {
+                    uint light = cluster_list.clusters[cluster].lights[i];
+                    color += direct_light(light_list.lights[light], surface, view_direction);
+                }
+
+                f_color = vec4(debug_cascade_tint(color, frag_pos), alpha);
+            }
+            ",
+    }
+}
+
+// Compute shaders that precompute image-based lighting, see `environment::Environment`. Cube
+// maps are written through 2D array views, one layer per face in the order +x, -x, +y, -y, +z,
+// -z.
+
+pub mod equirect_to_cube {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: "
+            #version 450
+
+            layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+            layout(set = 0, binding = 0) uniform sampler2D equirect;
+            layout(set = 0, binding = 1, rgba16f) uniform writeonly image2DArray cube;
+
+            const float PI = 3.14159265359;
+
+            // Direction through the center of texel `id.xy` of face `id.z`.
+            vec3 cube_direction(ivec3 id, int size) {
+                vec2 uv = 2.0 * (vec2(id.xy) + 0.5) / float(size) - 1.0;
+                switch (id.z) {
+                    case 0: return normalize(vec3(1.0, -uv.y, -uv.x));
+                    case 1: return normalize(vec3(-1.0, -uv.y, uv.x));
+                    case 2: return normalize(vec3(uv.x, 1.0, uv.y));
+                    case 3: return normalize(vec3(uv.x, -1.0, -uv.y));
+                    case 4: return normalize(vec3(uv.x, -uv.y, 1.0));
+                    default: return normalize(vec3(-uv.x, -uv.y, -1.0));
+                }
+            }
+
+            void main() {
+                int size = imageSize(cube).x;
+                ivec3 id = ivec3(gl_GlobalInvocationID);
+                if (id.x >= size || id.y >= size) {
+                    return;
+                }
+
+                // The top row of the image looks straight up.
+                vec3 direction = cube_direction(id, size);
+                vec2 uv = vec2(
+                    atan(direction.z, direction.x) / (2.0 * PI) + 0.5,
+                    acos(clamp(direction.y, -1.0, 1.0)) / PI
+                );
+                imageStore(cube, id, vec4(texture(equirect, uv).rgb, 1.0));
+            }
+            ",
+    }
+}
+
+pub mod irradiance {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: "
+            #version 450
+
+            layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+            layout(set = 0, binding = 0) uniform samplerCube environment;
+            layout(set = 0, binding = 1, rgba16f) uniform writeonly image2DArray irradiance;
+
+            const float PI = 3.14159265359;
+            // Angle between samples of the hemisphere, in radians.
+            const float SAMPLE_DELTA = 0.025;
+
+            vec3 cube_direction(ivec3 id, int size) {
+                vec2 uv = 2.0 * (vec2(id.xy) + 0.5) / float(size) - 1.0;
+                switch (id.z) {
+                    case 0: return normalize(vec3(1.0, -uv.y, -uv.x));
+                    case 1: return normalize(vec3(-1.0, -uv.y, uv.x));
+                    case 2: return normalize(vec3(uv.x, 1.0, uv.y));
+                    case 3: return normalize(vec3(uv.x, -1.0, -uv.y));
+                    case 4: return normalize(vec3(uv.x, -uv.y, 1.0));
+                    default: return normalize(vec3(-uv.x, -uv.y, -1.0));
+                }
+            }
+
+            // Cosine-weighted average of the light arriving from the hemisphere around each
+            // direction, i.e. the diffuse lighting of a surface facing that way.
+            void main() {
+                int size = imageSize(irradiance).x;
+                ivec3 id = ivec3(gl_GlobalInvocationID);
+                if (id.x >= size || id.y >= size) {
+                    return;
+                }
+
+                vec3 normal = cube_direction(id, size);
+                vec3 up = abs(normal.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(0.0, 0.0, 1.0);
+                vec3 right = normalize(cross(up, normal));
+                up = cross(normal, right);
+
+                vec3 sum = vec3(0.0);
+                float count = 0.0;
+                for (float phi = 0.0; phi < 2.0 * PI; phi += SAMPLE_DELTA) {
+                    for (float theta = 0.0; theta < 0.5 * PI; theta += SAMPLE_DELTA) {
+                        vec3 local = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+                        vec3 direction = local.x * right + local.y * up + local.z * normal;
+                        sum += textureLod(environment, direction, 0.0).rgb * cos(theta) * sin(theta);
+                        count += 1.0;
+                    }
+                }
+
+                imageStore(irradiance, id, vec4(PI * sum / count, 1.0));
+            }
+            ",
+    }
+}
+
+pub mod prefilter {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: "
+            #version 450
+
+            layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+            layout(set = 0, binding = 0) uniform samplerCube environment;
+            // One mip level of the prefiltered map.
+            layout(set = 0, binding = 1, rgba16f) uniform writeonly image2DArray prefiltered;
+
+            layout(push_constant) uniform Prefilter_Data {
+                float roughness;
+            } params;
+
+            const float PI = 3.14159265359;
+            const uint SAMPLE_COUNT = 1024u;
+
+            vec3 cube_direction(ivec3 id, int size) {
+                vec2 uv = 2.0 * (vec2(id.xy) + 0.5) / float(size) - 1.0;
+                switch (id.z) {
+                    case 0: return normalize(vec3(1.0, -uv.y, -uv.x));
+                    case 1: return normalize(vec3(-1.0, -uv.y, uv.x));
+                    case 2: return normalize(vec3(uv.x, 1.0, uv.y));
+                    case 3: return normalize(vec3(uv.x, -1.0, -uv.y));
+                    case 4: return normalize(vec3(uv.x, -uv.y, 1.0));
+                    default: return normalize(vec3(-uv.x, -uv.y, -1.0));
+                }
+            }
+
+            vec2 hammersley(uint i, uint n) {
+                uint bits = i;
+                bits = (bits << 16u) | (bits >> 16u);
+                bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+                bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+                bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+                bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+                return vec2(float(i) / float(n), float(bits) * 2.3283064365386963e-10);
+            }
+
+            // Halfway vector distributed like the GGX lobe around `normal`.
+            vec3 importance_sample_ggx(vec2 xi, vec3 normal, float roughness) {
+                float a = roughness * roughness;
+                float phi = 2.0 * PI * xi.x;
+                float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+                float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+
+                vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+                vec3 tangent = normalize(cross(up, normal));
+                vec3 bitangent = cross(normal, tangent);
+                return normalize(
+                    tangent * cos(phi) * sin_theta + bitangent * sin(phi) * sin_theta
+                        + normal * cos_theta
+                );
+            }
+
+            float distribution_ggx(float n_dot_h, float roughness) {
+                float a2 = roughness * roughness * roughness * roughness;
+                float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+                return a2 / (PI * d * d);
+            }
+
+            // Convolves the environment with the GGX lobe for `params.roughness`, assuming the
+            // view direction equals the reflection direction. Each sample reads the mip level of
+            // the environment whose texels cover about the solid angle the sample stands for,
+            // which is filtered importance sampling: unlikely directions are sampled sparsely,
+            // and reading them sharp would show bright spots as fireflies.
+            void main() {
+                int size = imageSize(prefiltered).x;
+                ivec3 id = ivec3(gl_GlobalInvocationID);
+                if (id.x >= size || id.y >= size) {
+                    return;
+                }
+
+                float source_size = float(textureSize(environment, 0).x);
+                float texel_solid_angle = 4.0 * PI / (6.0 * source_size * source_size);
+
+                vec3 normal = cube_direction(id, size);
+                vec3 sum = vec3(0.0);
+                float weight = 0.0;
+                for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+                    vec3 halfway = importance_sample_ggx(
+                        hammersley(i, SAMPLE_COUNT), normal, params.roughness
+                    );
+                    vec3 light_direction = normalize(2.0 * dot(normal, halfway) * halfway - normal);
+                    float n_dot_l = dot(normal, light_direction);
+                    if (n_dot_l > 0.0) {
+                        // With the view along the normal, the density of the light direction is
+                        // D * n_dot_h / (4 * v_dot_h) = D / 4.
+                        float n_dot_h = max(dot(normal, halfway), 0.0);
+                        float pdf = distribution_ggx(n_dot_h, params.roughness) / 4.0;
+                        float sample_solid_angle = 1.0 / (float(SAMPLE_COUNT) * pdf + 0.0001);
+                        float lod = params.roughness == 0.0
+                            ? 0.0
+                            : max(0.5 * log2(sample_solid_angle / texel_solid_angle), 0.0);
+                        sum += textureLod(environment, light_direction, lod).rgb * n_dot_l;
+                        weight += n_dot_l;
+                    }
+                }
+
+                imageStore(prefiltered, id, vec4(sum / max(weight, 0.0001), 1.0));
+            }
+            ",
+        types_meta: {
+            use bytemuck::{Pod, Zeroable};
+
+            #[derive(Clone, Copy, Zeroable, Pod)]
+        }
+    }
+}
+
+pub mod brdf_lut {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: "
+            #version 450
+
+            layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+            layout(set = 0, binding = 0, rgba16f) uniform writeonly image2D lut;
+
+            const float PI = 3.14159265359;
+            const uint SAMPLE_COUNT = 1024u;
+
+            vec2 hammersley(uint i, uint n) {
+                uint bits = i;
+                bits = (bits << 16u) | (bits >> 16u);
+                bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+                bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+                bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+                bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+                return vec2(float(i) / float(n), float(bits) * 2.3283064365386963e-10);
+            }
+
+            // GGX halfway vector around +z.
+            vec3 importance_sample_ggx(vec2 xi, float roughness) {
+                float a = roughness * roughness;
+                float phi = 2.0 * PI * xi.x;
+                float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+                float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+                return vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+            }
+
+            // Smith's method with the remapping of k used for image-based lighting.
+            float geometry_smith(float n_dot_v, float n_dot_l, float roughness) {
+                float k = roughness * roughness / 2.0;
+                float g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+                float g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+                return g_v * g_l;
+            }
+
+            // Scale (r) and bias (g) applied to F0 by the split-sum approximation, indexed by
+            // the cosine between normal and view direction (x) and the roughness (y).
+            void main() {
+                ivec2 size = imageSize(lut);
+                ivec2 id = ivec2(gl_GlobalInvocationID.xy);
+                if (id.x >= size.x || id.y >= size.y) {
+                    return;
+                }
+
+                vec2 uv = (vec2(id) + 0.5) / vec2(size);
+                float n_dot_v = uv.x;
+                float roughness = uv.y;
+                vec3 view_direction = vec3(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+
+                float scale = 0.0;
+                float bias = 0.0;
+                for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+                    vec3 halfway = importance_sample_ggx(hammersley(i, SAMPLE_COUNT), roughness);
+                    vec3 light_direction =
+                        normalize(2.0 * dot(view_direction, halfway) * halfway - view_direction);
+
+                    float n_dot_l = max(light_direction.z, 0.0);
+                    if (n_dot_l > 0.0) {
+                        float n_dot_h = max(halfway.z, 0.0);
+                        float v_dot_h = max(dot(view_direction, halfway), 0.0);
+                        float g = geometry_smith(n_dot_v, n_dot_l, roughness);
+                        float visibility = g * v_dot_h / max(n_dot_h * n_dot_v, 0.0001);
+                        float fresnel = pow(1.0 - v_dot_h, 5.0);
+                        scale += (1.0 - fresnel) * visibility;
+                        bias += fresnel * visibility;
+                    }
+                }
+
+                imageStore(lut, id, vec4(scale, bias, 0.0, 0.0) / float(SAMPLE_COUNT));
+            }
+            ",
+    }
+}
+
+// Depth-only pass rendering shadow maps from the point of view of a light.
+
+pub mod shadow_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+            #version 450
+
+            layout(location = 0) in vec3 position;
+
+            layout(push_constant) uniform Shadow_Caster {
+                // Light space times world transform of the node.
+                mat4 transform;
+            } caster;
+
+            void main() {
+                gl_Position = caster.transform * vec4(position, 1.0);
+            }
+            ",
+        types_meta: {
+            use bytemuck::{Pod, Zeroable};
+
+            #[derive(Clone, Copy, Zeroable, Pod)]
+        }
+    }
+}
+
+pub mod shadow_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+            #version 450
+
+            void main() {}
+            ",
+    }
+}
+
+// Depth prepass of nodes with masked materials, see `ssao::DepthPrepass`, drawn with `vs`.
+// Leaves out the same fragments as the main pass.
+
+pub mod masked_depth_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        include: ["src/shaders"],
+        src: "
+            #version 450
+
+            #include <lighting.glsl>
+            #include <material.glsl>
+
+            void main() {
+                surface_alpha();
+            }
+            ",
+    }
+}
+
+// Screen-space ambient occlusion, see `ssao::Ssao`. Both passes are drawn with `post_vs`.
+
+pub mod ssao_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+            #version 450
+
+            #define MAX_SSAO_SAMPLES 32
+
+            layout(location = 0) in vec2 in_uv;
+
+            // Occlusion in red, linear depth in green for the blur.
+            layout(location = 0) out vec4 f_occlusion;
+
+            layout(set = 0, binding = 0) uniform sampler2D depth_buffer;
+            // Tiled rotations around the normal, encoded as 0.5 + 0.5 * (cos, sin).
+            layout(set = 0, binding = 1) uniform sampler2D noise;
+
+            layout(set = 0, binding = 2) uniform Ssao_Data {
+                mat4 projection;
+                mat4 inverse_projection;
+                // Offsets in a hemisphere around +z, scaled by the radius.
+                vec4 kernel[MAX_SSAO_SAMPLES];
+                float radius;
+                float bias;
+                float intensity;
+                int sample_count;
+            } ssao;
+
+            // View-space position of the surface seen at `uv`.
+            vec3 view_position(vec2 uv) {
+                float depth = texture(depth_buffer, uv).r;
+                vec4 position = ssao.inverse_projection * vec4(uv * 2.0 - 1.0, depth, 1.0);
+                return position.xyz / position.w;
+            }
+
+            // The normal of the surface at `position`, from the neighbors on its side of depth
+            // discontinuities, so edges don't get the normal of the background.
+            vec3 view_normal(vec3 position) {
+                vec2 texel = 1.0 / vec2(textureSize(depth_buffer, 0));
+                vec3 right = view_position(in_uv + vec2(texel.x, 0.0)) - position;
+                vec3 left = position - view_position(in_uv - vec2(texel.x, 0.0));
+                vec3 down = view_position(in_uv + vec2(0.0, texel.y)) - position;
+                vec3 up = position - view_position(in_uv - vec2(0.0, texel.y));
+
+                vec3 dx = abs(right.z) < abs(left.z) ? right : left;
+                vec3 dy = abs(down.z) < abs(up.z) ? down : up;
+                vec3 normal = normalize(cross(dx, dy));
+                return dot(normal, position) > 0.0 ? -normal : normal;
+            }
+
+            void main() {
+                // Nothing was drawn here.
+                if (texture(depth_buffer, in_uv).r >= 1.0) {
+                    f_occlusion = vec4(1.0, 65504.0, 0.0, 0.0);
+                    return;
+                }
+
+                vec3 position = view_position(in_uv);
+                vec3 normal = view_normal(position);
+
+                vec2 noise_scale = vec2(textureSize(depth_buffer, 0)) / vec2(textureSize(noise, 0));
+                vec3 random = vec3(texture(noise, in_uv * noise_scale).xy * 2.0 - 1.0, 0.0);
+                vec3 tangent = normalize(random - normal * dot(random, normal));
+                vec3 bitangent = cross(normal, tangent);
+                mat3 tbn = mat3(tangent, bitangent, normal);
+
+                float occlusion = 0.0;
+                for (int i = 0; i < ssao.sample_count; i++) {
+                    vec3 sample_position = position + tbn * ssao.kernel[i].xyz * ssao.radius;
+
+                    vec4 clip = ssao.projection * vec4(sample_position, 1.0);
+                    vec2 uv = clip.xy / clip.w * 0.5 + 0.5;
+                    float scene_depth = view_position(uv).z;
+
+                    // Geometry far in front of the pixel, like an object in front of a wall,
+                    // doesn't occlude it.
+                    float range = smoothstep(0.0, 1.0, ssao.radius / abs(position.z - scene_depth));
+                    if (scene_depth >= sample_position.z + ssao.bias) {
+                        occlusion += range;
+                    }
+                }
+
+                float visibility = 1.0 - occlusion / float(max(ssao.sample_count, 1));
+                f_occlusion = vec4(pow(visibility, ssao.intensity), -position.z, 0.0, 0.0);
+            }
+            ",
+        types_meta: {
+            use bytemuck::{Pod, Zeroable};
+
+            #[derive(Clone, Copy, Zeroable, Pod)]
+        }
+    }
+}
+
+pub mod ssao_blur_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+            #version 450
+
+            layout(location = 0) in vec2 in_uv;
+
+            layout(location = 0) out vec4 f_occlusion;
+
+            layout(set = 0, binding = 0) uniform sampler2D source;
+
+            layout(push_constant) uniform Ssao_Blur {
+                // One texel along the blurred axis, in texels.
+                vec2 direction;
+                float sharpness;
+            } blur;
+
+            // Gaussian weights of the center and each distance, in texels.
+            const float WEIGHTS[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+            // Bilateral blur along one axis: neighbors at a different depth get less weight, so
+            // the occlusion of an object doesn't bleed onto the background behind it.
+            void main() {
+                vec2 texel = blur.direction / vec2(textureSize(source, 0));
+                vec2 center = texture(source, in_uv).rg;
+
+                float sum = center.r * WEIGHTS[0];
+                float total = WEIGHTS[0];
+                for (int i = 1; i < 5; i++) {
+                    for (int side = -1; side <= 1; side += 2) {
+                        vec2 neighbor = texture(source, in_uv + texel * float(i * side)).rg;
+                        float difference = abs(neighbor.g - center.g) / max(center.g, 1e-4);
+                        float weight = WEIGHTS[i] * exp(-difference * blur.sharpness);
+                        sum += neighbor.r * weight;
+                        total += weight;
+                    }
+                }
+
+                f_occlusion = vec4(sum / total, center.g, 0.0, 0.0);
+            }
+            ",
+        types_meta: {
+            use bytemuck::{Pod, Zeroable};
+
+            #[derive(Clone, Copy, Zeroable, Pod)]
+        }
+    }
+}
+
+// Full-screen passes of the post-processing chain, see `post::PostEffect`. They all read the
+// previous result through `source` and write a new image.
+
+pub mod post_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+            #version 450
+
+            layout(location = 0) out vec2 out_uv;
+
+            // A single triangle covering the screen, with uv 0 to 1 across the visible part.
+            void main() {
+                out_uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+                gl_Position = vec4(out_uv * 2.0 - 1.0, 0.0, 1.0);
+            }
+            ",
+    }
+}
+
+pub mod bloom_prefilter_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+            #version 450
+
+            layout(location = 0) in vec2 in_uv;
+
+            layout(location = 0) out vec4 f_color;
+
+            layout(set = 0, binding = 0) uniform sampler2D source;
+
+            layout(push_constant) uniform Bloom_Prefilter {
+                float threshold;
+                float knee;
+            } bloom;
+
+            // Keeps the part of each pixel brighter than the threshold, fading in over the knee
+            // below it rather than cutting off sharply.
+            vec3 prefilter(vec3 color) {
+                float brightness = max(color.r, max(color.g, color.b));
+                float soft = clamp(brightness - bloom.threshold + bloom.knee, 0.0, 2.0 * bloom.knee);
+                soft = soft * soft / (4.0 * bloom.knee + 1e-5);
+                float contribution = max(soft, brightness - bloom.threshold);
+                return color * contribution / max(brightness, 1e-5);
+            }
+
+            // Renders at half the size of the source: each of the bilinear taps averages 2x2
+            // texels, together covering 4x4.
+            void main() {
+                vec2 texel = 1.0 / textureSize(source, 0);
+                vec3 color = texture(source, in_uv + texel * vec2(-1.0, -1.0)).rgb;
+                color += texture(source, in_uv + texel * vec2(1.0, -1.0)).rgb;
+                color += texture(source, in_uv + texel * vec2(-1.0, 1.0)).rgb;
+                color += texture(source, in_uv + texel * vec2(1.0, 1.0)).rgb;
+                f_color = vec4(prefilter(color * 0.25), 1.0);
+            }
+            ",
+        types_meta: {
+            use bytemuck::{Pod, Zeroable};
+
+            #[derive(Clone, Copy, Zeroable, Pod)]
+        }
+    }
+}
+
+pub mod bloom_downsample_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+            #version 450
+
+            layout(location = 0) in vec2 in_uv;
+
+            layout(location = 0) out vec4 f_color;
+
+            layout(set = 0, binding = 0) uniform sampler2D source;
+
+            // Same 4x4 box as the prefilter, one level further down the chain.
+            void main() {
+                vec2 texel = 1.0 / textureSize(source, 0);
+                vec3 color = texture(source, in_uv + texel * vec2(-1.0, -1.0)).rgb;
+                color += texture(source, in_uv + texel * vec2(1.0, -1.0)).rgb;
+                color += texture(source, in_uv + texel * vec2(-1.0, 1.0)).rgb;
+                color += texture(source, in_uv + texel * vec2(1.0, 1.0)).rgb;
+                f_color = vec4(color * 0.25, 1.0);
+            }
+            ",
+    }
+}
+
+pub mod bloom_upsample_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+            #version 450
+
+            layout(location = 0) in vec2 in_uv;
+
+            layout(location = 0) out vec4 f_color;
+
+            layout(set = 0, binding = 0) uniform sampler2D source;
+
+            // 3x3 tent filter over the smaller level, added onto the larger one by blending.
+            void main() {
+                vec2 texel = 1.0 / textureSize(source, 0);
+                vec3 color = texture(source, in_uv).rgb * 4.0;
+                color += texture(source, in_uv + texel * vec2(-1.0, 0.0)).rgb * 2.0;
+                color += texture(source, in_uv + texel * vec2(1.0, 0.0)).rgb * 2.0;
+                color += texture(source, in_uv + texel * vec2(0.0, -1.0)).rgb * 2.0;
+                color += texture(source, in_uv + texel * vec2(0.0, 1.0)).rgb * 2.0;
+                color += texture(source, in_uv + texel * vec2(-1.0, -1.0)).rgb;
+                color += texture(source, in_uv + texel * vec2(1.0, -1.0)).rgb;
+                color += texture(source, in_uv + texel * vec2(-1.0, 1.0)).rgb;
+                color += texture(source, in_uv + texel * vec2(1.0, 1.0)).rgb;
+                f_color = vec4(color / 16.0, 1.0);
+            }
+            ",
+    }
+}
+
+pub mod bloom_composite_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+            #version 450
+
+            layout(location = 0) in vec2 in_uv;
+
+            layout(location = 0) out vec4 f_color;
+
+            layout(set = 0, binding = 0) uniform sampler2D source;
+            // The largest level of the chain, holding the sum of all of them.
+            layout(set = 0, binding = 1) uniform sampler2D bloom_image;
+
+            layout(push_constant) uniform Bloom_Composite {
+                float intensity;
+            } bloom;
+
+            void main() {
+                vec3 color = texture(source, in_uv).rgb;
+                color += texture(bloom_image, in_uv).rgb * bloom.intensity;
+                f_color = vec4(color, 1.0);
+            }
+            ",
+        types_meta: {
+            use bytemuck::{Pod, Zeroable};
+
+            #[derive(Clone, Copy, Zeroable, Pod)]
+        }
+    }
+}
+
+pub mod tone_map_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+            #version 450
+
+            #define REINHARD 0
+            #define ACES 1
+
+            layout(location = 0) in vec2 in_uv;
+
+            layout(location = 0) out vec4 f_color;
+
+            layout(set = 0, binding = 0) uniform sampler2D source;
+
+            layout(push_constant) uniform Tone_Mapping {
+                float exposure;
+                int operator;
+            } tone_mapping;
+
+            // Fit of the ACES filmic curve by Krzysztof Narkowicz.
+            vec3 tone_map_aces(vec3 color) {
+                const float a = 2.51;
+                const float b = 0.03;
+                const float c = 2.43;
+                const float d = 0.59;
+                const float e = 0.14;
+                return clamp((color * (a * color + b)) / (color * (c * color + d) + e), 0.0, 1.0);
+            }
+
+            void main() {
+                vec3 color = texture(source, in_uv).rgb * tone_mapping.exposure;
+                if (tone_mapping.operator == REINHARD) {
+                    color = color / (1.0 + color);
+                } else {
+                    color = tone_map_aces(color);
+                }
+                f_color = vec4(color, 1.0);
+            }
+            ",
+        types_meta: {
+            use bytemuck::{Pod, Zeroable};
+
+            #[derive(Clone, Copy, Zeroable, Pod)]
+        }
+    }
+}
+
+pub mod color_grading_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+            #version 450
+
+            layout(location = 0) in vec2 in_uv;
+
+            layout(location = 0) out vec4 f_color;
+
+            layout(set = 0, binding = 0) uniform sampler2D source;
+            // A size^3 lookup table laid out as size slices of size x size texels side by side,
+            // indexed and filled with sRGB encoded colors, see `post::neutral_lut`.
+            layout(set = 0, binding = 1) uniform sampler2D lut;
+
+            layout(push_constant) uniform Color_Grading {
+                float strength;
+            } grading;
+
+            vec3 encode_srgb(vec3 color) {
+                vec3 low = color * 12.92;
+                vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+                return mix(high, low, lessThanEqual(color, vec3(0.0031308)));
+            }
+
+            vec3 decode_srgb(vec3 color) {
+                vec3 low = color / 12.92;
+                vec3 high = pow((color + 0.055) / 1.055, vec3(2.4));
+                return mix(high, low, lessThanEqual(color, vec3(0.04045)));
+            }
+
+            // Blue picks the slice; red and green are filtered within the two closest slices,
+            // which are then blended.
+            vec3 look_up(vec3 color) {
+                float size = float(textureSize(lut, 0).y);
+                float slice = color.b * (size - 1.0);
+                float lower = floor(slice);
+                float upper = min(lower + 1.0, size - 1.0);
+                vec2 texel = (color.rg * (size - 1.0) + 0.5) / vec2(size * size, size);
+                vec3 a = textureLod(lut, texel + vec2(lower / size, 0.0), 0.0).rgb;
+                vec3 b = textureLod(lut, texel + vec2(upper / size, 0.0), 0.0).rgb;
+                return mix(a, b, slice - lower);
+            }
+
+            void main() {
+                vec3 color = clamp(texture(source, in_uv).rgb, 0.0, 1.0);
+                vec3 graded = decode_srgb(look_up(encode_srgb(color)));
+                f_color = vec4(mix(color, graded, grading.strength), 1.0);
+            }
+            ",
+        types_meta: {
+            use bytemuck::{Pod, Zeroable};
+
+            #[derive(Clone, Copy, Zeroable, Pod)]
+        }
+    }
+}
+
+pub mod vignette_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+            #version 450
+
+            layout(location = 0) in vec2 in_uv;
+
+            layout(location = 0) out vec4 f_color;
+
+            layout(set = 0, binding = 0) uniform sampler2D source;
+
+            layout(push_constant) uniform Vignette {
+                float intensity;
+                float radius;
+                float softness;
+            } vignette;
+
+            void main() {
+                // 0 in the center, 1 in the corners.
+                float distance = length(in_uv - 0.5) * sqrt(2.0);
+                float darkening = smoothstep(
+                    vignette.radius - vignette.softness,
+                    vignette.radius,
+                    distance
+                );
+                vec3 color = texture(source, in_uv).rgb * (1.0 - vignette.intensity * darkening);
+                f_color = vec4(color, 1.0);
+            }
+            ",
+        types_meta: {
+            use bytemuck::{Pod, Zeroable};
+
+            #[derive(Clone, Copy, Zeroable, Pod)]
+        }
+    }
+}
+
+pub mod fxaa_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+            #version 450
+
+            layout(location = 0) in vec2 in_uv;
+
+            layout(location = 0) out vec4 f_color;
+
+            layout(set = 0, binding = 0) uniform sampler2D source;
+
+            layout(push_constant) uniform Fxaa {
+                float span_max;
+            } fxaa;
+
+            const float REDUCE_MIN = 1.0 / 128.0;
+            const float REDUCE_MUL = 1.0 / 8.0;
+
+            // FXAA measures contrast in perceptual terms, but the source is still linear, ahead
+            // of `encode_srgb`; the square root roughly gamma-encodes the luma.
+            float luma(vec3 color) {
+                return sqrt(dot(clamp(color, 0.0, 1.0), vec3(0.299, 0.587, 0.114)));
+            }
+
+            // The reduced FXAA of Timothy Lottes: estimates the direction of the edge through
+            // the pixel from the luma of its corners, then blurs along it.
+            void main() {
+                vec2 texel = 1.0 / textureSize(source, 0);
+                float luma_nw = luma(texture(source, in_uv + texel * vec2(-1.0, -1.0)).rgb);
+                float luma_ne = luma(texture(source, in_uv + texel * vec2(1.0, -1.0)).rgb);
+                float luma_sw = luma(texture(source, in_uv + texel * vec2(-1.0, 1.0)).rgb);
+                float luma_se = luma(texture(source, in_uv + texel * vec2(1.0, 1.0)).rgb);
+                vec3 center = texture(source, in_uv).rgb;
+                float luma_m = luma(center);
+                float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+                float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+                vec2 direction = vec2(
+                    (luma_sw + luma_se) - (luma_nw + luma_ne),
+                    (luma_nw + luma_sw) - (luma_ne + luma_se)
+                );
+                float reduce = max(
+                    (luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * REDUCE_MUL,
+                    REDUCE_MIN
+                );
+                float scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+                direction = clamp(direction * scale, -fxaa.span_max, fxaa.span_max) * texel;
+
+                vec3 near = 0.5 * (
+                    texture(source, in_uv + direction * (1.0 / 3.0 - 0.5)).rgb +
+                    texture(source, in_uv + direction * (2.0 / 3.0 - 0.5)).rgb
+                );
+                vec3 far = near * 0.5 + 0.25 * (
+                    texture(source, in_uv - direction * 0.5).rgb +
+                    texture(source, in_uv + direction * 0.5).rgb
+                );
+                // Sampling too far along the edge picks up unrelated pixels.
+                float luma_far = luma(far);
+                vec3 color = luma_far < luma_min || luma_far > luma_max ? near : far;
+                f_color = vec4(color, 1.0);
+            }
+            ",
+        types_meta: {
+            use bytemuck::{Pod, Zeroable};
+
+            #[derive(Clone, Copy, Zeroable, Pod)]
+        }
+    }
+}
+
+pub mod output_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+            #version 450
+
+            layout(location = 0) in vec2 in_uv;
+
+            layout(location = 0) out vec4 f_color;
+
+            layout(set = 0, binding = 0) uniform sampler2D source;
+
+            layout(push_constant) uniform Output {
+                // Set when the output format doesn't encode sRGB on its own.
+                int encode_srgb;
+            } output_format;
+
+            vec3 encode_srgb(vec3 color) {
+                vec3 low = color * 12.92;
+                vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+                return mix(high, low, lessThanEqual(color, vec3(0.0031308)));
+            }
+
+            // Copies the end of the chain into the swapchain or offscreen image.
+            void main() {
+                vec3 color = clamp(texture(source, in_uv).rgb, 0.0, 1.0);
+                if (output_format.encode_srgb != 0) {
+                    color = encode_srgb(color);
+                }
+                f_color = vec4(color, 1.0);
+            }
+            ",
+        types_meta: {
+            use bytemuck::{Pod, Zeroable};
+
+            #[derive(Clone, Copy, Zeroable, Pod)]
+        }
+    }
+}
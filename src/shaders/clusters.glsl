@@ -0,0 +1,43 @@
+// Clusters of the view frustum the lights are binned into by `light_culling`, see
+// `clusters::LightClusters`. The frustum is cut into a grid of tiles on screen and into slices
+// in depth, each deeper than the last so clusters stay about as deep as they are wide.
+
+// Must match `clusters::MAX_CLUSTER_LIGHTS`.
+#define MAX_CLUSTER_LIGHTS 128
+
+layout(set = 0, binding = 15) uniform Cluster_Data {
+    mat4 view;
+    // From clip space back to view space.
+    mat4 inverse_projection;
+    // x, y: tiles across and down the screen, z: depth slices, w: number of clusters
+    uvec4 grid;
+    // Of the target, in pixels.
+    vec2 screen_size;
+    // Camera planes the slices span.
+    float near;
+    float far;
+} cluster_grid;
+
+struct Cluster {
+    uint count;
+    // Indices into the list of lights.
+    uint lights[MAX_CLUSTER_LIGHTS];
+};
+
+// View depth at which depth slice `slice` starts.
+float slice_depth(uint slice) {
+    float ratio = cluster_grid.far / cluster_grid.near;
+    return cluster_grid.near * pow(ratio, float(slice) / float(cluster_grid.grid.z));
+}
+
+// Index of the cluster holding the pixel at `frag_coord`, `depth` in front of the camera.
+uint cluster_index(vec2 frag_coord, float depth) {
+    uvec3 grid = cluster_grid.grid.xyz;
+    uvec2 tile = uvec2(frag_coord / cluster_grid.screen_size * vec2(grid.xy));
+    tile = min(tile, grid.xy - 1);
+
+    float ratio = cluster_grid.far / cluster_grid.near;
+    float slice = log(max(depth, cluster_grid.near) / cluster_grid.near) / log(ratio);
+    uint z = min(uint(slice * float(grid.z)), grid.z - 1);
+    return (z * grid.y + tile.y) * grid.x + tile.x;
+}
@@ -0,0 +1,404 @@
+// Lighting shared by the fragment shaders of every render path: the ambient and environment
+// light, shadows, and the reflection of each light by a `Surface`. Included after `#version`;
+// the including shader declares the list of lights and loops over it with `direct_light`.
+
+// Must match `shadow::MAX_SHADOW_MAPS`, `shadow::MAX_CASCADES` and `shadow::MAX_POINT_SHADOWS`.
+#define MAX_SHADOW_MAPS 8
+#define MAX_CASCADES 4
+#define MAX_POINT_SHADOWS 2
+
+#include <lights.glsl>
+
+// Must match the values packed by `Renderer::draw_writes`.
+#define BLINN_PHONG 0
+#define PBR 1
+
+layout(set = 0, binding = 1) uniform Ambient_Data {
+    vec3 color;
+    float intensity;
+} ambient;
+
+layout(set = 0, binding = 4) uniform Camera_Data {
+    vec3 position;
+} camera;
+
+// Precomputed by `environment::Environment`. Only sampled when `enabled` is set,
+// otherwise the ambient light is used instead.
+layout(set = 0, binding = 5) uniform samplerCube irradiance_map;
+layout(set = 0, binding = 6) uniform samplerCube prefiltered_map;
+layout(set = 0, binding = 7) uniform sampler2D brdf_lut;
+
+layout(set = 0, binding = 8) uniform Environment_Data {
+    float intensity;
+    // Mip level of `prefiltered_map` for a roughness of 1.
+    float max_lod;
+    int enabled;
+} environment;
+
+layout(set = 0, binding = 9) uniform Shadow_Data {
+    mat4 light_space[MAX_SHADOW_MAPS];
+    // View depth at which each cascade of a directional light ends.
+    vec4 cascade_splits;
+    // x: near and y: far plane of each point light cube map
+    vec4 point_planes[MAX_POINT_SHADOWS];
+    // xyz: direction the camera looks in
+    vec4 camera_forward;
+    float depth_bias;
+    float normal_bias;
+    int pcf_radius;
+    int cascade_count;
+    // Fraction of each cascade fading into the next one.
+    float cascade_blend;
+    int debug_cascades;
+} shadows;
+
+layout(set = 0, binding = 10) uniform sampler2DArrayShadow shadow_maps;
+layout(set = 0, binding = 11)
+    uniform samplerCubeShadow point_shadow_maps[MAX_POINT_SHADOWS];
+
+// Screen-space ambient occlusion in red, 1 where nothing is occluded or it is off.
+layout(set = 0, binding = 14) uniform sampler2D ambient_occlusion;
+
+const float PI = 3.14159265359;
+
+// What a pixel shows: a point of a node's surface, with its material.
+struct Surface {
+    // In world space.
+    vec3 position;
+    vec3 normal;
+    vec3 albedo;
+    vec3 specular_color;
+    float shininess;
+    float metallic;
+    float roughness;
+    // Ambient occlusion of the material.
+    float occlusion;
+    vec3 emissive;
+    int shading;
+};
+
+// Fraction of the light reaching `position` according to shadow map `layer`, filtered
+// over a square of shadow map texels.
+float sample_shadow(int layer, vec3 position) {
+    vec4 clip = shadows.light_space[layer] * vec4(position, 1.0);
+    vec3 ndc = clip.xyz / clip.w;
+    if (clip.w <= 0.0 || any(greaterThan(abs(ndc.xy), vec2(1.0))) || ndc.z > 1.0) {
+        return 1.0;
+    }
+
+    vec2 uv = ndc.xy * 0.5 + 0.5;
+    float depth = ndc.z - shadows.depth_bias;
+    vec2 texel = 1.0 / vec2(textureSize(shadow_maps, 0).xy);
+    float lit = 0.0;
+    for (int x = -shadows.pcf_radius; x <= shadows.pcf_radius; x++) {
+        for (int y = -shadows.pcf_radius; y <= shadows.pcf_radius; y++) {
+            vec2 offset = vec2(x, y) * texel;
+            lit += texture(shadow_maps, vec4(uv + offset, float(layer), depth));
+        }
+    }
+    float size = float(2 * shadows.pcf_radius + 1);
+    return lit / (size * size);
+}
+
+// Descriptor arrays can only be indexed with constants unless the device supports
+// dynamic indexing. Needs a branch per slot up to `MAX_POINT_SHADOWS`.
+float sample_point_map(int slot, vec4 coordinates) {
+    if (slot == 0) {
+        return texture(point_shadow_maps[0], coordinates);
+    }
+    return texture(point_shadow_maps[1], coordinates);
+}
+
+// Fraction of the light reaching `position` according to cube map `slot` of a point
+// light at `light_position`, filtered over a cube of directions.
+float sample_point_shadow(int slot, vec3 light_position, vec3 position) {
+    vec3 offset = position - light_position;
+    // Each face projects along its major axis, so that's the distance to compare.
+    vec3 axes = abs(offset);
+    float distance = max(axes.x, max(axes.y, axes.z));
+    float near = shadows.point_planes[slot].x;
+    float far = shadows.point_planes[slot].y;
+    if (distance >= far) {
+        return 1.0;
+    }
+    float depth = far * (distance - near) / (distance * (far - near));
+    depth -= shadows.depth_bias;
+
+    float texel = 2.0 * distance / float(textureSize(point_shadow_maps[0], 0).x);
+    float lit = 0.0;
+    for (int x = -shadows.pcf_radius; x <= shadows.pcf_radius; x++) {
+        for (int y = -shadows.pcf_radius; y <= shadows.pcf_radius; y++) {
+            for (int z = -shadows.pcf_radius; z <= shadows.pcf_radius; z++) {
+                vec3 direction = offset + vec3(x, y, z) * texel;
+                lit += sample_point_map(slot, vec4(direction, depth));
+            }
+        }
+    }
+    float size = float(2 * shadows.pcf_radius + 1);
+    return lit / (size * size * size);
+}
+
+// Distance of `position` from the camera along its view direction.
+float view_depth(vec3 position) {
+    return dot(position - camera.position, shadows.camera_forward.xyz);
+}
+
+// Index of the cascade covering `position`, or `cascade_count` past the last one.
+int cascade_index(vec3 position) {
+    float depth = view_depth(position);
+    int cascade = 0;
+    while (cascade < shadows.cascade_count && depth > shadows.cascade_splits[cascade]) {
+        cascade++;
+    }
+    return cascade;
+}
+
+// Fraction of the light from `light` reaching `surface`.
+float shadow_factor(Light light, Surface surface, vec3 light_direction) {
+    int layer = int(light.cone.z);
+    if (layer < 0) {
+        return 1.0;
+    }
+
+    // Moving the lookup along the normal keeps surfaces from shadowing themselves,
+    // which matters most at grazing angles.
+    float grazing = 1.0 - max(dot(surface.normal, light_direction), 0.0);
+    vec3 position = surface.position + surface.normal * shadows.normal_bias * grazing;
+    int kind = int(light.position.w);
+    if (kind == POINT_LIGHT) {
+        return sample_point_shadow(layer, light.position.xyz, position);
+    }
+    if (kind == SPOT_LIGHT) {
+        return sample_shadow(layer, position);
+    }
+
+    // Directional lights have one map per cascade and cast no shadows past the last.
+    int cascade = cascade_index(surface.position);
+    if (cascade >= shadows.cascade_count) {
+        return 1.0;
+    }
+    float lit = sample_shadow(layer + cascade, position);
+
+    // Close to its far end, a cascade fades into the next one so the change in
+    // resolution doesn't show as a seam.
+    float start = cascade > 0 ? shadows.cascade_splits[cascade - 1] : 0.0;
+    float end = shadows.cascade_splits[cascade];
+    float blend = max((end - start) * shadows.cascade_blend, 0.0001);
+    float fade = (end - view_depth(surface.position)) / blend;
+    if (fade < 1.0 && cascade + 1 < shadows.cascade_count) {
+        float next = sample_shadow(layer + cascade + 1, position);
+        lit = mix(next, lit, fade);
+    }
+    return lit;
+}
+
+// Inverse-square falloff, smoothly reaching zero at `range`.
+float attenuation(float distance, float range) {
+    float falloff = 1.0 / max(distance * distance, 0.0001);
+    if (range <= 0.0) {
+        return falloff;
+    }
+    float ratio = distance / range;
+    float window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    return falloff * window * window;
+}
+
+// Returns the light arriving at `surface` from `light`, and the direction towards the light
+// in `light_direction`.
+vec3 incoming_light(Light light, Surface surface, out vec3 light_direction) {
+    int kind = int(light.position.w);
+    float strength = light.color.a;
+
+    if (kind == DIRECTIONAL_LIGHT) {
+        light_direction = -normalize(light.direction.xyz);
+    } else {
+        vec3 offset = light.position.xyz - surface.position;
+        float distance = length(offset);
+        light_direction = offset / max(distance, 0.0001);
+        strength *= attenuation(distance, light.direction.w);
+
+        if (kind == SPOT_LIGHT) {
+            float cos_angle = dot(-light_direction, normalize(light.direction.xyz));
+            strength *= smoothstep(light.cone.y, light.cone.x, cos_angle);
+        }
+    }
+
+    // Out of range lights skip the shadow lookup, which matters with many of them.
+    if (strength > 0.0) {
+        strength *= shadow_factor(light, surface, light_direction);
+    }
+    return strength * light.color.rgb;
+}
+
+// Light arriving from all around at a surface facing `normal`.
+vec3 ambient_light(vec3 normal) {
+    if (environment.enabled != 0) {
+        return environment.intensity * texture(irradiance_map, normal).rgb;
+    }
+    return ambient.intensity * ambient.color;
+}
+
+// Fraction of the ambient light reaching this pixel, according to the SSAO pass.
+float screen_occlusion() {
+    vec2 size = vec2(textureSize(ambient_occlusion, 0));
+    return texture(ambient_occlusion, gl_FragCoord.xy / size).r;
+}
+
+vec3 blinn_phong(Light light, Surface surface, vec3 view_direction) {
+    vec3 light_direction;
+    vec3 light_color = incoming_light(light, surface, light_direction);
+
+    float diffuse = max(dot(surface.normal, light_direction), 0.0);
+    vec3 color = diffuse * light_color * surface.albedo;
+
+    // Highlights only on the side facing the light.
+    if (diffuse > 0.0) {
+        vec3 halfway = normalize(light_direction + view_direction);
+        float specular = pow(max(dot(surface.normal, halfway), 0.0), surface.shininess);
+        color += specular * light_color * surface.specular_color;
+    }
+    return color;
+}
+
+// Trowbridge-Reitz GGX normal distribution.
+float distribution_ggx(float n_dot_h, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / max(PI * d * d, 0.0001);
+}
+
+// Smith's method with the Schlick-GGX approximation for direct lighting.
+float geometry_smith(float n_dot_v, float n_dot_l, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    float g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    float g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return g_v * g_l;
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Fresnel averaged over the rough lobe, for light arriving from all directions.
+vec3 fresnel_schlick_roughness(float cos_theta, vec3 f0, float roughness) {
+    vec3 grazing = max(vec3(1.0 - roughness), f0);
+    return f0 + (grazing - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Diffuse and specular reflection of the environment, using the split-sum
+// approximation for the specular part.
+vec3 image_based_lighting(
+    vec3 normal,
+    vec3 view_direction,
+    vec3 albedo,
+    vec3 f0,
+    float metallic,
+    float roughness
+) {
+    float n_dot_v = max(dot(normal, view_direction), 0.0001);
+    vec3 fresnel = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+
+    vec3 irradiance = texture(irradiance_map, normal).rgb;
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * irradiance * albedo;
+
+    vec3 reflection = reflect(-view_direction, normal);
+    float lod = roughness * environment.max_lod;
+    vec3 prefiltered = textureLod(prefiltered_map, reflection, lod).rgb;
+    vec2 brdf = texture(brdf_lut, vec2(n_dot_v, roughness)).rg;
+    vec3 specular = prefiltered * (fresnel * brdf.x + brdf.y);
+
+    return environment.intensity * (diffuse + specular);
+}
+
+float pbr_metallic(Surface surface) {
+    return clamp(surface.metallic, 0.0, 1.0);
+}
+
+// Very low roughness makes highlights from point lights vanish.
+float pbr_roughness(Surface surface) {
+    return clamp(surface.roughness, 0.04, 1.0);
+}
+
+// Dielectrics reflect about 4% at normal incidence, metals tint the reflection.
+vec3 pbr_f0(Surface surface) {
+    return mix(vec3(0.04), surface.albedo, pbr_metallic(surface));
+}
+
+vec3 cook_torrance(Light light, Surface surface, vec3 view_direction) {
+    float metallic = pbr_metallic(surface);
+    float roughness = pbr_roughness(surface);
+    vec3 f0 = pbr_f0(surface);
+    vec3 normal = surface.normal;
+    float n_dot_v = max(dot(normal, view_direction), 0.0001);
+
+    vec3 light_direction;
+    vec3 light_color = incoming_light(light, surface, light_direction);
+
+    float n_dot_l = max(dot(normal, light_direction), 0.0);
+    if (n_dot_l <= 0.0) {
+        return vec3(0.0);
+    }
+
+    vec3 halfway = normalize(light_direction + view_direction);
+    float n_dot_h = max(dot(normal, halfway), 0.0);
+    vec3 fresnel = fresnel_schlick(max(dot(halfway, view_direction), 0.0), f0);
+    float d = distribution_ggx(n_dot_h, roughness);
+    float g = geometry_smith(n_dot_v, n_dot_l, roughness);
+    vec3 specular = d * g * fresnel / max(4.0 * n_dot_v * n_dot_l, 0.0001);
+
+    // Metals have no diffuse reflection.
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * surface.albedo / PI;
+    return (diffuse + specular) * light_color * n_dot_l;
+}
+
+// Light from `light` reflected by `surface` towards the viewer.
+vec3 direct_light(Light light, Surface surface, vec3 view_direction) {
+    if (surface.shading == PBR) {
+        return cook_torrance(light, surface, view_direction);
+    }
+    return blinn_phong(light, surface, view_direction);
+}
+
+// Ambient or environment light reflected by `surface` towards the viewer, plus its emission.
+vec3 indirect_light(Surface surface, vec3 view_direction) {
+    float occlusion = screen_occlusion();
+    if (surface.shading != PBR) {
+        return ambient_light(surface.normal) * occlusion * surface.albedo;
+    }
+
+    vec3 indirect;
+    if (environment.enabled != 0) {
+        indirect = image_based_lighting(
+            surface.normal,
+            view_direction,
+            surface.albedo,
+            pbr_f0(surface),
+            pbr_metallic(surface),
+            pbr_roughness(surface)
+        );
+    } else {
+        indirect = ambient_light(surface.normal) * surface.albedo;
+    }
+    indirect *= surface.occlusion * occlusion;
+    return indirect + surface.emissive;
+}
+
+// Tints `color` with the shadow cascade covering `position` when `debug_cascades` is set.
+vec3 debug_cascade_tint(vec3 color, vec3 position) {
+    if (shadows.debug_cascades == 0) {
+        return color;
+    }
+
+    const vec3 CASCADE_COLORS[MAX_CASCADES] = vec3[](
+        vec3(1.0, 0.2, 0.2),
+        vec3(0.2, 1.0, 0.2),
+        vec3(0.2, 0.2, 1.0),
+        vec3(1.0, 1.0, 0.2)
+    );
+    int cascade = cascade_index(position);
+    if (cascade < shadows.cascade_count) {
+        color = mix(color, CASCADE_COLORS[cascade], 0.5);
+    }
+    return color;
+}
@@ -0,0 +1,18 @@
+// Layout of a light, shared by the shaders lighting surfaces and by `light_culling`.
+
+#define DIRECTIONAL_LIGHT 0
+#define POINT_LIGHT 1
+#define SPOT_LIGHT 2
+
+struct Light {
+    // xyz: position, w: one of the light kinds above
+    vec4 position;
+    // xyz: direction the light shines in, w: range (0 for unlimited)
+    vec4 direction;
+    // rgb: color, a: intensity
+    vec4 color;
+    // x: cosine of the inner cone angle, y: cosine of the outer cone angle,
+    // z: layer of the light's first shadow map, or its cube map for point lights, -1
+    // if it casts no shadows
+    vec4 cone;
+};
@@ -0,0 +1,104 @@
+// Material of the node being drawn and its textures, shared by the fragment shaders drawing
+// nodes with `vs`. Included after `lighting.glsl`.
+
+layout(location = 0) in vec3 in_color;
+layout(location = 1) in vec3 in_normal;
+layout(location = 2) in vec3 frag_pos;
+layout(location = 3) in vec2 in_uv;
+layout(location = 4) in vec4 in_tangent;
+
+layout(set = 0, binding = 3) uniform Material_Data {
+    vec3 base_color;
+    float shininess;
+    vec3 specular_color;
+    float metallic;
+    vec3 emissive;
+    float roughness;
+    float occlusion;
+    int shading;
+    float normal_scale;
+    float occlusion_strength;
+    float alpha;
+    float alpha_cutoff;
+    int alpha_mode;
+} material;
+
+// Must match the order of `material::AlphaMode`.
+#define ALPHA_OPAQUE 0
+#define ALPHA_MASK 1
+#define ALPHA_BLEND 2
+
+// White for materials without a texture.
+layout(set = 0, binding = 12) uniform sampler2D base_color_texture;
+// Tangent-space normals; (0.5, 0.5, 1) for materials without a normal map.
+layout(set = 0, binding = 13) uniform sampler2D normal_texture;
+// Roughness in green and metalness in blue, occlusion in red, and emission. White for materials
+// without one.
+layout(set = 0, binding = 17) uniform sampler2D metallic_roughness_texture;
+layout(set = 0, binding = 18) uniform sampler2D occlusion_texture;
+layout(set = 0, binding = 19) uniform sampler2D emissive_texture;
+
+// The interpolated normal, perturbed by the normal map in the tangent space of the
+// vertices. Meshes without tangents keep the interpolated normal.
+vec3 surface_normal() {
+    vec3 normal = normalize(in_normal);
+    vec3 tangent = in_tangent.xyz;
+    if (dot(tangent, tangent) < 1e-8) {
+        return normal;
+    }
+
+    // Interpolation leaves the tangent slightly off perpendicular.
+    tangent = normalize(tangent - normal * dot(normal, tangent));
+    vec3 bitangent = cross(normal, tangent) * (in_tangent.w < 0.0 ? -1.0 : 1.0);
+    vec3 mapped = texture(normal_texture, in_uv).xyz * 2.0 - 1.0;
+    mapped.xy *= material.normal_scale;
+    return normalize(mat3(tangent, bitangent, normal) * mapped);
+}
+
+// `surface_normal`, facing the viewer on the back faces of double-sided materials.
+vec3 facing_normal() {
+    vec3 normal = surface_normal();
+    return gl_FrontFacing ? normal : -normal;
+}
+
+// Opacity of the fragment: the material's alpha times the base color texture's for blended
+// materials, 1 otherwise. Fragments of masked materials below the cutoff are discarded.
+float surface_alpha() {
+    if (material.alpha_mode == ALPHA_OPAQUE) {
+        return 1.0;
+    }
+    float alpha = material.alpha * texture(base_color_texture, in_uv).a;
+    if (material.alpha_mode == ALPHA_MASK) {
+        if (alpha < material.alpha_cutoff) {
+            discard;
+        }
+        return 1.0;
+    }
+    return alpha;
+}
+
+// Vertex color times the material's base color and texture.
+vec3 surface_albedo() {
+    vec3 base_color = material.base_color * texture(base_color_texture, in_uv).rgb;
+    return in_color * base_color;
+}
+
+// The point of the node drawn by this fragment, with the material's factors scaled by its
+// textures.
+Surface material_surface() {
+    vec4 metallic_roughness = texture(metallic_roughness_texture, in_uv);
+    float occlusion_map = texture(occlusion_texture, in_uv).r;
+    float occlusion = material.occlusion * mix(1.0, occlusion_map, material.occlusion_strength);
+    return Surface(
+        frag_pos,
+        facing_normal(),
+        surface_albedo(),
+        material.specular_color,
+        material.shininess,
+        material.metallic * metallic_roughness.b,
+        material.roughness * metallic_roughness.g,
+        occlusion,
+        material.emissive * texture(emissive_texture, in_uv).rgb,
+        material.shading
+    );
+}
@@ -0,0 +1,648 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use nalgebra_glm::{look_at, ortho_rh_zo, perspective_rh_zo, vec3, vec4, TMat4, Vec3};
+use vulkano::{
+    command_buffer::{
+        allocator::CommandBufferAllocator, AutoCommandBufferBuilder, RenderPassBeginInfo,
+        SubpassContents,
+    },
+    device::Device,
+    format::Format,
+    image::{
+        view::{ImageView, ImageViewCreateInfo, ImageViewType},
+        ImageAccess, ImageCreateFlags, ImageDimensions, ImageLayout, ImageSubresourceRange,
+        ImageUsage,
+    },
+    memory::allocator::StandardMemoryAllocator,
+    pipeline::{
+        graphics::{
+            depth_stencil::{CompareOp, DepthStencilState},
+            input_assembly::InputAssemblyState,
+            rasterization::{CullMode, RasterizationState},
+            vertex_input::BuffersDefinition,
+            viewport::{Viewport, ViewportState},
+        },
+        GraphicsPipeline, Pipeline,
+    },
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+    sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+};
+
+use crate::{
+    camera::Camera,
+    geometry::Bounds,
+    gpu_image::GpuImage,
+    mesh::Mesh,
+    scene::Node,
+    shaders::{shadow_fs, shadow_vs},
+    vertex::{Light, SpotLight, Vertex},
+};
+
+// Layers of the shadow map array. Spot lights take one layer and directional lights one per
+// cascade; lights that don't fit get no shadows. Must match `MAX_SHADOW_MAPS` in the fragment
+// shader.
+pub const MAX_SHADOW_MAPS: usize = 8;
+// Must match `MAX_CASCADES` in the fragment shader.
+pub const MAX_CASCADES: usize = 4;
+// Point lights casting shadows at the same time, each with its own cube map. Must match
+// `MAX_POINT_SHADOWS` in the fragment shader.
+pub const MAX_POINT_SHADOWS: usize = 2;
+
+// 16-bit depth is the only depth format every device can both render to and sample.
+const FORMAT: Format = Format::D16_UNORM;
+
+// Spot light shadows don't cover more than this angle, where the projection gets degenerate.
+const MAX_SPOT_FOV: f32 = 170.0 * std::f32::consts::PI / 180.0;
+const SPOT_NEAR: f32 = 0.05;
+const POINT_NEAR: f32 = 0.05;
+
+// Direction and up vector of each cube map face, in the order of the layers: +x, -x, +y, -y,
+// +z, -z. The up vectors follow the cube map conventions, where t grows towards -y on the side
+// faces.
+const CUBE_FACES: [([f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+];
+
+// How shadow maps are rendered and sampled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowSettings {
+    // Width and height of every shadow map, in texels.
+    pub map_size: u32,
+    // Width and height of each face of a point light's cube map, in texels.
+    pub point_map_size: u32,
+    // Subtracted from a fragment's depth in light space, which ranges from 0 to 1, before it is
+    // compared against the shadow map.
+    pub depth_bias: f32,
+    // World-space distance fragments are moved along their normal before the lookup, growing
+    // towards grazing angles where shadow acne is worst.
+    pub normal_bias: f32,
+    // Percentage-closer filtering averages the comparison over a square of
+    // `2 * pcf_radius + 1` texels on each side; 0 gives hard shadows.
+    pub pcf_radius: u32,
+    // Number of slices the camera frustum is split into for directional lights, each with its
+    // own shadow map, from 1 to `MAX_CASCADES`.
+    pub cascade_count: u32,
+    // Distance from the camera up to which directional lights cast shadows.
+    pub shadow_distance: f32,
+    // Mixes logarithmic (1) and uniform (0) cascade splits. Logarithmic splits spend more
+    // resolution close to the camera.
+    pub cascade_split_lambda: f32,
+    // Fraction of each cascade, at its far end, that fades into the next one to hide the seam.
+    pub cascade_blend: f32,
+    // Tints every pixel within the shadow distance with the color of its cascade.
+    pub debug_cascades: bool,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            map_size: 2048,
+            point_map_size: 1024,
+            depth_bias: 0.002,
+            normal_bias: 0.02,
+            pcf_radius: 1,
+            cascade_count: 4,
+            shadow_distance: 50.0,
+            cascade_split_lambda: 0.75,
+            cascade_blend: 0.1,
+            debug_cascades: false,
+        }
+    }
+}
+
+// A light that gets shadow maps this frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShadowCaster {
+    // Index of the light in `Scene::lights`.
+    pub light: usize,
+    // Layer of the light's first shadow map in the shadow map array.
+    pub first_layer: usize,
+    // Matrices mapping world positions into the clip space of each of the light's shadow maps:
+    // one per cascade for directional lights, a single one for spot lights.
+    pub light_spaces: Vec<TMat4<f32>>,
+}
+
+// Assigns shadow map layers to the lights casting shadows, in order, until the array is full.
+// Without `scene_bounds` nothing is drawn, so nothing casts shadows either.
+pub fn shadow_casters(
+    lights: &[Light],
+    camera: &Camera,
+    settings: &ShadowSettings,
+    scene_bounds: Option<Bounds>,
+) -> Vec<ShadowCaster> {
+    let Some(bounds) = scene_bounds else {
+        return Vec::new();
+    };
+
+    let mut casters = Vec::new();
+    let mut next_layer = 0;
+    for (i, light) in lights.iter().enumerate() {
+        if !light.cast_shadows() {
+            continue;
+        }
+
+        let light_spaces = match light {
+            Light::Directional(light) => {
+                cascade_light_spaces(light.direction, camera, settings, &bounds)
+            }
+            Light::Spot(light) => vec![spot_light_space(light, &bounds)],
+            // Point lights have cube maps of their own, see `point_shadow_casters`.
+            Light::Point(_) => continue,
+        };
+        if next_layer + light_spaces.len() > MAX_SHADOW_MAPS {
+            continue;
+        }
+
+        casters.push(ShadowCaster {
+            light: i,
+            first_layer: next_layer,
+            light_spaces,
+        });
+        next_layer += casters.last().unwrap().light_spaces.len();
+    }
+
+    casters
+}
+
+// Distance along the view direction at which each cascade ends. Cascades start at the camera's
+// near plane and the last one ends at the shadow distance.
+pub fn cascade_splits(camera: &Camera, settings: &ShadowSettings) -> Vec<f32> {
+    let count = settings.cascade_count.clamp(1, MAX_CASCADES as u32);
+    let near = camera.near;
+    let far = settings.shadow_distance.min(camera.far).max(near);
+    let lambda = settings.cascade_split_lambda.clamp(0.0, 1.0);
+
+    (1..=count)
+        .map(|i| {
+            let fraction = i as f32 / count as f32;
+            let logarithmic = near * (far / near).powf(fraction);
+            let uniform = near + (far - near) * fraction;
+            lambda * logarithmic + (1.0 - lambda) * uniform
+        })
+        .collect()
+}
+
+// Light space of each cascade of a directional light shining in `direction`. Every cascade
+// covers its slice of the camera frustum, plus whatever lies between the slice and the light
+// within `scene_bounds`.
+pub fn cascade_light_spaces(
+    direction: [f32; 3],
+    camera: &Camera,
+    settings: &ShadowSettings,
+    scene_bounds: &Bounds,
+) -> Vec<TMat4<f32>> {
+    let direction = Vec3::from(direction).normalize();
+    let splits = cascade_splits(camera, settings);
+
+    std::iter::once(camera.near)
+        .chain(splits.iter().copied())
+        .zip(&splits)
+        .map(|(near, &far)| {
+            let corners = frustum_corners(camera, near, far);
+            fit_cascade(&direction, &corners, scene_bounds, settings.map_size)
+        })
+        .collect()
+}
+
+// Orthographic light space around a bounding sphere of `corners`. The sphere's size doesn't
+// depend on the camera's orientation, and its center is snapped to whole shadow map texels, so
+// the shadows don't shimmer when the camera moves or turns.
+fn fit_cascade(
+    direction: &Vec3,
+    corners: &[Vec3; 8],
+    scene_bounds: &Bounds,
+    map_size: u32,
+) -> TMat4<f32> {
+    let center = corners.iter().sum::<Vec3>() / 8.0;
+    let radius = corners
+        .iter()
+        .map(|corner| (corner - center).norm())
+        .fold(0.0, f32::max);
+    // Rounding keeps floating point noise from changing the texel size.
+    let radius = ((radius * 16.0).ceil() / 16.0).max(1.0 / 16.0);
+
+    let rotation = look_at(&Vec3::zeros(), direction, &up_vector(direction));
+    let center = (rotation * vec4(center.x, center.y, center.z, 1.0)).xyz();
+    let texel = 2.0 * radius / map_size as f32;
+    let x = (center.x / texel).floor() * texel;
+    let y = (center.y / texel).floor() * texel;
+
+    // The light looks down -z: anything in the scene closer to it than the slice can cast
+    // shadows into the slice.
+    let scene = scene_bounds.transform(&rotation);
+    let near = -(center.z + radius).max(scene.max.z);
+    let far = -(center.z - radius);
+    ortho_rh_zo(x - radius, x + radius, y - radius, y + radius, near, far) * rotation
+}
+
+// Corners of the part of the camera's view between the distances `near` and `far`.
+fn frustum_corners(camera: &Camera, near: f32, far: f32) -> [Vec3; 8] {
+    let eye = camera.eye();
+    let forward = camera.forward();
+    let right = forward.cross(&up_vector(&forward)).normalize();
+    let up = right.cross(&forward);
+    let tan_half_fov = (camera.fov_y / 2.0).tan();
+
+    let mut corners = [Vec3::zeros(); 8];
+    for (i, distance) in [near, far].into_iter().enumerate() {
+        let half_height = distance * tan_half_fov;
+        let half_width = half_height * camera.aspect;
+        let center = eye + forward * distance;
+        corners[i * 4] = center - right * half_width - up * half_height;
+        corners[i * 4 + 1] = center + right * half_width - up * half_height;
+        corners[i * 4 + 2] = center - right * half_width + up * half_height;
+        corners[i * 4 + 3] = center + right * half_width + up * half_height;
+    }
+    corners
+}
+
+// Perspective light space along the cone of a spot light, reaching as far as the light does or,
+// for unlimited range, past everything within `scene_bounds`.
+pub fn spot_light_space(light: &SpotLight, scene_bounds: &Bounds) -> TMat4<f32> {
+    let position = Vec3::from(light.position);
+    let direction = Vec3::from(light.direction).normalize();
+    let far = if light.range > 0.0 {
+        light.range
+    } else {
+        (scene_bounds.center() - position).norm() + scene_bounds.radius()
+    };
+    let view = look_at(&position, &(position + direction), &up_vector(&direction));
+    let fov = (2.0 * light.outer_cone_angle).min(MAX_SPOT_FOV);
+    let projection = perspective_rh_zo(1.0, fov, SPOT_NEAR, far.max(2.0 * SPOT_NEAR));
+    projection * view
+}
+
+// A point light that gets a cube map this frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PointShadowCaster {
+    // Index of the light in `Scene::lights`.
+    pub light: usize,
+    // Which of the `MAX_POINT_SHADOWS` cube maps the light renders into.
+    pub slot: usize,
+    // Depth range of the cube map faces, around the light's position.
+    pub near: f32,
+    pub far: f32,
+    // Matrices mapping world positions into the clip space of each face.
+    pub light_spaces: [TMat4<f32>; 6],
+}
+
+// Assigns cube maps to the first `MAX_POINT_SHADOWS` point lights casting shadows. Like
+// `shadow_casters`, nothing casts shadows without `scene_bounds`.
+pub fn point_shadow_casters(
+    lights: &[Light],
+    scene_bounds: Option<Bounds>,
+) -> Vec<PointShadowCaster> {
+    let Some(bounds) = scene_bounds else {
+        return Vec::new();
+    };
+
+    lights
+        .iter()
+        .enumerate()
+        .filter_map(|(i, light)| match light {
+            Light::Point(light) if light.cast_shadows => Some((i, light)),
+            _ => None,
+        })
+        .take(MAX_POINT_SHADOWS)
+        .enumerate()
+        .map(|(slot, (i, light))| {
+            let position = Vec3::from(light.position);
+            let far = if light.range > 0.0 {
+                light.range
+            } else {
+                (bounds.center() - position).norm() + bounds.radius()
+            }
+            .max(2.0 * POINT_NEAR);
+
+            let projection = perspective_rh_zo(1.0, std::f32::consts::FRAC_PI_2, POINT_NEAR, far);
+            let light_spaces = CUBE_FACES.map(|(direction, up)| {
+                let view = look_at(
+                    &position,
+                    &(position + Vec3::from(direction)),
+                    &Vec3::from(up),
+                );
+                projection * view
+            });
+
+            PointShadowCaster {
+                light: i,
+                slot,
+                near: POINT_NEAR,
+                far,
+                light_spaces,
+            }
+        })
+        .collect()
+}
+
+// Any up vector works for a light's view, as long as it isn't parallel to the direction.
+fn up_vector(direction: &Vec3) -> Vec3 {
+    if direction.y.abs() > 0.99 {
+        vec3(0.0, 0.0, 1.0)
+    } else {
+        vec3(0.0, 1.0, 0.0)
+    }
+}
+
+// An array of `MAX_SHADOW_MAPS` depth maps and `MAX_POINT_SHADOWS` depth cube maps, with the
+// pass rendering into them.
+pub(crate) struct ShadowMaps {
+    render_pass: Arc<RenderPass>,
+    pipeline: Arc<GraphicsPipeline>,
+    sampler: Arc<Sampler>,
+    maps: MapArray,
+    point_maps: MapArray,
+}
+
+// Depth maps sharing one image, one layer each.
+struct MapArray {
+    size: u32,
+    // Views for sampling in the main pass, each over a range of layers.
+    views: Vec<Arc<ImageView<GpuImage>>>,
+    // One per layer.
+    framebuffers: Vec<Arc<Framebuffer>>,
+}
+
+impl ShadowMaps {
+    pub(crate) fn new(
+        device: &Arc<Device>,
+        memory_allocator: &StandardMemoryAllocator,
+        settings: &ShadowSettings,
+    ) -> Result<ShadowMaps> {
+        // The maps stay in the general layout outside the pass, so they can be sampled without
+        // further transitions.
+        let render_pass = vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                depth: {
+                    load: Clear,
+                    store: Store,
+                    format: FORMAT,
+                    samples: 1,
+                    initial_layout: ImageLayout::General,
+                    final_layout: ImageLayout::General,
+                }
+            },
+            pass: {
+                color: [],
+                depth_stencil: {depth}
+            }
+        )?;
+
+        let vs = shadow_vs::load(device.clone())?;
+        let fs = shadow_fs::load(device.clone())?;
+        // Back faces are drawn as well, so open meshes still cast shadows.
+        let pipeline = GraphicsPipeline::start()
+            .vertex_input_state(BuffersDefinition::new().vertex::<Vertex>())
+            .vertex_shader(vs.entry_point("main").unwrap(), ())
+            .input_assembly_state(InputAssemblyState::new())
+            .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+            .fragment_shader(fs.entry_point("main").unwrap(), ())
+            .depth_stencil_state(DepthStencilState::simple_depth_test())
+            .rasterization_state(RasterizationState::new().cull_mode(CullMode::None))
+            .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+            .build(device.clone())?;
+
+        // Compares against the stored depth; filtering happens in the shader.
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                compare: Some(CompareOp::LessOrEqual),
+                ..Default::default()
+            },
+        )?;
+
+        let maps = MapArray::maps(memory_allocator, &render_pass, settings.map_size)?;
+        let point_maps =
+            MapArray::point_maps(memory_allocator, &render_pass, settings.point_map_size)?;
+
+        Ok(ShadowMaps {
+            render_pass,
+            pipeline,
+            sampler,
+            maps,
+            point_maps,
+        })
+    }
+
+    // Reallocates the maps whose size changed.
+    pub(crate) fn resize(
+        &mut self,
+        memory_allocator: &StandardMemoryAllocator,
+        settings: &ShadowSettings,
+    ) -> Result<()> {
+        if settings.map_size != self.maps.size {
+            self.maps = MapArray::maps(memory_allocator, &self.render_pass, settings.map_size)?;
+        }
+        if settings.point_map_size != self.point_maps.size {
+            self.point_maps =
+                MapArray::point_maps(memory_allocator, &self.render_pass, settings.point_map_size)?;
+        }
+
+        Ok(())
+    }
+
+    // All layers of the shadow map array.
+    pub(crate) fn view(&self) -> &Arc<ImageView<GpuImage>> {
+        &self.maps.views[0]
+    }
+
+    // One cube view per point shadow slot.
+    pub(crate) fn point_views(&self) -> &[Arc<ImageView<GpuImage>>] {
+        &self.point_maps.views
+    }
+
+    pub(crate) fn sampler(&self) -> &Arc<Sampler> {
+        &self.sampler
+    }
+
+    // Records the pass rendering the depth of `draws` into shadow map `layer`, as seen through
+    // `light_space`.
+    pub(crate) fn record<L, A>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+        layer: usize,
+        light_space: &TMat4<f32>,
+        draws: &[(&Node, &Arc<Mesh>, TMat4<f32>)],
+    ) -> Result<()>
+    where
+        A: CommandBufferAllocator,
+    {
+        self.record_layer(builder, &self.maps, layer, light_space, draws)
+    }
+
+    // Records the passes rendering the depth of `draws` into all faces of `caster`'s cube map.
+    pub(crate) fn record_point<L, A>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+        caster: &PointShadowCaster,
+        draws: &[(&Node, &Arc<Mesh>, TMat4<f32>)],
+    ) -> Result<()>
+    where
+        A: CommandBufferAllocator,
+    {
+        for (face, light_space) in caster.light_spaces.iter().enumerate() {
+            let layer = caster.slot * 6 + face;
+            self.record_layer(builder, &self.point_maps, layer, light_space, draws)?;
+        }
+
+        Ok(())
+    }
+
+    fn record_layer<L, A>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+        maps: &MapArray,
+        layer: usize,
+        light_space: &TMat4<f32>,
+        draws: &[(&Node, &Arc<Mesh>, TMat4<f32>)],
+    ) -> Result<()>
+    where
+        A: CommandBufferAllocator,
+    {
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![Some(1f32.into())],
+                    ..RenderPassBeginInfo::framebuffer(maps.framebuffers[layer].clone())
+                },
+                SubpassContents::Inline,
+            )?
+            .set_viewport(
+                0,
+                [Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [maps.size as f32, maps.size as f32],
+                    depth_range: 0.0..1.0,
+                }],
+            )
+            .bind_pipeline_graphics(self.pipeline.clone());
+
+        for (_, mesh, world) in draws {
+            builder.push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                shadow_vs::ty::Shadow_Caster {
+                    transform: (light_space * world).into(),
+                },
+            );
+            mesh.draw(builder)?;
+        }
+
+        builder.end_render_pass()?;
+
+        Ok(())
+    }
+}
+
+impl MapArray {
+    // The shadow map array, sampled through a single 2D array view.
+    fn maps(
+        memory_allocator: &StandardMemoryAllocator,
+        render_pass: &Arc<RenderPass>,
+        size: u32,
+    ) -> Result<MapArray> {
+        let layers = MAX_SHADOW_MAPS as u32;
+        MapArray::new(
+            memory_allocator,
+            render_pass,
+            size,
+            ImageViewType::Dim2dArray,
+            layers,
+            1,
+        )
+    }
+
+    // Six faces per point light, sampled through one cube view each.
+    fn point_maps(
+        memory_allocator: &StandardMemoryAllocator,
+        render_pass: &Arc<RenderPass>,
+        size: u32,
+    ) -> Result<MapArray> {
+        MapArray::new(
+            memory_allocator,
+            render_pass,
+            size,
+            ImageViewType::Cube,
+            6,
+            MAX_POINT_SHADOWS as u32,
+        )
+    }
+
+    // `view_count` views of `view_type`, each over `layers_per_view` layers.
+    fn new(
+        memory_allocator: &StandardMemoryAllocator,
+        render_pass: &Arc<RenderPass>,
+        size: u32,
+        view_type: ImageViewType,
+        layers_per_view: u32,
+        view_count: u32,
+    ) -> Result<MapArray> {
+        let array_layers = layers_per_view * view_count;
+        let image = GpuImage::new(
+            memory_allocator,
+            ImageDimensions::Dim2d {
+                width: size,
+                height: size,
+                array_layers,
+            },
+            1,
+            FORMAT,
+            ImageUsage {
+                depth_stencil_attachment: true,
+                sampled: true,
+                ..ImageUsage::empty()
+            },
+            ImageCreateFlags {
+                cube_compatible: view_type == ImageViewType::Cube,
+                ..ImageCreateFlags::empty()
+            },
+        )?;
+
+        let layer_view = |layers: std::ops::Range<u32>, view_type| {
+            ImageView::new(
+                image.clone(),
+                ImageViewCreateInfo {
+                    view_type,
+                    subresource_range: ImageSubresourceRange {
+                        array_layers: layers,
+                        ..image.subresource_range()
+                    },
+                    ..ImageViewCreateInfo::from_image(&image)
+                },
+            )
+        };
+
+        let views = (0..view_count)
+            .map(|i| layer_view(i * layers_per_view..(i + 1) * layers_per_view, view_type))
+            .collect::<Result<_, _>>()?;
+
+        let framebuffers = (0..array_layers)
+            .map(|layer| {
+                let framebuffer = Framebuffer::new(
+                    render_pass.clone(),
+                    FramebufferCreateInfo {
+                        attachments: vec![layer_view(layer..layer + 1, ImageViewType::Dim2d)?],
+                        ..Default::default()
+                    },
+                )?;
+                Ok(framebuffer)
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(MapArray {
+            size,
+            views,
+            framebuffers,
+        })
+    }
+}
@@ -0,0 +1,613 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use nalgebra_glm::{TMat4, Vec3};
+use vulkano::{
+    buffer::CpuBufferPool,
+    command_buffer::{
+        allocator::CommandBufferAllocator, AutoCommandBufferBuilder, RenderPassBeginInfo,
+        SubpassContents,
+    },
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, PersistentDescriptorSet, WriteDescriptorSet,
+    },
+    device::Device,
+    format::Format,
+    image::{view::ImageView, ImageCreateFlags, ImageDimensions, ImageLayout, ImageUsage},
+    memory::allocator::StandardMemoryAllocator,
+    pipeline::{
+        graphics::{
+            depth_stencil::DepthStencilState,
+            input_assembly::InputAssemblyState,
+            rasterization::{CullMode, RasterizationState},
+            vertex_input::BuffersDefinition,
+            viewport::{Viewport, ViewportState},
+        },
+        GraphicsPipeline, Pipeline, PipelineBindPoint,
+    },
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+    sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+    shader::ShaderModule,
+};
+
+use crate::{
+    gpu_image::GpuImage,
+    material::{AlphaMode, Material},
+    mesh::Mesh,
+    shaders::{masked_depth_fs, post_vs, shadow_fs, ssao_blur_fs, ssao_fs, vs},
+    texture::{ColorSpace, ImageData, SamplerSettings, Texture},
+    upload::Uploader,
+    vertex::Vertex,
+};
+
+// Must match `MAX_SSAO_SAMPLES` in the SSAO shader.
+pub const MAX_SSAO_SAMPLES: usize = 32;
+
+// Same depth format as the shadow maps and the forward path's depth buffer, the only one every
+// device can both render to and sample.
+const DEPTH_FORMAT: Format = Format::D16_UNORM;
+// Occlusion in red and the linear depth the blur compares in green.
+const OCCLUSION_FORMAT: Format = Format::R16G16_SFLOAT;
+// Width and height of the tiled texture of random rotations.
+const NOISE_SIZE: u32 = 4;
+
+// Screen-space ambient occlusion: darkens ambient light in creases and where objects meet, by
+// testing points around each pixel against the depth of the scene. Off by default, since it costs
+// a few full-screen passes every frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SsaoSettings {
+    pub enabled: bool,
+    // World-space radius around each pixel in which geometry occludes it.
+    pub radius: f32,
+    // Depth difference below which samples don't count, against self-occlusion on flat
+    // surfaces.
+    pub bias: f32,
+    // Exponent applied to the unoccluded fraction; higher values darken more.
+    pub intensity: f32,
+    // Points tested per pixel, up to `MAX_SSAO_SAMPLES`.
+    pub sample_count: u32,
+    // How strongly depth differences stop the blur, keeping it from bleeding across edges.
+    pub blur_sharpness: f32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        SsaoSettings {
+            enabled: false,
+            radius: 0.5,
+            bias: 0.025,
+            intensity: 1.5,
+            sample_count: 16,
+            blur_sharpness: 20.0,
+        }
+    }
+}
+
+// Offsets of the points tested around each pixel, in a hemisphere of radius 1 around +z, which
+// the shader turns towards the surface normal. They are spread at random but the same on every
+// call, and bunched up towards the center, where occluders matter most.
+pub fn ssao_kernel(count: usize) -> Vec<Vec3> {
+    let mut random = Random(0x9e37_79b9);
+    (0..count)
+        .map(|i| {
+            let direction = loop {
+                let v = Vec3::new(
+                    random.next() * 2.0 - 1.0,
+                    random.next() * 2.0 - 1.0,
+                    random.next(),
+                );
+                // Rejects points outside the unit sphere, and those so close to the surface or
+                // the center that they can't tell anything apart.
+                let length = v.norm();
+                if length <= 1.0 && length > 0.1 && v.z > 0.1 * length {
+                    break v / length;
+                }
+            };
+            let t = (i + 1) as f32 / count as f32;
+            let scale = 0.1 + 0.9 * t * t;
+            direction * scale * (0.5 + 0.5 * random.next())
+        })
+        .collect()
+}
+
+// Random rotations around the normal, one per texel of the tiled noise texture, so neighboring
+// pixels test different points; the blur then averages out the pattern.
+fn noise_image() -> ImageData {
+    let mut random = Random(0x85eb_ca6b);
+    let rgba = (0..NOISE_SIZE * NOISE_SIZE)
+        .flat_map(|_| {
+            let angle = random.next() * std::f32::consts::TAU;
+            let encode = |v: f32| ((v * 0.5 + 0.5) * 255.0).round() as u8;
+            [encode(angle.cos()), encode(angle.sin()), 128, 255]
+        })
+        .collect();
+
+    ImageData {
+        width: NOISE_SIZE,
+        height: NOISE_SIZE,
+        rgba,
+    }
+}
+
+// Xorshift, as the offsets only need to look random.
+struct Random(u32);
+
+impl Random {
+    // Between 0 and 1.
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+// Where SSAO reads the depth of the scene from.
+pub(crate) enum SsaoDepth {
+    // The forward path's depth buffer is multisampled, so the depth prepass renders into an image
+    // of its own.
+    Separate,
+    // The forward path's depth buffer, rendered by the depth prepass and kept by the main pass.
+    DepthBuffer(Arc<ImageView<GpuImage>>),
+    // The depth of the deferred path's G-buffer, rendered by its own depth prepass and kept by
+    // the G-buffer subpass.
+    GBuffer(Arc<ImageView<GpuImage>>),
+}
+
+// Renders only the depth of the scene, so SSAO can sample it before the lighting runs. Drawn
+// with the main pass's vertex shader, which keeps the same depth when the main pass goes on from
+// it. The image stays in the general layout outside the pass, so SSAO can sample it without
+// further transitions.
+pub(crate) struct DepthPrepass {
+    render_pass: Arc<RenderPass>,
+    // Indexed by whether the material is double-sided.
+    pipelines: [Arc<GraphicsPipeline>; 2],
+    // Leaving out the fragments masked materials discard.
+    masked_pipelines: [Arc<GraphicsPipeline>; 2],
+}
+
+// A node in the depth prepass: its mesh and material, and the writes of its descriptor set in
+// the main pass, of which the prepass uses those its shaders declare.
+pub(crate) type PrepassDraw<'a> = (&'a Arc<Mesh>, &'a Material, Vec<WriteDescriptorSet>);
+
+impl DepthPrepass {
+    pub(crate) fn new(device: &Arc<Device>, format: Format) -> Result<DepthPrepass> {
+        let render_pass = vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                depth: {
+                    load: Clear,
+                    store: Store,
+                    format: format,
+                    samples: 1,
+                    initial_layout: ImageLayout::General,
+                    final_layout: ImageLayout::General,
+                }
+            },
+            pass: {
+                color: [],
+                depth_stencil: {depth}
+            }
+        )?;
+
+        let vs = vs::load(device.clone())?;
+        let pipeline = |fs: &Arc<ShaderModule>, cull_mode| {
+            GraphicsPipeline::start()
+                .vertex_input_state(BuffersDefinition::new().vertex::<Vertex>())
+                .vertex_shader(vs.entry_point("main").unwrap(), ())
+                .input_assembly_state(InputAssemblyState::new())
+                .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+                .fragment_shader(fs.entry_point("main").unwrap(), ())
+                .depth_stencil_state(DepthStencilState::simple_depth_test())
+                .rasterization_state(RasterizationState::new().cull_mode(cull_mode))
+                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+        };
+        let fs = shadow_fs::load(device.clone())?;
+        let masked_fs = masked_depth_fs::load(device.clone())?;
+
+        Ok(DepthPrepass {
+            pipelines: [
+                pipeline(&fs, CullMode::Back)?,
+                pipeline(&fs, CullMode::None)?,
+            ],
+            masked_pipelines: [
+                pipeline(&masked_fs, CullMode::Back)?,
+                pipeline(&masked_fs, CullMode::None)?,
+            ],
+            render_pass,
+        })
+    }
+
+    pub(crate) fn framebuffer(&self, depth: &Arc<ImageView<GpuImage>>) -> Result<Arc<Framebuffer>> {
+        let framebuffer = Framebuffer::new(
+            self.render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: vec![depth.clone()],
+                ..Default::default()
+            },
+        )?;
+
+        Ok(framebuffer)
+    }
+
+    // Records the pass into `framebuffer` for `draws`. Blended materials don't occlude what is
+    // behind them, so their nodes are left out.
+    pub(crate) fn record<L, A>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+        descriptor_set_allocator: &StandardDescriptorSetAllocator,
+        framebuffer: &Arc<Framebuffer>,
+        draws: Vec<PrepassDraw>,
+    ) -> Result<()>
+    where
+        A: CommandBufferAllocator,
+    {
+        let [width, height] = framebuffer.extent();
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![Some(1f32.into())],
+                    ..RenderPassBeginInfo::framebuffer(framebuffer.clone())
+                },
+                SubpassContents::Inline,
+            )?
+            .set_viewport(
+                0,
+                [Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [width as f32, height as f32],
+                    depth_range: 0.0..1.0,
+                }],
+            );
+        for (mesh, material, writes) in draws {
+            let pipelines = match material.alpha_mode {
+                AlphaMode::Opaque => &self.pipelines,
+                AlphaMode::Mask { .. } => &self.masked_pipelines,
+                AlphaMode::Blend => continue,
+            };
+            let pipeline = &pipelines[material.double_sided as usize];
+            let layout = &pipeline.layout().set_layouts()[0];
+            let writes = writes
+                .into_iter()
+                .filter(|write| layout.bindings().contains_key(&write.binding()));
+            let set =
+                PersistentDescriptorSet::new(descriptor_set_allocator, layout.clone(), writes)?;
+            builder
+                .bind_pipeline_graphics(pipeline.clone())
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    pipeline.layout().clone(),
+                    0,
+                    set,
+                );
+            mesh.draw(builder)?;
+        }
+        builder.end_render_pass()?;
+
+        Ok(())
+    }
+}
+
+// Computes the occlusion from the depth of the scene and blurs the result, before the lighting
+// samples it. The depth comes from a depth prepass, which the forward and clustered paths record
+// with `record_depth` and the deferred path with its own.
+pub(crate) struct Ssao {
+    depth_prepass: DepthPrepass,
+    render_pass: Arc<RenderPass>,
+    occlusion_pipeline: Arc<GraphicsPipeline>,
+    blur_pipeline: Arc<GraphicsPipeline>,
+    sampler: Arc<Sampler>,
+    noise: Texture,
+    uniform_buffer: CpuBufferPool<ssao_fs::ty::Ssao_Data>,
+}
+
+// Images sized like the target.
+pub(crate) struct SsaoTargets {
+    depth: Arc<ImageView<GpuImage>>,
+    // Renders `depth` in the depth prepass, unless it comes from the G-buffer.
+    depth_framebuffer: Option<Arc<Framebuffer>>,
+    // The occlusion is computed into the first, blurred horizontally into the second and back
+    // vertically into the first.
+    occlusion: [(Arc<ImageView<GpuImage>>, Arc<Framebuffer>); 2],
+}
+
+impl Ssao {
+    pub(crate) fn new(uploader: &Uploader) -> Result<Ssao> {
+        let device = uploader.graphics_queue().device();
+        let memory_allocator = uploader.memory_allocator();
+
+        // The images stay in the general layout outside the passes, so the next one can sample
+        // them without further transitions.
+        let render_pass = vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                color: {
+                    load: DontCare,
+                    store: Store,
+                    format: OCCLUSION_FORMAT,
+                    samples: 1,
+                    initial_layout: ImageLayout::General,
+                    final_layout: ImageLayout::General,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {}
+            }
+        )?;
+
+        let vs = post_vs::load(device.clone())?;
+        let full_screen = |fs: Arc<vulkano::shader::ShaderModule>| {
+            GraphicsPipeline::start()
+                .vertex_input_state(BuffersDefinition::new())
+                .vertex_shader(vs.entry_point("main").unwrap(), ())
+                .input_assembly_state(InputAssemblyState::new())
+                .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+                .fragment_shader(fs.entry_point("main").unwrap(), ())
+                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+        };
+        let occlusion_pipeline = full_screen(ssao_fs::load(device.clone())?)?;
+        let blur_pipeline = full_screen(ssao_blur_fs::load(device.clone())?)?;
+
+        // Depth can't be filtered, and neither can the occlusion without blurring across edges.
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
+
+        let noise = uploader.submit_graphics(|builder| {
+            Texture::from_data(
+                uploader,
+                builder,
+                &noise_image(),
+                ColorSpace::Linear,
+                &SamplerSettings {
+                    mag_filter: Filter::Nearest,
+                    min_filter: Filter::Nearest,
+                    anisotropy: None,
+                    ..Default::default()
+                },
+            )
+        })?;
+
+        Ok(Ssao {
+            depth_prepass: DepthPrepass::new(device, DEPTH_FORMAT)?,
+            render_pass,
+            occlusion_pipeline,
+            blur_pipeline,
+            sampler,
+            noise,
+            uniform_buffer: CpuBufferPool::uniform_buffer(memory_allocator.clone()),
+        })
+    }
+
+    pub(crate) fn targets(
+        &self,
+        memory_allocator: &StandardMemoryAllocator,
+        dimensions: [u32; 2],
+        depth: SsaoDepth,
+    ) -> Result<SsaoTargets> {
+        let image = |format, usage| {
+            let image = GpuImage::new(
+                memory_allocator,
+                ImageDimensions::Dim2d {
+                    width: dimensions[0],
+                    height: dimensions[1],
+                    array_layers: 1,
+                },
+                1,
+                format,
+                usage,
+                ImageCreateFlags::empty(),
+            )?;
+            anyhow::Ok(ImageView::new_default(image)?)
+        };
+
+        let (depth, depth_framebuffer) = match depth {
+            SsaoDepth::Separate => {
+                let depth = image(
+                    DEPTH_FORMAT,
+                    ImageUsage {
+                        depth_stencil_attachment: true,
+                        sampled: true,
+                        ..ImageUsage::empty()
+                    },
+                )?;
+                let depth_framebuffer = self.depth_prepass.framebuffer(&depth)?;
+                (depth, Some(depth_framebuffer))
+            }
+            SsaoDepth::DepthBuffer(depth) => {
+                let depth_framebuffer = self.depth_prepass.framebuffer(&depth)?;
+                (depth, Some(depth_framebuffer))
+            }
+            SsaoDepth::GBuffer(depth) => (depth, None),
+        };
+        let occlusion_usage = ImageUsage {
+            color_attachment: true,
+            sampled: true,
+            ..ImageUsage::empty()
+        };
+        let occlusion = || {
+            let view = image(OCCLUSION_FORMAT, occlusion_usage)?;
+            let framebuffer = Framebuffer::new(
+                self.render_pass.clone(),
+                FramebufferCreateInfo {
+                    attachments: vec![view.clone()],
+                    ..Default::default()
+                },
+            )?;
+            anyhow::Ok((view, framebuffer))
+        };
+
+        Ok(SsaoTargets {
+            depth,
+            depth_framebuffer,
+            occlusion: [occlusion()?, occlusion()?],
+        })
+    }
+
+    // The blurred occlusion, 1 where nothing is occluded.
+    pub(crate) fn occlusion(&self, targets: &SsaoTargets) -> Arc<ImageView<GpuImage>> {
+        targets.occlusion[0].0.clone()
+    }
+
+    pub(crate) fn sampler(&self) -> &Arc<Sampler> {
+        &self.sampler
+    }
+
+    // Records the depth prepass of `draws`. Does nothing when the depth comes from the G-buffer, whose prepass the deferred path
+    // records itself.
+    pub(crate) fn record_depth<L, A>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+        descriptor_set_allocator: &StandardDescriptorSetAllocator,
+        targets: &SsaoTargets,
+        draws: Vec<PrepassDraw>,
+    ) -> Result<()>
+    where
+        A: CommandBufferAllocator,
+    {
+        match &targets.depth_framebuffer {
+            Some(framebuffer) => {
+                self.depth_prepass
+                    .record(builder, descriptor_set_allocator, framebuffer, draws)
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Records the occlusion and blur passes, from the depth the scene was rendered with through
+    // `projection`.
+    pub(crate) fn record<L, A>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+        descriptor_set_allocator: &StandardDescriptorSetAllocator,
+        targets: &SsaoTargets,
+        projection: &TMat4<f32>,
+        settings: &SsaoSettings,
+    ) -> Result<()>
+    where
+        A: CommandBufferAllocator,
+    {
+        let viewport = viewport(targets);
+
+        let sample_count = (settings.sample_count as usize).clamp(1, MAX_SSAO_SAMPLES);
+        let mut kernel = [[0.0; 4]; MAX_SSAO_SAMPLES];
+        for (slot, offset) in kernel.iter_mut().zip(ssao_kernel(sample_count)) {
+            *slot = [offset.x, offset.y, offset.z, 0.0];
+        }
+        let uniforms = self.uniform_buffer.from_data(ssao_fs::ty::Ssao_Data {
+            projection: (*projection).into(),
+            inverse_projection: projection.try_inverse().unwrap_or_default().into(),
+            kernel,
+            radius: settings.radius,
+            bias: settings.bias,
+            intensity: settings.intensity,
+            sample_count: sample_count as i32,
+        })?;
+        let set = PersistentDescriptorSet::new(
+            descriptor_set_allocator,
+            self.occlusion_pipeline.layout().set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::image_view_sampler(
+                    0,
+                    targets.depth.clone(),
+                    self.sampler.clone(),
+                ),
+                WriteDescriptorSet::image_view_sampler(
+                    1,
+                    self.noise.view.clone(),
+                    self.noise.sampler.clone(),
+                ),
+                WriteDescriptorSet::buffer(2, uniforms),
+            ],
+        )?;
+        self.begin(
+            builder,
+            &self.occlusion_pipeline,
+            targets.occlusion[0].1.clone(),
+            set,
+            &viewport,
+        )?;
+        builder.draw(3, 1, 0, 0)?.end_render_pass()?;
+
+        // Horizontally from the first image into the second, then vertically back.
+        for (source, target, direction) in [(0, 1, [1.0, 0.0]), (1, 0, [0.0, 1.0])] {
+            let set = PersistentDescriptorSet::new(
+                descriptor_set_allocator,
+                self.blur_pipeline.layout().set_layouts()[0].clone(),
+                [WriteDescriptorSet::image_view_sampler(
+                    0,
+                    targets.occlusion[source].0.clone(),
+                    self.sampler.clone(),
+                )],
+            )?;
+            self.begin(
+                builder,
+                &self.blur_pipeline,
+                targets.occlusion[target].1.clone(),
+                set,
+                &viewport,
+            )?
+            .push_constants(
+                self.blur_pipeline.layout().clone(),
+                0,
+                ssao_blur_fs::ty::Ssao_Blur {
+                    direction,
+                    sharpness: settings.blur_sharpness,
+                },
+            )
+            .draw(3, 1, 0, 0)?
+            .end_render_pass()?;
+        }
+
+        Ok(())
+    }
+
+    fn begin<'b, L, A>(
+        &self,
+        builder: &'b mut AutoCommandBufferBuilder<L, A>,
+        pipeline: &Arc<GraphicsPipeline>,
+        framebuffer: Arc<Framebuffer>,
+        set: Arc<PersistentDescriptorSet>,
+        viewport: &Viewport,
+    ) -> Result<&'b mut AutoCommandBufferBuilder<L, A>>
+    where
+        A: CommandBufferAllocator,
+    {
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![None],
+                    ..RenderPassBeginInfo::framebuffer(framebuffer)
+                },
+                SubpassContents::Inline,
+            )?
+            .set_viewport(0, [viewport.clone()])
+            .bind_pipeline_graphics(pipeline.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                pipeline.layout().clone(),
+                0,
+                set,
+            );
+
+        Ok(builder)
+    }
+}
+
+fn viewport(targets: &SsaoTargets) -> Viewport {
+    let [width, height] = targets.occlusion[0].1.extent();
+    Viewport {
+        origin: [0.0, 0.0],
+        dimensions: [width as f32, height as f32],
+        depth_range: 0.0..1.0,
+    }
+}
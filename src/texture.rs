@@ -0,0 +1,236 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{Context, Result};
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer},
+    device::Device,
+    format::Format,
+    image::{view::ImageView, ImageDimensions, ImmutableImage, MipmapsCount},
+    sampler::{
+        Filter, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode, LOD_CLAMP_NONE,
+    },
+};
+
+use crate::{renderer::Renderer, upload::Uploader};
+
+// Decoded pixels, always converted to 8-bit RGBA, row by row from the top.
+#[derive(Debug, Clone)]
+pub struct ImageData {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+// Decodes a PNG or JPEG file.
+pub fn load_image(path: impl AsRef<Path>) -> Result<ImageData> {
+    let path = path.as_ref();
+    let image = image::open(path)
+        .with_context(|| format!("failed to load {}", path.display()))?
+        .into_rgba8();
+
+    Ok(ImageData {
+        width: image.width(),
+        height: image.height(),
+        rgba: image.into_raw(),
+    })
+}
+
+// How the values of a texture are encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    // Colors, which the sampler converts to linear values.
+    Srgb,
+    // Data such as normal maps, sampled as stored.
+    Linear,
+}
+
+impl ColorSpace {
+    fn format(self) -> Format {
+        match self {
+            ColorSpace::Srgb => Format::R8G8B8A8_SRGB,
+            ColorSpace::Linear => Format::R8G8B8A8_UNORM,
+        }
+    }
+}
+
+// How a texture is filtered and repeated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplerSettings {
+    // Filter for pixels covering less than a texel.
+    pub mag_filter: Filter,
+    // Filter for pixels covering more than a texel.
+    pub min_filter: Filter,
+    // Whether to blend between the two closest mip levels.
+    pub mipmap_mode: SamplerMipmapMode,
+    // Horizontal and vertical behavior outside the image.
+    pub address_mode: [SamplerAddressMode; 2],
+    // Maximum number of samples along the direction a texture is stretched in, clamped to what
+    // the device supports. `None`, or a device without anisotropic filtering, turns it off.
+    pub anisotropy: Option<f32>,
+}
+
+impl Default for SamplerSettings {
+    // Trilinear filtering with 16x anisotropy, repeating the image.
+    fn default() -> Self {
+        SamplerSettings {
+            mag_filter: Filter::Linear,
+            min_filter: Filter::Linear,
+            mipmap_mode: SamplerMipmapMode::Linear,
+            address_mode: [SamplerAddressMode::Repeat; 2],
+            anisotropy: Some(16.0),
+        }
+    }
+}
+
+// An image with a full mip chain and the sampler to read it with.
+#[derive(Debug)]
+pub struct Texture {
+    pub(crate) view: Arc<ImageView<ImmutableImage>>,
+    pub(crate) sampler: Arc<Sampler>,
+}
+
+// Textures are only equal to themselves, as comparing pixels is rarely what's wanted.
+impl PartialEq for Texture {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.view, &other.view) && Arc::ptr_eq(&self.sampler, &other.sampler)
+    }
+}
+
+impl Texture {
+    pub fn load(
+        renderer: &Renderer,
+        path: impl AsRef<Path>,
+        color_space: ColorSpace,
+        sampler: &SamplerSettings,
+    ) -> Result<Texture> {
+        Texture::from_image(renderer, &load_image(path)?, color_space, sampler)
+    }
+
+    // Uploads a single texture in its own submission; use `record` to upload many at once.
+    pub fn from_image(
+        renderer: &Renderer,
+        image: &ImageData,
+        color_space: ColorSpace,
+        sampler: &SamplerSettings,
+    ) -> Result<Texture> {
+        let uploader = renderer.uploader();
+        uploader.submit_graphics(|builder| {
+            Texture::record(uploader, builder, image, color_space, sampler)
+        })
+    }
+
+    // Records the upload of `image` with a full mip chain into `builder`, which must come from
+    // `Uploader::submit_graphics`: mip levels are generated by blitting each level from the
+    // previous one, which the transfer queue can't do. The texture can be used once the
+    // submission is done.
+    pub fn record(
+        uploader: &Uploader,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        image: &ImageData,
+        color_space: ColorSpace,
+        sampler: &SamplerSettings,
+    ) -> Result<Texture> {
+        Texture::new(
+            uploader,
+            builder,
+            image,
+            color_space,
+            MipmapsCount::Log2,
+            sampler,
+        )
+    }
+
+    // A single texel of the given color, bound for materials without a texture so the
+    // descriptor set layout stays the same.
+    pub(crate) fn solid(
+        uploader: &Uploader,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        rgba: [u8; 4],
+        color_space: ColorSpace,
+    ) -> Result<Texture> {
+        let image = ImageData {
+            width: 1,
+            height: 1,
+            rgba: rgba.to_vec(),
+        };
+        Texture::new(
+            uploader,
+            builder,
+            &image,
+            color_space,
+            MipmapsCount::One,
+            &SamplerSettings::default(),
+        )
+    }
+
+    // Uploads `image` without mip levels, for data generated by the renderer.
+    pub(crate) fn from_data(
+        uploader: &Uploader,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        image: &ImageData,
+        color_space: ColorSpace,
+        sampler: &SamplerSettings,
+    ) -> Result<Texture> {
+        Texture::new(
+            uploader,
+            builder,
+            image,
+            color_space,
+            MipmapsCount::One,
+            sampler,
+        )
+    }
+
+    fn new(
+        uploader: &Uploader,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        image: &ImageData,
+        color_space: ColorSpace,
+        mip_levels: MipmapsCount,
+        sampler: &SamplerSettings,
+    ) -> Result<Texture> {
+        let gpu_image = ImmutableImage::from_iter(
+            uploader.memory_allocator().as_ref(),
+            image.rgba.iter().copied(),
+            ImageDimensions::Dim2d {
+                width: image.width,
+                height: image.height,
+                array_layers: 1,
+            },
+            mip_levels,
+            color_space.format(),
+            builder,
+        )?;
+
+        Ok(Texture {
+            view: ImageView::new_default(gpu_image)?,
+            sampler: create_sampler(uploader.graphics_queue().device(), sampler)?,
+        })
+    }
+}
+
+fn create_sampler(device: &Arc<Device>, settings: &SamplerSettings) -> Result<Arc<Sampler>> {
+    let anisotropy = settings
+        .anisotropy
+        .filter(|_| device.enabled_features().sampler_anisotropy)
+        .map(|anisotropy| {
+            let max = device.physical_device().properties().max_sampler_anisotropy;
+            anisotropy.clamp(1.0, max)
+        });
+    let [u, v] = settings.address_mode;
+
+    let sampler = Sampler::new(
+        device.clone(),
+        SamplerCreateInfo {
+            mag_filter: settings.mag_filter,
+            min_filter: settings.min_filter,
+            mipmap_mode: settings.mipmap_mode,
+            address_mode: [u, v, SamplerAddressMode::ClampToEdge],
+            anisotropy,
+            lod: 0.0..=LOD_CLAMP_NONE,
+            ..Default::default()
+        },
+    )?;
+
+    Ok(sampler)
+}
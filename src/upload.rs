@@ -0,0 +1,154 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytemuck::Pod;
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer},
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
+        CopyBufferInfo, PrimaryAutoCommandBuffer,
+    },
+    device::Queue,
+    memory::allocator::StandardMemoryAllocator,
+    sync::{self, GpuFuture},
+};
+
+// Copies data into device-local memory through host-visible staging buffers. Transfers are
+// submitted to a dedicated transfer queue when the device has one, and to the graphics queue
+// otherwise. Resources it creates are shared between both queue families so they can be used
+// for rendering without ownership transfers. Uploads that need graphics commands, such as the
+// blits generating mip levels, go through `submit_graphics` instead.
+pub struct Uploader {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    command_buffer_allocator: StandardCommandBufferAllocator,
+    queue: Arc<Queue>,
+    graphics_queue: Arc<Queue>,
+    queue_family_indices: Vec<u32>,
+}
+
+impl Uploader {
+    // `queue` is the queue transfers are submitted to, `graphics_queue` the queue that renders
+    // with the uploaded resources.
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        queue: Arc<Queue>,
+        graphics_queue: Arc<Queue>,
+    ) -> Uploader {
+        let mut queue_family_indices = vec![graphics_queue.queue_family_index()];
+        if queue.queue_family_index() != graphics_queue.queue_family_index() {
+            queue_family_indices.push(queue.queue_family_index());
+        }
+
+        Uploader {
+            command_buffer_allocator: StandardCommandBufferAllocator::new(
+                queue.device().clone(),
+                Default::default(),
+            ),
+            memory_allocator,
+            queue,
+            graphics_queue,
+            queue_family_indices,
+        }
+    }
+
+    pub fn queue(&self) -> &Arc<Queue> {
+        &self.queue
+    }
+
+    pub fn graphics_queue(&self) -> &Arc<Queue> {
+        &self.graphics_queue
+    }
+
+    pub fn memory_allocator(&self) -> &Arc<StandardMemoryAllocator> {
+        &self.memory_allocator
+    }
+
+    // Queue families that must be able to access resources filled by this uploader; pass them
+    // when creating images or buffers that are written through `submit`.
+    pub fn queue_family_indices(&self) -> &[u32] {
+        &self.queue_family_indices
+    }
+
+    // Records transfer commands with `record`, submits them and waits until they are done, so
+    // the staging buffers can be freed and the results used right away.
+    pub fn submit<R>(
+        &self,
+        record: impl FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) -> Result<R>,
+    ) -> Result<R> {
+        self.submit_to(&self.queue, record)
+    }
+
+    // Like `submit`, but on the graphics queue, for commands the transfer queue may not support.
+    // Batching many uploads into one call saves a submission and a wait for each.
+    pub fn submit_graphics<R>(
+        &self,
+        record: impl FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) -> Result<R>,
+    ) -> Result<R> {
+        self.submit_to(&self.graphics_queue, record)
+    }
+
+    fn submit_to<R>(
+        &self,
+        queue: &Arc<Queue>,
+        record: impl FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) -> Result<R>,
+    ) -> Result<R> {
+        let mut builder = AutoCommandBufferBuilder::primary(
+            &self.command_buffer_allocator,
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+        let result = record(&mut builder)?;
+        let command_buffer = builder.build()?;
+
+        sync::now(queue.device().clone())
+            .then_execute(queue.clone(), command_buffer)?
+            .then_signal_fence_and_flush()?
+            .wait(None)?;
+
+        Ok(result)
+    }
+
+    // Host-visible buffer holding `data`, to be used as the source of a transfer.
+    pub fn staging_buffer<T>(&self, data: &[T]) -> Result<Arc<CpuAccessibleBuffer<[T]>>>
+    where
+        T: Pod + Send + Sync,
+    {
+        let buffer = CpuAccessibleBuffer::from_iter(
+            &self.memory_allocator,
+            BufferUsage {
+                transfer_src: true,
+                ..BufferUsage::empty()
+            },
+            false,
+            data.iter().copied(),
+        )?;
+
+        Ok(buffer)
+    }
+
+    // Records the copy of `data` into a new device-local buffer with the given usage. The
+    // buffer holds valid data once the command buffer built by `submit` has executed.
+    pub fn buffer<T>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        data: &[T],
+        usage: BufferUsage,
+    ) -> Result<Arc<DeviceLocalBuffer<[T]>>>
+    where
+        T: Pod + Send + Sync,
+    {
+        let staging = self.staging_buffer(data)?;
+        let buffer = DeviceLocalBuffer::array(
+            &self.memory_allocator,
+            data.len() as u64,
+            BufferUsage {
+                transfer_dst: true,
+                ..usage
+            },
+            self.queue_family_indices.iter().copied(),
+        )?;
+        builder.copy_buffer(CopyBufferInfo::buffers(staging, buffer.clone()))?;
+
+        Ok(buffer)
+    }
+}
@@ -1,5 +1,4 @@
 use bytemuck::{Pod, Zeroable};
-use nalgebra_glm::{identity, TMat4};
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
@@ -7,26 +6,15 @@ pub struct Vertex {
     pub position: [f32; 3],
     pub normal: [f32; 3],
     pub color: [f32; 3],
+    // Texture coordinates, with (0, 0) at the top left of the image.
+    pub uv: [f32; 2],
+    // Direction of increasing u in xyz, and the handedness of the tangent space in w: the
+    // bitangent is `cross(normal, tangent.xyz) * w`. All zero when the mesh has no tangents, which
+    // turns off normal mapping. See `geometry::generate_tangents`.
+    pub tangent: [f32; 4],
 }
 
-vulkano::impl_vertex!(Vertex, position, normal, color);
-
-#[derive(Debug, Clone)]
-pub struct MVP {
-    pub model: TMat4<f32>,
-    pub view: TMat4<f32>,
-    pub projection: TMat4<f32>,
-}
-
-impl MVP {
-    pub fn new() -> MVP {
-        MVP {
-            model: identity(),
-            view: identity(),
-            projection: identity(),
-        }
-    }
-}
+vulkano::impl_vertex!(Vertex, position, normal, color, uv, tangent);
 
 #[derive(Default, Debug, Clone)]
 pub struct AmbientLight {
@@ -34,8 +22,80 @@ pub struct AmbientLight {
     pub intensity: f32,
 }
 
-#[derive(Default, Debug, Clone)]
+// Number of lights the forward render path can shade. Must match `MAX_LIGHTS` in the fragment
+// shader.
+pub const MAX_LIGHTS: usize = 16;
+
+// Light arriving from infinitely far away, e.g. the sun.
+#[derive(Debug, Clone)]
 pub struct DirectionalLight {
-    pub position: [f32; 4],
+    // Direction the light travels in.
+    pub direction: [f32; 3],
     pub color: [f32; 3],
+    pub intensity: f32,
+    // Lights casting shadows get shadow maps in order, as long as there are layers left, see
+    // `shadow::shadow_casters`.
+    pub cast_shadows: bool,
+}
+
+// Light shining in all directions from a point, fading with the square of the distance.
+#[derive(Debug, Clone)]
+pub struct PointLight {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+    // Distance at which the light has faded out completely, or 0 for no limit.
+    pub range: f32,
+    // Only the first `shadow::MAX_POINT_SHADOWS` point lights casting shadows get a cube map.
+    pub cast_shadows: bool,
+}
+
+// A point light restricted to a cone around `direction`.
+#[derive(Debug, Clone)]
+pub struct SpotLight {
+    pub position: [f32; 3],
+    pub direction: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub range: f32,
+    // Angles from the cone's axis, in radians. The light is at full strength inside the inner
+    // angle and fades out towards the outer one.
+    pub inner_cone_angle: f32,
+    pub outer_cone_angle: f32,
+    pub cast_shadows: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum Light {
+    Directional(DirectionalLight),
+    Point(PointLight),
+    Spot(SpotLight),
+}
+
+impl Light {
+    pub fn cast_shadows(&self) -> bool {
+        match self {
+            Light::Directional(light) => light.cast_shadows,
+            Light::Point(light) => light.cast_shadows,
+            Light::Spot(light) => light.cast_shadows,
+        }
+    }
+}
+
+impl From<DirectionalLight> for Light {
+    fn from(light: DirectionalLight) -> Self {
+        Light::Directional(light)
+    }
+}
+
+impl From<PointLight> for Light {
+    fn from(light: PointLight) -> Self {
+        Light::Point(light)
+    }
+}
+
+impl From<SpotLight> for Light {
+    fn from(light: SpotLight) -> Self {
+        Light::Spot(light)
+    }
 }
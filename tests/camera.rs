@@ -0,0 +1,88 @@
+use nalgebra_glm::{vec3, vec4, Vec3};
+use rufix::camera::{Camera, CameraMode};
+use winit::event::{DeviceEvent, MouseScrollDelta};
+
+fn assert_near(actual: Vec3, expected: Vec3) {
+    assert!(
+        (actual - expected).norm() < 1e-5,
+        "expected {:?}, got {:?}",
+        expected,
+        actual
+    );
+}
+
+#[test]
+fn orbit_camera_looks_at_its_target() {
+    let mut camera = Camera::orbit(vec3(1.0, 2.0, 3.0), 4.0);
+    camera.rotate(100.0, -50.0);
+
+    assert_near(camera.eye() + camera.forward() * 4.0, vec3(1.0, 2.0, 3.0));
+    let target = camera.view() * vec4(1.0, 2.0, 3.0, 1.0);
+    assert_near(target.xyz(), vec3(0.0, 0.0, -4.0));
+}
+
+#[test]
+fn projection_uses_radians_and_vulkan_clip_space() {
+    let mut camera = Camera::default();
+    camera.fov_y = std::f32::consts::FRAC_PI_2;
+    camera.near = 0.5;
+    camera.far = 10.0;
+    camera.resize([200, 100]);
+    let projection = camera.projection();
+
+    // A 90 degree field of view maps y = -z to the edge of the image, which is at the top in
+    // Vulkan's clip space.
+    let top = projection * vec4(0.0, 2.0, -2.0, 1.0);
+    assert!((top.y / top.w + 1.0).abs() < 1e-5);
+    let right = projection * vec4(4.0, 0.0, -2.0, 1.0);
+    assert!((right.x / right.w - 1.0).abs() < 1e-5);
+
+    // Depth goes from 0 at the near plane to 1 at the far plane.
+    let near = projection * vec4(0.0, 0.0, -0.5, 1.0);
+    let far = projection * vec4(0.0, 0.0, -10.0, 1.0);
+    assert!((near.z / near.w).abs() < 1e-5);
+    assert!((far.z / far.w - 1.0).abs() < 1e-5);
+}
+
+#[test]
+fn pitch_is_clamped() {
+    let mut camera = Camera::default();
+    camera.rotate(0.0, -1e6);
+
+    assert!(camera.pitch < std::f32::consts::FRAC_PI_2);
+    assert!(camera.forward().y > 0.99);
+}
+
+#[test]
+fn wheel_zooms_the_orbit_camera() {
+    let mut camera = Camera::orbit(vec3(0.0, 0.0, 0.0), 5.0);
+    camera.handle_device_event(&DeviceEvent::MouseWheel {
+        delta: MouseScrollDelta::LineDelta(0.0, 2.0),
+    });
+
+    assert!(camera.distance < 5.0);
+}
+
+#[test]
+fn switching_modes_keeps_the_view() {
+    let mut camera = Camera::orbit(vec3(0.0, 0.0, -2.5), 2.5);
+    camera.rotate(40.0, 10.0);
+    let view = camera.view();
+
+    camera.set_mode(CameraMode::Fly);
+    assert_eq!(camera.mode, CameraMode::Fly);
+    assert!((camera.view() - view).abs().max() < 1e-5);
+
+    camera.set_mode(CameraMode::Orbit);
+    assert!((camera.view() - view).abs().max() < 1e-5);
+}
+
+#[test]
+fn fly_camera_only_moves_while_keys_are_held() {
+    let mut camera = Camera::orbit(vec3(0.0, 0.0, -2.5), 2.5);
+    camera.set_mode(CameraMode::Fly);
+    let start = camera.eye();
+
+    camera.update(1.0);
+    assert_near(camera.eye(), start);
+}
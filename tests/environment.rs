@@ -0,0 +1,42 @@
+use std::path::PathBuf;
+
+use rufix::environment::load_hdr;
+
+// A 2x1 Radiance image with flat (not run-length encoded) RGBE pixels.
+fn write_hdr(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut hdr = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n".to_vec();
+    // Mantissas scaled by 2^(exponent - 136): (1, 0.5, 0.25) and (4, 2, 0).
+    hdr.extend_from_slice(&[128, 64, 32, 129]);
+    hdr.extend_from_slice(&[128, 64, 0, 131]);
+
+    let path = dir.join("environment.hdr");
+    std::fs::write(&path, hdr).unwrap();
+    path
+}
+
+#[test]
+fn loads_linear_rgba() {
+    let image = load_hdr(write_hdr("hdr_pixels")).unwrap();
+
+    assert_eq!((image.width, image.height), (2, 1));
+    let expected = [1.0, 0.5, 0.25, 1.0, 4.0, 2.0, 0.0, 1.0];
+    assert_eq!(image.rgba.len(), expected.len());
+    for (actual, expected) in image.rgba.iter().zip(expected) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+}
+
+#[test]
+fn reports_missing_files() {
+    let error = load_hdr("does/not/exist.hdr").unwrap_err();
+
+    assert!(format!("{:#}", error).contains("does/not/exist.hdr"));
+}
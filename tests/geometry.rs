@@ -0,0 +1,156 @@
+use nalgebra_glm::{
+    mat3_to_mat4, mat4_to_mat3, normalize, rotation, scaling, translation, vec3, vec4,
+};
+use rufix::{
+    geometry::{generate_tangents, normal_matrix},
+    vertex::Vertex,
+};
+
+// A unit quad in the xy plane facing +z, with the image upright on it: v = 0 along the top edge.
+fn quad(flip_u: bool) -> (Vec<Vertex>, Vec<u32>) {
+    let corners = [
+        ([0.0, 0.0, 0.0], [0.0, 1.0]),
+        ([1.0, 0.0, 0.0], [1.0, 1.0]),
+        ([1.0, 1.0, 0.0], [1.0, 0.0]),
+        ([0.0, 1.0, 0.0], [0.0, 0.0]),
+    ];
+    let vertices = corners
+        .iter()
+        .map(|&(position, [u, v])| Vertex {
+            position,
+            normal: [0.0, 0.0, 1.0],
+            uv: [if flip_u { 1.0 - u } else { u }, v],
+            ..Default::default()
+        })
+        .collect();
+    (vertices, vec![0, 1, 2, 0, 2, 3])
+}
+
+#[test]
+fn tangents_follow_the_texture_coordinates() {
+    let (mut vertices, mut indices) = quad(false);
+    generate_tangents(&mut vertices, &mut indices);
+
+    // The bitangent, cross(normal, tangent) * w, points up the image: +y.
+    for vertex in &vertices {
+        assert_eq!(vertex.tangent, [1.0, 0.0, 0.0, 1.0]);
+    }
+}
+
+#[test]
+fn mirrored_texture_coordinates_flip_the_handedness() {
+    let (mut vertices, mut indices) = quad(true);
+    generate_tangents(&mut vertices, &mut indices);
+
+    for vertex in &vertices {
+        assert_eq!(vertex.tangent, [-1.0, 0.0, 0.0, -1.0]);
+    }
+}
+
+#[test]
+fn identical_vertices_share_tangents() {
+    // Two triangles of a bent strip, unindexed, so the shared edge appears twice.
+    let vertex = |position, uv| Vertex {
+        position,
+        normal: [0.0, 0.0, 1.0],
+        uv,
+        ..Default::default()
+    };
+    let mut vertices = vec![
+        vertex([0.0, 0.0, 0.0], [0.0, 1.0]),
+        vertex([1.0, 0.0, 0.0], [1.0, 1.0]),
+        vertex([1.0, 1.0, 0.0], [1.0, 0.0]),
+        vertex([1.0, 0.0, 0.0], [1.0, 1.0]),
+        vertex([2.0, 0.5, 0.0], [2.0, 1.0]),
+        vertex([1.0, 1.0, 0.0], [1.0, 0.0]),
+    ];
+    let mut indices: Vec<u32> = (0..6).collect();
+    generate_tangents(&mut vertices, &mut indices);
+
+    assert_eq!(vertices[1].tangent, vertices[3].tangent);
+    assert_eq!(vertices[2].tangent, vertices[5].tangent);
+    // The edge blends both triangles, whose u directions differ.
+    assert_ne!(vertices[1].tangent, vertices[0].tangent);
+    assert_ne!(vertices[1].tangent, vertices[4].tangent);
+    for vertex in &vertices {
+        let [x, y, z, w] = vertex.tangent;
+        assert!((x * x + y * y + z * z - 1.0).abs() < 1e-5 && z == 0.0 && w == 1.0);
+    }
+}
+
+#[test]
+fn mirrored_seams_split_shared_vertices() {
+    // Two quads sharing the edge at x = 1, the right one showing the image mirrored.
+    let vertex = |position, uv| Vertex {
+        position,
+        normal: [0.0, 0.0, 1.0],
+        uv,
+        ..Default::default()
+    };
+    let mut vertices = vec![
+        vertex([0.0, 0.0, 0.0], [0.0, 1.0]),
+        vertex([1.0, 0.0, 0.0], [1.0, 1.0]),
+        vertex([1.0, 1.0, 0.0], [1.0, 0.0]),
+        vertex([0.0, 1.0, 0.0], [0.0, 0.0]),
+        vertex([2.0, 0.0, 0.0], [0.0, 1.0]),
+        vertex([2.0, 1.0, 0.0], [0.0, 0.0]),
+    ];
+    let original = vertices.clone();
+    let mut indices = vec![0, 1, 2, 0, 2, 3, 1, 4, 5, 1, 5, 2];
+    let before = indices.clone();
+    generate_tangents(&mut vertices, &mut indices);
+
+    // Both vertices on the seam get a mirrored copy.
+    assert_eq!(vertices.len(), 8);
+    for (corner, (&index, &was)) in indices.iter().zip(&before).enumerate() {
+        let vertex = &vertices[index as usize];
+        assert_eq!(vertex.position, original[was as usize].position);
+        assert_eq!(vertex.uv, original[was as usize].uv);
+        let expected = if corner < 6 {
+            [1.0, 0.0, 0.0, 1.0]
+        } else {
+            [-1.0, 0.0, 0.0, -1.0]
+        };
+        assert_eq!(vertex.tangent, expected);
+    }
+}
+
+#[test]
+fn degenerate_texture_coordinates_get_some_tangent() {
+    let (mut vertices, mut indices) = quad(false);
+    for vertex in &mut vertices {
+        vertex.uv = [0.5, 0.5];
+    }
+    generate_tangents(&mut vertices, &mut indices);
+
+    for vertex in &vertices {
+        let [x, y, z, w] = vertex.tangent;
+        assert!((x * x + y * y - 1.0).abs() < 1e-5 && z == 0.0 && w == 1.0);
+    }
+}
+
+#[test]
+fn normal_matrix_keeps_normals_perpendicular_under_non_uniform_scale() {
+    let world = rotation(0.7, &vec3(0.0, 1.0, 0.0)) * scaling(&vec3(3.0, 1.0, 0.5));
+    // A slanted surface, with an edge vector lying in it.
+    let normal = normalize(&vec3(1.0, 1.0, 0.0));
+    let along = vec3(1.0, -1.0, 2.0);
+
+    let normal = (normal_matrix(&world) * vec4(normal.x, normal.y, normal.z, 0.0)).xyz();
+    let along = (world * vec4(along.x, along.y, along.z, 0.0)).xyz();
+    assert!(normal.dot(&along).abs() < 1e-5);
+}
+
+#[test]
+fn normal_matrix_of_a_rotation_is_the_rotation() {
+    let world = translation(&vec3(1.0, 2.0, 3.0)) * rotation(1.1, &normalize(&vec3(1.0, 2.0, 0.5)));
+    let expected = mat3_to_mat4(&mat4_to_mat3(&world));
+    assert!((normal_matrix(&world) - expected).abs().max() < 1e-5);
+}
+
+#[test]
+fn normal_matrix_of_a_flattened_node_keeps_directions() {
+    let world = scaling(&vec3(2.0, 0.0, 1.0));
+    let normal = normal_matrix(&world) * vec4(0.0, 1.0, 0.0, 0.0);
+    assert!(normal.y > 0.0 && normal.x == 0.0 && normal.z == 0.0);
+}
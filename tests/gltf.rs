@@ -0,0 +1,230 @@
+use std::path::PathBuf;
+
+use rufix::{
+    gltf::{load_gltf, AlphaMode, WrappingMode},
+    material::{self, ShadingModel},
+};
+use vulkano::sampler::{Filter, SamplerAddressMode};
+
+// A single triangle in the xy plane facing +z, attached to a child node. The buffer holds three
+// positions, three normals and three u16 indices.
+fn buffer() -> Vec<u8> {
+    let positions = [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+    let normals = [[0.0f32, 0.0, 1.0]; 3];
+    let indices = [0u16, 1, 2];
+
+    let mut data = Vec::new();
+    for value in positions.iter().chain(&normals).flatten() {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    for index in indices {
+        data.extend_from_slice(&index.to_le_bytes());
+    }
+    data.resize(80, 0);
+    data
+}
+
+// `buffer` is the JSON for the buffer, `texture` the JSON for an optional image, texture and
+// base color texture reference.
+fn document(buffer: &str, texture: Option<(&str, &str)>) -> String {
+    let (image_json, material_texture) = texture.unwrap_or(("", ""));
+    format!(
+        r#"{{
+    "asset": {{ "version": "2.0" }},
+    "scene": 0,
+    "scenes": [{{ "nodes": [0] }}],
+    "nodes": [
+        {{ "name": "parent", "translation": [1, 0, 0], "children": [1] }},
+        {{ "name": "child", "translation": [0, 2, 0], "mesh": 0 }}
+    ],
+    "meshes": [{{
+        "name": "triangle",
+        "primitives": [{{
+            "attributes": {{ "POSITION": 0, "NORMAL": 1 }},
+            "indices": 2,
+            "material": 0
+        }}]
+    }}],
+    "materials": [{{
+        "name": "tinted",
+        "pbrMetallicRoughness": {{
+            {material_texture}
+            "baseColorFactor": [0.5, 1, 1, 1],
+            "metallicFactor": 0.25,
+            "roughnessFactor": 0.75
+        }},
+        "alphaMode": "MASK",
+        "alphaCutoff": 0.3,
+        "doubleSided": true
+    }}],
+    {image_json}
+    "buffers": [{buffer}],
+    "bufferViews": [
+        {{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+        {{ "buffer": 0, "byteOffset": 36, "byteLength": 36 }},
+        {{ "buffer": 0, "byteOffset": 72, "byteLength": 6 }}
+    ],
+    "accessors": [
+        {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+           "min": [0, 0, 0], "max": [1, 1, 0] }},
+        {{ "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC3" }},
+        {{ "bufferView": 2, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+    ]
+}}"#
+    )
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn write_gltf(name: &str) -> PathBuf {
+    let dir = temp_dir(name);
+    std::fs::write(dir.join("model.bin"), buffer()).unwrap();
+    let path = dir.join("model.gltf");
+    let json = document(r#"{ "uri": "model.bin", "byteLength": 80 }"#, None);
+    std::fs::write(&path, json).unwrap();
+    path
+}
+
+// A 2x1 RGB image: one red and one blue pixel.
+fn png() -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut data, 2, 1);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&[255, 0, 0, 0, 0, 255]).unwrap();
+    writer.finish().unwrap();
+    data
+}
+
+// Same scene as `write_gltf`, packed into a single GLB file with the PNG stored in the binary
+// chunk after the geometry.
+fn write_glb(name: &str) -> PathBuf {
+    let mut bin = buffer();
+    let image = png();
+    let image_offset = bin.len();
+    bin.extend_from_slice(&image);
+    while !bin.len().is_multiple_of(4) {
+        bin.push(0);
+    }
+
+    let texture = r#""images": [{ "bufferView": 3, "mimeType": "image/png" }],
+    "samplers": [{ "wrapS": 33071 }],
+    "textures": [{ "source": 0, "sampler": 0 }],"#;
+    let mut json = document(
+        &format!(r#"{{ "byteLength": {} }}"#, bin.len()),
+        Some((texture, r#""baseColorTexture": { "index": 0 },"#)),
+    );
+    // The image view is appended to the list of buffer views.
+    json = json.replace(
+        r#"{ "buffer": 0, "byteOffset": 72, "byteLength": 6 }"#,
+        &format!(
+            r#"{{ "buffer": 0, "byteOffset": 72, "byteLength": 6 }},
+        {{ "buffer": 0, "byteOffset": {}, "byteLength": {} }}"#,
+            image_offset,
+            image.len()
+        ),
+    );
+    let mut json = json.into_bytes();
+    while !json.len().is_multiple_of(4) {
+        json.push(b' ');
+    }
+
+    let mut glb = Vec::new();
+    glb.extend_from_slice(b"glTF");
+    glb.extend_from_slice(&2u32.to_le_bytes());
+    glb.extend_from_slice(&((12 + 8 + json.len() + 8 + bin.len()) as u32).to_le_bytes());
+    glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    glb.extend_from_slice(b"JSON");
+    glb.extend_from_slice(&json);
+    glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+    glb.extend_from_slice(b"BIN\0");
+    glb.extend_from_slice(&bin);
+
+    let path = temp_dir(name).join("model.glb");
+    std::fs::write(&path, glb).unwrap();
+    path
+}
+
+#[test]
+fn keeps_the_node_hierarchy() {
+    let scene = load_gltf(write_gltf("gltf_hierarchy")).unwrap();
+
+    assert_eq!(scene.roots, [0]);
+    assert_eq!(scene.nodes[0].name.as_deref(), Some("parent"));
+    assert_eq!(scene.nodes[0].children, [1]);
+    assert_eq!(scene.nodes[1].parent, Some(0));
+    assert_eq!(scene.nodes[1].mesh, Some(0));
+
+    let world = scene.world_transforms()[1];
+    assert_eq!(
+        [world[(0, 3)], world[(1, 3)], world[(2, 3)]],
+        [1.0, 2.0, 0.0]
+    );
+}
+
+#[test]
+fn reads_pbr_materials() {
+    let scene = load_gltf(write_gltf("gltf_materials")).unwrap();
+
+    let material = &scene.materials[0];
+    assert_eq!(material.name.as_deref(), Some("tinted"));
+    assert_eq!(material.base_color_factor, [0.5, 1.0, 1.0, 1.0]);
+    assert_eq!(material.base_color_texture, None);
+    assert_eq!(material.metallic_factor, 0.25);
+    assert_eq!(material.roughness_factor, 0.75);
+    assert_eq!(material.alpha_mode, AlphaMode::Mask);
+    assert_eq!(material.alpha_cutoff, 0.3);
+    assert!(material.double_sided);
+    assert_eq!(scene.meshes[0].primitives[0].material, Some(0));
+
+    let material = material.to_material();
+    assert_eq!(material.shading, ShadingModel::Pbr);
+    assert_eq!(material.base_color, [0.5, 1.0, 1.0]);
+    assert_eq!((material.metallic, material.roughness), (0.25, 0.75));
+    assert_eq!(
+        material.alpha_mode,
+        material::AlphaMode::Mask { cutoff: 0.3 }
+    );
+    assert!(material.double_sided);
+}
+
+#[test]
+fn decodes_textures_embedded_in_glb() {
+    let scene = load_gltf(write_glb("gltf_glb")).unwrap();
+
+    assert_eq!(scene.materials[0].base_color_texture, Some(0));
+    let texture = scene.textures[0];
+    assert_eq!(texture.sampler.wrap_s, WrappingMode::ClampToEdge);
+    assert_eq!(texture.sampler.wrap_t, WrappingMode::Repeat);
+    let settings = texture.sampler.to_settings();
+    assert_eq!(
+        settings.address_mode,
+        [SamplerAddressMode::ClampToEdge, SamplerAddressMode::Repeat]
+    );
+    assert_eq!(settings.mag_filter, Filter::Linear);
+
+    let image = &scene.images[texture.image];
+    assert_eq!((image.width, image.height), (2, 1));
+    assert_eq!(image.rgba, [255, 0, 0, 255, 0, 0, 255, 255]);
+}
+
+#[test]
+fn flattens_meshes_into_world_space() {
+    let vertices = load_gltf(write_glb("gltf_flatten")).unwrap().flatten();
+
+    assert_eq!(vertices.len(), 3);
+    assert_eq!(vertices[0].position, [1.0, 2.0, 0.0]);
+    assert_eq!(vertices[1].position, [2.0, 2.0, 0.0]);
+    assert_eq!(vertices[2].position, [1.0, 3.0, 0.0]);
+    for vertex in &vertices {
+        assert_eq!(vertex.normal, [0.0, 0.0, 1.0]);
+        assert_eq!(vertex.color, [0.5, 1.0, 1.0]);
+        // The primitive has no texture coordinates.
+        assert_eq!(vertex.uv, [0.0, 0.0]);
+    }
+}
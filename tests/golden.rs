@@ -0,0 +1,917 @@
+// Golden-image regression tests.
+//
+// Each test renders a fixed scene with a headless renderer and compares the result with a
+// reference image checked in under `tests/golden/`. Set `RUFIX_BLESS=1` to write the current
+// output as the new reference instead of comparing against it. When a comparison fails, the
+// actual image and a diff image are written next to each other under the cargo test temp
+// directory so the change can be inspected.
+//
+// The tests need a Vulkan device, so they are ignored by default and run with
+// `cargo test --test golden -- --ignored`, where a missing device fails them.
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use nalgebra_glm::{scaling, translation, vec3};
+use rufix::{
+    environment::HdrImage,
+    material::{AlphaMode, Material},
+    post::{neutral_lut, Bloom, ColorGrading, Fxaa, ToneMapOperator, ToneMapping, Vignette},
+    scene::{demo_transform, Node},
+    texture::{ColorSpace, ImageData, SamplerSettings, Texture},
+    vertex::{DirectionalLight, PointLight, SpotLight, MAX_LIGHTS},
+    Environment, Mesh, RenderPath, RenderTarget, Renderer, Scene,
+};
+use vulkano::{
+    instance::{Instance, InstanceCreateInfo},
+    sampler::{Filter, SamplerAddressMode},
+    VulkanLibrary,
+};
+
+const WIDTH: u32 = 128;
+const HEIGHT: u32 = 128;
+
+// Software and hardware rasterizers disagree slightly on edges and rounding, so a pixel only
+// counts as different when a channel is off by more than `channel`, and a handful of such pixels
+// is accepted.
+struct Tolerance {
+    channel: u8,
+    max_mismatched_pixels: usize,
+}
+
+const DEFAULT_TOLERANCE: Tolerance = Tolerance {
+    channel: 2,
+    max_mismatched_pixels: 16,
+};
+
+struct Image {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn lit_cube_at_rest() {
+    assert_matches_golden(
+        "lit_cube_at_rest",
+        |_, cube| Scene::demo(0.0, cube),
+        &DEFAULT_TOLERANCE,
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn lit_cube_rotated() {
+    assert_matches_golden(
+        "lit_cube_rotated",
+        |_, cube| Scene::demo(2.5, cube),
+        &DEFAULT_TOLERANCE,
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn point_and_spot_lights() {
+    assert_matches_golden(
+        "point_and_spot_lights",
+        |_, cube| {
+            let mut scene = Scene::demo(2.5, cube);
+            scene.lights = vec![
+                PointLight {
+                    position: [1.5, 0.0, -1.0],
+                    color: [1.0, 0.2, 0.2],
+                    intensity: 4.0,
+                    range: 5.0,
+                    cast_shadows: false,
+                }
+                .into(),
+                SpotLight {
+                    position: [0.0, 2.0, 0.0],
+                    direction: [0.0, -2.0, -2.5],
+                    color: [0.2, 0.4, 1.0],
+                    intensity: 8.0,
+                    range: 0.0,
+                    inner_cone_angle: 0.2,
+                    outer_cone_angle: 0.4,
+                    cast_shadows: false,
+                }
+                .into(),
+            ];
+            scene
+        },
+        &DEFAULT_TOLERANCE,
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn pbr_cube() {
+    assert_matches_golden(
+        "pbr_cube",
+        |_, cube| {
+            let mut scene = Scene::demo(2.5, cube);
+            let node = scene.nodes().next().unwrap().0;
+            scene
+                .node_mut(node)
+                .set_material(Material::pbr([0.9, 0.6, 0.2], 1.0, 0.3));
+            scene
+        },
+        &DEFAULT_TOLERANCE,
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn non_uniform_scale() {
+    assert_matches_golden(
+        "non_uniform_scale",
+        |_, cube| {
+            // Squashing the spinning cube through its parent slants its faces, which shows normals
+            // transformed like positions as wrongly lit faces.
+            let mut scene = Scene::new();
+            let parent = scene.add_node(Node {
+                transform: scaling(&vec3(1.6, 0.6, 1.0)),
+                ..Default::default()
+            });
+            scene.add_node(Node {
+                parent: Some(parent),
+                transform: demo_transform(2.5),
+                mesh: Some(cube),
+                material: Material::glossy([1.0, 1.0, 1.0], 32.0),
+                ..Default::default()
+            });
+            scene
+        },
+        &DEFAULT_TOLERANCE,
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn reinhard_tone_mapping() {
+    assert_matches_golden(
+        "reinhard_tone_mapping",
+        |_, cube| {
+            // Bright enough to go well past 1 before tone mapping.
+            let mut scene = Scene::demo(2.5, cube);
+            scene.post_effects = vec![ToneMapping {
+                operator: ToneMapOperator::Reinhard,
+                exposure: 4.0,
+                ..Default::default()
+            }
+            .into()];
+            scene
+        },
+        &DEFAULT_TOLERANCE,
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn post_effects() {
+    assert_matches_golden(
+        "post_effects",
+        |renderer, cube| {
+            // A lookup table swapping red and blue.
+            let mut lut = neutral_lut(16);
+            for texel in lut.rgba.chunks_mut(4) {
+                texel.swap(0, 2);
+            }
+            let sampler = SamplerSettings {
+                address_mode: [SamplerAddressMode::ClampToEdge; 2],
+                anisotropy: None,
+                ..Default::default()
+            };
+            let lut = Texture::from_image(renderer, &lut, ColorSpace::Linear, &sampler).unwrap();
+
+            let mut scene = Scene::demo(2.5, cube);
+            scene.lights[0] = DirectionalLight {
+                direction: [4.0, -4.0, -2.5],
+                color: [1.0, 0.8, 0.6],
+                intensity: 4.0,
+                cast_shadows: false,
+            }
+            .into();
+            scene.post_effects = vec![
+                Bloom {
+                    intensity: 0.5,
+                    ..Default::default()
+                }
+                .into(),
+                ToneMapping::default().into(),
+                ColorGrading::new(Arc::new(lut)).unwrap().into(),
+                Vignette::default().into(),
+                Fxaa::default().into(),
+            ];
+            scene
+        },
+        &DEFAULT_TOLERANCE,
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn disabled_post_effects_are_skipped() {
+    let render_with = |effects: &dyn Fn(&mut Scene)| {
+        render(|_, cube| {
+            let mut scene = Scene::demo(2.5, cube);
+            effects(&mut scene);
+            scene
+        })
+    };
+    let plain = render_with(&|_| {});
+    let toggled = render_with(&|scene| {
+        scene.post_effects.push(Vignette::default().into());
+        scene.post_effects.last_mut().unwrap().set_enabled(false);
+    });
+    let vignetted = render_with(&|scene| scene.post_effects.push(Vignette::default().into()));
+
+    assert_eq!(plain.rgba, toggled.rgba);
+    assert_ne!(plain.rgba, vignetted.rgba);
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn shadowed_cube() {
+    assert_matches_golden(
+        "shadowed_cube",
+        |_, cube| shadowed_scene(cube),
+        &DEFAULT_TOLERANCE,
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn shadow_cascades() {
+    assert_matches_golden(
+        "shadow_cascades",
+        |_, cube| {
+            let mut scene = shadowed_scene(cube);
+            // Short enough for the floor to span all cascades.
+            scene.shadows.shadow_distance = 6.0;
+            scene.shadows.debug_cascades = true;
+            scene
+        },
+        &DEFAULT_TOLERANCE,
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn pbr_textured_cube() {
+    assert_matches_golden(
+        "pbr_textured_cube",
+        |renderer, cube| {
+            // Two columns per texture, so each face shows both halves side by side.
+            let texture = |left: [u8; 4], right: [u8; 4], color_space| {
+                let image = ImageData {
+                    width: 2,
+                    height: 1,
+                    rgba: [left, right].concat(),
+                };
+                let sampler = SamplerSettings {
+                    mag_filter: Filter::Nearest,
+                    ..Default::default()
+                };
+                Some(Arc::new(
+                    Texture::from_image(renderer, &image, color_space, &sampler).unwrap(),
+                ))
+            };
+
+            let mut scene = Scene::demo(2.5, cube);
+            let node = scene.nodes().next().unwrap().0;
+            scene.node_mut(node).set_material(Material {
+                // Rough dielectric on the left, polished metal on the right.
+                metallic_roughness_texture: texture(
+                    [0, 255, 0, 255],
+                    [0, 60, 255, 255],
+                    ColorSpace::Linear,
+                ),
+                occlusion_texture: texture([255; 4], [100, 100, 100, 255], ColorSpace::Linear),
+                emissive: [1.0, 1.0, 1.0],
+                emissive_texture: texture([0, 0, 0, 255], [40, 10, 0, 255], ColorSpace::Srgb),
+                ..Material::pbr([0.9, 0.6, 0.2], 1.0, 1.0)
+            });
+            scene
+        },
+        &DEFAULT_TOLERANCE,
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn environment_lighting() {
+    assert_matches_golden(
+        "environment_lighting",
+        environment_scene,
+        &DEFAULT_TOLERANCE,
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn environment_maps_outlive_their_precompute() {
+    // The maps are computed in their own submission; frames rendered afterwards must still see
+    // them rather than cleared or undefined images.
+    let mut renderer = headless_renderer();
+    let cube = Arc::new(Mesh::cube(renderer.uploader()).unwrap());
+    let scene = environment_scene(&mut renderer, cube.clone());
+    let mut unlit = environment_scene(&mut renderer, cube);
+    unlit.environment = None;
+    unlit.ambient_light.intensity = 0.0;
+
+    let mut render = |scene: &Scene| {
+        renderer.render_frame(scene).unwrap();
+        renderer.read_pixels().unwrap()
+    };
+    let first = render(&scene);
+    let dark = render(&unlit);
+    let second = render(&scene);
+
+    assert_eq!(first, second);
+    let brighter = first
+        .chunks(4)
+        .zip(dark.chunks(4))
+        .filter(|(lit, dark)| {
+            lit[..3]
+                .iter()
+                .zip(*dark)
+                .any(|(l, d)| *l > d.saturating_add(8))
+        })
+        .count();
+    assert!(brighter > (WIDTH * HEIGHT / 20) as usize);
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn point_light_shadows() {
+    assert_matches_golden(
+        "point_light_shadows",
+        |_, cube| {
+            let mut scene = shadowed_scene(cube);
+            scene.lights = vec![PointLight {
+                position: [1.0, 0.5, -2.0],
+                color: [1.0, 0.9, 0.8],
+                intensity: 6.0,
+                range: 0.0,
+                cast_shadows: true,
+            }
+            .into()];
+            scene
+        },
+        &DEFAULT_TOLERANCE,
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn textured_cube() {
+    assert_matches_golden(
+        "textured_cube",
+        |renderer, cube| {
+            // A 4x4 checkerboard, sharp at any distance.
+            let rgba = (0..16)
+                .flat_map(|i| match (i % 4 + i / 4) % 2 {
+                    0 => [230, 230, 230, 255],
+                    _ => [40, 90, 200, 255],
+                })
+                .collect();
+            let image = ImageData {
+                width: 4,
+                height: 4,
+                rgba,
+            };
+            let sampler = SamplerSettings {
+                mag_filter: Filter::Nearest,
+                ..Default::default()
+            };
+            let texture =
+                Texture::from_image(renderer, &image, ColorSpace::Srgb, &sampler).unwrap();
+
+            let mut scene = Scene::demo(2.5, cube);
+            let node = scene.nodes().next().unwrap().0;
+            scene.node_mut(node).set_material(Material {
+                base_color_texture: Some(Arc::new(texture)),
+                ..Default::default()
+            });
+            scene
+        },
+        &DEFAULT_TOLERANCE,
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn normal_mapped_cube() {
+    assert_matches_golden(
+        "normal_mapped_cube",
+        |renderer, cube| {
+            // Ridges running along v: normals tilt left and right across each face.
+            let rgba = (0..64)
+                .flat_map(|i| match i % 8 {
+                    0..=3 => [60, 128, 240, 255],
+                    _ => [196, 128, 240, 255],
+                })
+                .collect();
+            let image = ImageData {
+                width: 8,
+                height: 8,
+                rgba,
+            };
+            let texture =
+                Texture::from_image(renderer, &image, ColorSpace::Linear, &Default::default())
+                    .unwrap();
+
+            let mut scene = Scene::demo(2.5, cube);
+            let node = scene.nodes().next().unwrap().0;
+            scene.node_mut(node).set_material(Material {
+                normal_texture: Some(Arc::new(texture)),
+                ..Material::glossy([0.8, 0.8, 0.8], 32.0)
+            });
+            scene
+        },
+        &DEFAULT_TOLERANCE,
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn multisampling_smooths_edges_only() {
+    let mut renderer = headless_renderer();
+    assert_eq!(renderer.samples(), 1);
+    assert!(renderer.set_samples(3).is_err());
+    let cube = Arc::new(Mesh::cube(renderer.uploader()).unwrap());
+    let scene = Scene::demo(2.5, cube);
+
+    let mut render = |samples| {
+        renderer.set_samples(samples).unwrap();
+        assert_eq!(renderer.samples(), samples);
+        renderer.render_frame(&scene).unwrap();
+        renderer.read_pixels().unwrap()
+    };
+    let aliased = render(1);
+    let smoothed = render(4);
+
+    // Inside faces every sample sees the same surface; only pixels along edges change.
+    let changed = aliased
+        .chunks(4)
+        .zip(smoothed.chunks(4))
+        .filter(|(a, s)| a.iter().zip(*s).any(|(a, s)| a.abs_diff(*s) > 2))
+        .count();
+    assert!(changed > 0);
+    assert!(changed < (WIDTH * HEIGHT / 10) as usize);
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn ambient_occlusion() {
+    assert_matches_golden(
+        "ambient_occlusion",
+        |_, cube| ambient_scene(cube),
+        &DEFAULT_TOLERANCE,
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn ambient_occlusion_only_darkens() {
+    let occluded = render(|_, cube| ambient_scene(cube));
+    let unoccluded = render(|_, cube| {
+        let mut scene = ambient_scene(cube);
+        scene.ssao.enabled = false;
+        scene
+    });
+
+    let pixels = || occluded.rgba.chunks(4).zip(unoccluded.rgba.chunks(4));
+    assert!(pixels().all(|(o, u)| o.iter().zip(u).all(|(o, u)| *o <= u.saturating_add(2))));
+    assert!(pixels().any(|(o, u)| o.iter().zip(u).any(|(o, u)| o + 8 < *u)));
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn ambient_occlusion_matches_across_depth_sources() {
+    // Without multisampling the forward path renders the depth SSAO reads into its depth buffer,
+    // with it into a separate image, and the deferred path reads the G-buffer's.
+    let occluded = |path, samples| {
+        render(|renderer, cube| {
+            renderer.set_render_path(path).unwrap();
+            renderer.set_samples(samples).unwrap();
+            ambient_scene(cube)
+        })
+    };
+    let forward = occluded(RenderPath::Forward, 1);
+
+    // Multisampling smooths the edges, and the deferred path has a more precise depth buffer
+    // and half-float normals.
+    let cases = [
+        ("multisampled forward", occluded(RenderPath::Forward, 4), 10),
+        ("deferred", occluded(RenderPath::Deferred, 1), 50),
+    ];
+    for (name, image, fraction) in cases {
+        let tolerance = Tolerance {
+            channel: 4,
+            max_mismatched_pixels: (WIDTH * HEIGHT / fraction) as usize,
+        };
+        let (mismatched, _) = compare(&image, &forward, &tolerance);
+        assert!(
+            mismatched <= tolerance.max_mismatched_pixels,
+            "{} pixels differ between the forward and {} paths",
+            mismatched,
+            name
+        );
+    }
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn deferred_many_lights() {
+    assert_matches_golden(
+        "deferred_many_lights",
+        |renderer, cube| {
+            renderer.set_render_path(RenderPath::Deferred).unwrap();
+            many_lights_scene(cube)
+        },
+        &DEFAULT_TOLERANCE,
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn deferred_matches_forward() {
+    let render_path = |path| {
+        render(|renderer, cube| {
+            renderer.set_samples(1).unwrap();
+            renderer.set_render_path(path).unwrap();
+            assert_eq!(renderer.render_path(), path);
+            let mut scene = shadowed_scene(cube.clone());
+            scene.add_node(Node {
+                transform: translation(&vec3(1.5, -1.0, -2.0)) * scaling(&vec3(0.3, 0.3, 0.3)),
+                mesh: Some(cube),
+                material: Material::pbr([1.0, 0.77, 0.34], 1.0, 0.3),
+                ..Default::default()
+            });
+            scene
+        })
+    };
+    let forward = render_path(RenderPath::Forward);
+    let deferred = render_path(RenderPath::Deferred);
+
+    // Positions come from the depth buffer and normals from half floats, so shadow edges and
+    // highlights may move by a pixel.
+    let tolerance = Tolerance {
+        channel: 4,
+        max_mismatched_pixels: (WIDTH * HEIGHT / 50) as usize,
+    };
+    let (mismatched, _) = compare(&deferred, &forward, &tolerance);
+    assert!(
+        mismatched <= tolerance.max_mismatched_pixels,
+        "{} pixels differ between the forward and deferred paths",
+        mismatched
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn clustered_many_lights() {
+    assert_matches_golden(
+        "clustered_many_lights",
+        |renderer, cube| {
+            renderer.set_render_path(RenderPath::Clustered).unwrap();
+            many_lights_scene(cube)
+        },
+        &DEFAULT_TOLERANCE,
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn clustered_matches_deferred() {
+    let render_path = |path| {
+        render(|renderer, cube| {
+            renderer.set_samples(1).unwrap();
+            renderer.set_render_path(path).unwrap();
+            many_lights_scene(cube)
+        })
+    };
+    let deferred = render_path(RenderPath::Deferred);
+    let clustered = render_path(RenderPath::Clustered);
+
+    // Light culling must not leave out any light reaching a pixel, so only the precision of
+    // the G-buffer makes a difference.
+    let tolerance = Tolerance {
+        channel: 4,
+        max_mismatched_pixels: (WIDTH * HEIGHT / 50) as usize,
+    };
+    let (mismatched, _) = compare(&clustered, &deferred, &tolerance);
+    assert!(
+        mismatched <= tolerance.max_mismatched_pixels,
+        "{} pixels differ between the deferred and clustered paths",
+        mismatched
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn clustered_transparency_multisampled() {
+    assert_matches_golden(
+        "clustered_transparency_multisampled",
+        |renderer, cube| {
+            renderer.set_render_path(RenderPath::Clustered).unwrap();
+            renderer.set_samples(4).unwrap();
+            transparent_scene(cube)
+        },
+        &DEFAULT_TOLERANCE,
+    );
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn only_the_forward_path_is_limited_to_max_lights() {
+    let mut renderer = headless_renderer();
+    let cube = Arc::new(Mesh::cube(renderer.uploader()).unwrap());
+    let scene = many_lights_scene(cube);
+    assert!(scene.lights.len() > MAX_LIGHTS);
+
+    assert!(renderer.render_frame(&scene).is_err());
+    renderer.set_render_path(RenderPath::Deferred).unwrap();
+    renderer.render_frame(&scene).unwrap();
+    renderer.set_render_path(RenderPath::Clustered).unwrap();
+    renderer.render_frame(&scene).unwrap();
+}
+
+// The demo cube above a floor, lit by a shadow casting sun.
+fn shadowed_scene(cube: Arc<Mesh>) -> Scene {
+    let mut scene = Scene::demo(2.5, cube.clone());
+    // A flattened cube below the spinning one serves as the floor.
+    scene.add_node(Node {
+        transform: translation(&vec3(0.0, -2.0, -2.5)) * scaling(&vec3(4.0, 0.1, 4.0)),
+        mesh: Some(cube),
+        ..Default::default()
+    });
+    scene.lights = vec![DirectionalLight {
+        direction: [0.5, -4.0, -1.0],
+        color: [1.0, 1.0, 1.0],
+        intensity: 1.0,
+        cast_shadows: true,
+    }
+    .into()];
+    scene
+}
+
+// The demo cube above a floor, lit by a grid of small colored point lights.
+fn many_lights_scene(cube: Arc<Mesh>) -> Scene {
+    let mut scene = shadowed_scene(cube);
+    scene.ambient_light.intensity = 0.05;
+    scene.lights.clear();
+    for x in 0..10 {
+        for z in 0..10 {
+            let hue = (x + z) as f32 / 18.0;
+            scene.lights.push(
+                PointLight {
+                    position: [x as f32 * 0.8 - 3.6, -1.5, z as f32 * 0.8 - 6.1],
+                    color: [1.0 - hue, 0.5, hue],
+                    intensity: 0.5,
+                    range: 1.5,
+                    cast_shadows: false,
+                }
+                .into(),
+            );
+        }
+    }
+    scene
+}
+
+// `many_lights_scene` seen through two overlapping, half transparent panes. The nearer one is
+// added first, so they are only blended in the right order when the renderer sorts them.
+fn transparent_scene(cube: Arc<Mesh>) -> Scene {
+    let mut scene = many_lights_scene(cube.clone());
+    let panes = [
+        (vec3(-0.2, 0.0, -1.2), [1.0, 0.3, 0.3]),
+        (vec3(0.2, 0.2, -1.6), [0.3, 0.3, 1.0]),
+    ];
+    for (position, color) in panes {
+        scene.add_node(Node {
+            transform: translation(&position) * scaling(&vec3(0.4, 0.4, 0.01)),
+            mesh: Some(cube.clone()),
+            material: Material {
+                alpha: 0.5,
+                alpha_mode: AlphaMode::Blend,
+                double_sided: true,
+                ..Material::pbr(color, 0.0, 0.5)
+            },
+            ..Default::default()
+        });
+    }
+    scene
+}
+
+// A gold cube lit by a sky that is blue above the horizon and brown below, with no lights.
+fn environment_scene(renderer: &mut Renderer, cube: Arc<Mesh>) -> Scene {
+    let (width, height) = (16, 8);
+    let rgba = (0..width * height)
+        .flat_map(|i| {
+            if i / width < height / 2 {
+                [0.4, 0.6, 1.0, 1.0]
+            } else {
+                [0.3, 0.2, 0.1, 1.0]
+            }
+        })
+        .collect();
+    let sky = HdrImage {
+        width,
+        height,
+        rgba,
+    };
+
+    let mut scene = Scene::demo(2.5, cube);
+    let node = scene.nodes().next().unwrap().0;
+    scene
+        .node_mut(node)
+        .set_material(Material::pbr([1.0, 0.77, 0.34], 1.0, 0.3));
+    scene.lights.clear();
+    scene.environment = Some(Arc::new(
+        Environment::from_equirect(renderer, &sky).unwrap(),
+    ));
+    scene.environment_intensity = 1.0;
+    scene
+}
+
+// A cube resting on a floor, lit by ambient light alone so occlusion is all that shades it, with
+// SSAO turned on.
+fn ambient_scene(cube: Arc<Mesh>) -> Scene {
+    let mut scene = Scene::new();
+    scene.add_node(Node {
+        transform: translation(&vec3(0.0, -0.5, -2.5)) * scaling(&vec3(0.5, 0.5, 0.5)),
+        mesh: Some(cube.clone()),
+        ..Default::default()
+    });
+    scene.add_node(Node {
+        transform: translation(&vec3(0.0, -1.05, -2.5)) * scaling(&vec3(3.0, 0.05, 3.0)),
+        mesh: Some(cube),
+        ..Default::default()
+    });
+    scene.ambient_light.intensity = 1.0;
+    scene.lights.clear();
+    scene.ssao.enabled = true;
+    scene
+}
+
+// `scene` builds the scene to render around the demo cube, using the renderer for any other
+// resources it needs.
+fn assert_matches_golden(
+    name: &str,
+    scene: impl Fn(&mut Renderer, Arc<Mesh>) -> Scene,
+    tolerance: &Tolerance,
+) {
+    let actual = render(scene);
+    let reference_path = golden_dir().join(format!("{}.png", name));
+
+    if std::env::var_os("RUFIX_BLESS").is_some() {
+        write_png(&reference_path, &actual);
+        eprintln!("blessed {}", reference_path.display());
+        return;
+    }
+
+    assert!(
+        reference_path.exists(),
+        "missing reference image {}, bless it with `RUFIX_BLESS=1` on a machine with a Vulkan device",
+        reference_path.display()
+    );
+    let expected = read_png(&reference_path);
+    assert_eq!(
+        (actual.width, actual.height),
+        (expected.width, expected.height),
+        "`{}` was rendered at a different size than its reference",
+        name
+    );
+
+    let (mismatched, diff) = compare(&actual, &expected, tolerance);
+    if mismatched > tolerance.max_mismatched_pixels {
+        let out_dir = diff_dir();
+        let actual_path = out_dir.join(format!("{}.actual.png", name));
+        let diff_path = out_dir.join(format!("{}.diff.png", name));
+        write_png(&actual_path, &actual);
+        write_png(&diff_path, &diff);
+        panic!(
+            "`{}` differs from its reference in {} pixels (at most {} allowed)\n  actual: {}\n  diff: {}",
+            name,
+            mismatched,
+            tolerance.max_mismatched_pixels,
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+// Returns the number of pixels outside the tolerance and an image highlighting them in red over
+// a dimmed copy of the reference.
+fn compare(actual: &Image, expected: &Image, tolerance: &Tolerance) -> (usize, Image) {
+    let mut mismatched = 0;
+    let mut diff = Vec::with_capacity(expected.rgba.len());
+
+    for (a, e) in actual.rgba.chunks(4).zip(expected.rgba.chunks(4)) {
+        let differs = a
+            .iter()
+            .zip(e)
+            .any(|(a, e)| a.abs_diff(*e) > tolerance.channel);
+        if differs {
+            mismatched += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            let luma = (e[0] as u32 + e[1] as u32 + e[2] as u32) / 3;
+            let dimmed = (luma / 4) as u8;
+            diff.extend_from_slice(&[dimmed, dimmed, dimmed, 255]);
+        }
+    }
+
+    (
+        mismatched,
+        Image {
+            width: expected.width,
+            height: expected.height,
+            rgba: diff,
+        },
+    )
+}
+
+fn render(scene: impl Fn(&mut Renderer, Arc<Mesh>) -> Scene) -> Image {
+    let mut renderer = headless_renderer();
+    let cube = Arc::new(Mesh::cube(renderer.uploader()).unwrap());
+    let scene = scene(&mut renderer, cube);
+    renderer.render_frame(&scene).unwrap();
+
+    Image {
+        width: WIDTH,
+        height: HEIGHT,
+        rgba: renderer.read_pixels().unwrap(),
+    }
+}
+
+// Fails the test rather than skipping it when there's no device to render with.
+fn headless_renderer() -> Renderer {
+    assert!(
+        vulkan_available(),
+        "no Vulkan device available, the golden tests need one"
+    );
+    Renderer::new(RenderTarget::Headless {
+        width: WIDTH,
+        height: HEIGHT,
+    })
+    .unwrap()
+}
+
+fn vulkan_available() -> bool {
+    let library = match VulkanLibrary::new() {
+        Ok(library) => library,
+        Err(_) => return false,
+    };
+    let instance = match Instance::new(library, InstanceCreateInfo::default()) {
+        Ok(instance) => instance,
+        Err(_) => return false,
+    };
+    instance
+        .enumerate_physical_devices()
+        .map(|mut devices| devices.next().is_some())
+        .unwrap_or(false)
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+}
+
+fn diff_dir() -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden-diffs");
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn read_png(path: &Path) -> Image {
+    let decoder = png::Decoder::new(File::open(path).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+    let mut rgba = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut rgba).unwrap();
+    assert_eq!(
+        (info.color_type, info.bit_depth),
+        (png::ColorType::Rgba, png::BitDepth::Eight),
+        "{} is not an 8-bit RGBA image",
+        path.display()
+    );
+    rgba.truncate(info.buffer_size());
+
+    Image {
+        width: info.width,
+        height: info.height,
+        rgba,
+    }
+}
+
+fn write_png(path: &Path, image: &Image) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    rufix::headless::write_png(path, image.width, image.height, &image.rgba).unwrap();
+}
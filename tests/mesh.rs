@@ -0,0 +1,83 @@
+use rufix::{
+    mesh::{check_triangle_list, deduplicate, Indices},
+    vertex::Vertex,
+};
+
+fn vertex(position: [f32; 3]) -> Vertex {
+    Vertex {
+        position,
+        normal: [0.0, 0.0, 1.0],
+        color: [1.0, 1.0, 1.0],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn shares_repeated_vertices() {
+    // A quad split into two triangles sharing an edge.
+    let triangles = [
+        vertex([0.0, 0.0, 0.0]),
+        vertex([1.0, 0.0, 0.0]),
+        vertex([1.0, 1.0, 0.0]),
+        vertex([0.0, 0.0, 0.0]),
+        vertex([1.0, 1.0, 0.0]),
+        vertex([0.0, 1.0, 0.0]),
+    ];
+    let (vertices, indices) = deduplicate(&triangles);
+
+    assert_eq!(vertices.len(), 4);
+    assert_eq!(indices, [0, 1, 2, 0, 2, 3]);
+    for (&index, original) in indices.iter().zip(&triangles) {
+        assert_eq!(vertices[index as usize].position, original.position);
+    }
+}
+
+#[test]
+fn keeps_vertices_that_differ_in_any_attribute() {
+    let mut other_normal = vertex([0.0, 0.0, 0.0]);
+    other_normal.normal = [0.0, 1.0, 0.0];
+    let (vertices, indices) = deduplicate(&[vertex([0.0, 0.0, 0.0]), other_normal]);
+
+    assert_eq!(vertices.len(), 2);
+    assert_eq!(indices, [0, 1]);
+}
+
+#[test]
+fn treats_negative_zero_as_zero() {
+    // Transformed models easily end up with `-0.0` coordinates.
+    let (vertices, _) = deduplicate(&[vertex([0.0, 1.0, 0.0]), vertex([-0.0, 1.0, 0.0])]);
+
+    assert_eq!(vertices.len(), 1);
+}
+
+#[test]
+fn uses_16_bit_indices_when_possible() {
+    assert_eq!(Indices::new(vec![0, 1, 2], 3), Indices::U16(vec![0, 1, 2]));
+    assert_eq!(
+        Indices::new(vec![0, 1, 65535], 65536),
+        Indices::U16(vec![0, 1, 65535])
+    );
+    assert_eq!(
+        Indices::new(vec![0, 1, 65536], 65537),
+        Indices::U32(vec![0, 1, 65536])
+    );
+}
+
+#[test]
+fn accepts_whole_triangles_of_valid_indices() {
+    assert!(check_triangle_list(&[0, 1, 2, 0, 2, 3], 4).is_ok());
+}
+
+#[test]
+fn rejects_a_partial_triangle() {
+    let error = check_triangle_list(&[0, 1, 2, 0], 3).unwrap_err();
+
+    assert!(error.to_string().contains("4 indices"), "{error}");
+}
+
+#[test]
+fn rejects_indices_past_the_last_vertex() {
+    let error = check_triangle_list(&[0, 1, 3], 3).unwrap_err();
+
+    assert!(error.to_string().contains("index 3"), "{error}");
+}
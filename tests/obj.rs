@@ -0,0 +1,112 @@
+use std::path::PathBuf;
+
+use rufix::{
+    geometry::{fit_to_unit_cube, NormalMode},
+    obj::load_obj,
+};
+
+// A unit square in the xy plane facing +z, split into two triangles, plus a tilted triangle
+// sharing one of its edges.
+const OBJ: &str = "\
+mtllib model.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+v 1 0 -1
+usemtl red
+f 1 2 3 4
+f 2 5 3
+";
+
+const MTL: &str = "\
+newmtl red
+Kd 1.0 0.0 0.0
+";
+
+fn write_model(name: &str, obj: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("model.mtl"), MTL).unwrap();
+    let path = dir.join("model.obj");
+    std::fs::write(&path, obj).unwrap();
+    path
+}
+
+#[test]
+fn triangulates_and_uses_material_colors() {
+    let vertices = load_obj(write_model("obj_colors", OBJ), NormalMode::Flat).unwrap();
+
+    assert_eq!(vertices.len(), 9);
+    assert!(vertices.iter().all(|v| v.color == [1.0, 0.0, 0.0]));
+}
+
+#[test]
+fn generates_flat_normals() {
+    let vertices = load_obj(write_model("obj_flat", OBJ), NormalMode::Flat).unwrap();
+
+    for vertex in &vertices[..6] {
+        assert_eq!(vertex.normal, [0.0, 0.0, 1.0]);
+    }
+    // Positions are kept in the file's coordinate system.
+    assert_eq!(vertices[1].position, [1.0, 0.0, 0.0]);
+}
+
+#[test]
+fn generates_smooth_normals_for_shared_positions() {
+    let vertices = load_obj(write_model("obj_smooth", OBJ), NormalMode::Smooth).unwrap();
+
+    // Vertex 2 (index 1 of the first triangle) is shared with the tilted triangle, so its normal
+    // leans towards +x.
+    let shared = vertices[1].normal;
+    assert!(shared[0] > 0.0 && shared[2] > 0.0);
+    let length = shared.iter().map(|c| c * c).sum::<f32>().sqrt();
+    assert!((length - 1.0).abs() < 1e-5);
+
+    // Vertex 1 only belongs to the square.
+    assert_eq!(vertices[0].normal, [0.0, 0.0, 1.0]);
+}
+
+#[test]
+fn keeps_normals_from_the_file() {
+    let obj = "\
+v 0 0 0
+v 1 0 0
+v 0 1 0
+vn 0 1 0
+f 1//1 2//1 3//1
+";
+    let vertices = load_obj(write_model("obj_normals", obj), NormalMode::Flat).unwrap();
+
+    assert!(vertices.iter().all(|v| v.normal == [0.0, 1.0, 0.0]));
+}
+
+#[test]
+fn reads_texture_coordinates() {
+    let obj = "\
+v 0 0 0
+v 1 0 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 0.5 0.75
+f 1/1 2/2 3/3
+";
+    let vertices = load_obj(write_model("obj_uvs", obj), NormalMode::Flat).unwrap();
+
+    // Flipped so v grows downwards, like in the image.
+    let uvs: Vec<_> = vertices.iter().map(|v| v.uv).collect();
+    assert_eq!(uvs, [[0.0, 1.0], [1.0, 1.0], [0.5, 0.25]]);
+}
+
+#[test]
+fn fits_models_into_the_unit_cube() {
+    let mut vertices = load_obj(write_model("obj_fit", OBJ), NormalMode::Flat).unwrap();
+    fit_to_unit_cube(&mut vertices);
+
+    let max = vertices
+        .iter()
+        .flat_map(|v| v.position)
+        .fold(0.0f32, |max, c| max.max(c.abs()));
+    assert!((max - 1.0).abs() < 1e-6);
+}
@@ -0,0 +1,52 @@
+use rufix::{
+    post::{default_post_effects, neutral_lut, Bloom, PostEffect, ToneMapping},
+    Scene,
+};
+
+#[test]
+fn neutral_lut_maps_every_color_to_itself() {
+    let size = 16;
+    let lut = neutral_lut(size);
+    assert_eq!((lut.width, lut.height), (size * size, size));
+    assert_eq!(lut.rgba.len(), (size * size * size * 4) as usize);
+
+    let texel = |x: u32, y: u32| {
+        let i = ((y * lut.width + x) * 4) as usize;
+        &lut.rgba[i..i + 4]
+    };
+    // Red grows within a slice, green downwards, blue from slice to slice.
+    assert_eq!(texel(0, 0), [0, 0, 0, 255]);
+    assert_eq!(texel(size - 1, 0), [255, 0, 0, 255]);
+    assert_eq!(texel(0, size - 1), [0, 255, 0, 255]);
+    assert_eq!(texel(size * (size - 1), 0), [0, 0, 255, 255]);
+    assert_eq!(texel(3 * size + 5, 7), [85, 119, 51, 255]);
+}
+
+#[test]
+fn only_tone_mapping_is_on_by_default() {
+    let effects = default_post_effects();
+    let enabled: Vec<_> = effects.iter().filter(|effect| effect.enabled()).collect();
+    assert_eq!(enabled, [&PostEffect::ToneMapping(ToneMapping::default())]);
+
+    // Bloom needs HDR values, so it runs before tone mapping.
+    let position = |matches: fn(&PostEffect) -> bool| effects.iter().position(matches).unwrap();
+    assert!(
+        position(|effect| matches!(effect, PostEffect::Bloom(_)))
+            < position(|effect| matches!(effect, PostEffect::ToneMapping(_)))
+    );
+    assert_eq!(Scene::new().post_effects, effects);
+}
+
+#[test]
+fn effects_can_be_toggled() {
+    let mut effect: PostEffect = Bloom::default().into();
+    assert!(effect.enabled());
+    effect.set_enabled(false);
+    assert_eq!(
+        effect,
+        PostEffect::Bloom(Bloom {
+            enabled: false,
+            ..Default::default()
+        })
+    );
+}
@@ -0,0 +1,72 @@
+use nalgebra_glm::{translation, vec3};
+use rufix::scene::{Node, Scene};
+
+#[test]
+fn composes_parent_and_child_transforms() {
+    let mut scene = Scene::new();
+    let parent = scene.add_node(Node {
+        transform: translation(&vec3(1.0, 0.0, 0.0)),
+        ..Default::default()
+    });
+    let child = scene.add_node(Node {
+        transform: translation(&vec3(0.0, 2.0, 0.0)),
+        parent: Some(parent),
+        ..Default::default()
+    });
+    let grandchild = scene.add_node(Node {
+        parent: Some(child),
+        ..Default::default()
+    });
+
+    let transforms = scene.world_transforms();
+    assert_eq!(transforms[0], translation(&vec3(1.0, 0.0, 0.0)));
+    assert_eq!(transforms[1], translation(&vec3(1.0, 2.0, 0.0)));
+    assert_eq!(transforms[2], transforms[1]);
+
+    // Moving the parent moves its whole subtree.
+    scene
+        .node_mut(parent)
+        .set_transform(translation(&vec3(0.0, 0.0, -3.0)));
+    let transforms = scene.world_transforms();
+    assert_eq!(transforms[2], translation(&vec3(0.0, 2.0, -3.0)));
+    assert_eq!(scene.node(grandchild).parent, Some(child));
+}
+
+#[test]
+fn hiding_a_node_hides_its_descendants() {
+    let mut scene = Scene::new();
+    let root = scene.add_node(Node::default());
+    let hidden = scene.add_node(Node {
+        parent: Some(root),
+        visible: false,
+        ..Default::default()
+    });
+    scene.add_node(Node {
+        parent: Some(hidden),
+        ..Default::default()
+    });
+    scene.add_node(Node {
+        parent: Some(root),
+        ..Default::default()
+    });
+
+    assert_eq!(scene.visibility(), [true, false, false, true]);
+
+    scene.node_mut(root).set_visible(false);
+    assert_eq!(scene.visibility(), [false; 4]);
+}
+
+#[test]
+#[should_panic]
+fn rejects_parents_that_are_not_in_the_scene() {
+    let mut other = Scene::new();
+    other.add_node(Node::default());
+    let foreign = other.add_node(Node::default());
+
+    let mut scene = Scene::new();
+    scene.add_node(Node::default());
+    scene.add_node(Node {
+        parent: Some(foreign),
+        ..Default::default()
+    });
+}
@@ -0,0 +1,307 @@
+use nalgebra_glm::{vec3, vec4, Vec3};
+use rufix::{
+    geometry::Bounds,
+    shadow::{
+        cascade_light_spaces, cascade_splits, point_shadow_casters, shadow_casters,
+        spot_light_space, ShadowSettings, MAX_POINT_SHADOWS, MAX_SHADOW_MAPS,
+    },
+    vertex::{DirectionalLight, Light, PointLight, SpotLight},
+    Camera,
+};
+
+const SUN_DIRECTION: [f32; 3] = [1.0, -2.0, -0.5];
+
+fn bounds() -> Bounds {
+    Bounds {
+        min: vec3(-20.0, -2.0, -30.0),
+        max: vec3(20.0, 10.0, 10.0),
+    }
+}
+
+fn camera() -> Camera {
+    let mut camera = Camera::orbit(vec3(0.0, 0.0, -5.0), 5.0);
+    camera.aspect = 16.0 / 9.0;
+    camera.yaw = 0.4;
+    camera.pitch = -0.3;
+    camera
+}
+
+fn sun(cast_shadows: bool) -> Light {
+    DirectionalLight {
+        direction: SUN_DIRECTION,
+        color: [1.0, 1.0, 1.0],
+        intensity: 1.0,
+        cast_shadows,
+    }
+    .into()
+}
+
+fn spot() -> SpotLight {
+    SpotLight {
+        position: [0.0, 2.0, -2.0],
+        direction: [0.0, -1.0, 0.0],
+        color: [1.0, 1.0, 1.0],
+        intensity: 1.0,
+        range: 0.0,
+        inner_cone_angle: 0.3,
+        outer_cone_angle: 0.5,
+        cast_shadows: true,
+    }
+}
+
+fn lamp(range: f32) -> Light {
+    PointLight {
+        position: [1.0, 2.0, -3.0],
+        color: [1.0, 1.0, 1.0],
+        intensity: 1.0,
+        range,
+        cast_shadows: true,
+    }
+    .into()
+}
+
+fn to_clip(matrix: &nalgebra_glm::Mat4, point: Vec3) -> Vec3 {
+    let clip = matrix * vec4(point.x, point.y, point.z, 1.0);
+    clip.xyz() / clip.w
+}
+
+// Points on the edges of the camera's view at `distance` from it.
+fn view_points(camera: &Camera, distance: f32) -> Vec<Vec3> {
+    let forward = camera.forward();
+    let right = forward.cross(&vec3(0.0, 1.0, 0.0)).normalize();
+    let up = right.cross(&forward);
+    let half_height = distance * (camera.fov_y / 2.0).tan();
+    let half_width = half_height * camera.aspect;
+    let center = camera.eye() + forward * distance;
+    [
+        (-1.0, -1.0),
+        (1.0, -1.0),
+        (-1.0, 1.0),
+        (1.0, 1.0),
+        (0.0, 0.0),
+    ]
+    .iter()
+    .map(|&(x, y)| center + right * half_width * x + up * half_height * y)
+    .collect()
+}
+
+#[test]
+fn cascade_splits_reach_the_shadow_distance() {
+    let settings = ShadowSettings::default();
+    let splits = cascade_splits(&camera(), &settings);
+
+    assert_eq!(splits.len(), settings.cascade_count as usize);
+    assert!(
+        splits.windows(2).all(|pair| pair[0] < pair[1]),
+        "{:?}",
+        splits
+    );
+    assert!((splits.last().unwrap() - settings.shadow_distance).abs() < 1e-3);
+
+    // Uniform splits are evenly spaced, and nothing reaches past the camera's far plane.
+    let uniform = ShadowSettings {
+        cascade_count: 2,
+        cascade_split_lambda: 0.0,
+        shadow_distance: 1000.0,
+        ..Default::default()
+    };
+    let camera = camera();
+    let splits = cascade_splits(&camera, &uniform);
+    let middle = (camera.near + camera.far) / 2.0;
+    assert!((splits[0] - middle).abs() < 1e-3, "{:?}", splits);
+    assert_eq!(splits[1], camera.far);
+}
+
+#[test]
+fn cascades_cover_their_slice_of_the_view() {
+    let camera = camera();
+    let settings = ShadowSettings::default();
+    let splits = cascade_splits(&camera, &settings);
+    let matrices = cascade_light_spaces(SUN_DIRECTION, &camera, &settings, &bounds());
+    assert_eq!(matrices.len(), splits.len());
+
+    let mut near = camera.near;
+    for (matrix, &far) in matrices.iter().zip(&splits) {
+        for distance in [near, (near + far) / 2.0, far] {
+            for point in view_points(&camera, distance) {
+                let clip = to_clip(matrix, point);
+                assert!(clip.x.abs() <= 1.0 && clip.y.abs() <= 1.0, "{:?}", clip);
+                assert!((0.0..=1.0).contains(&clip.z), "{:?}", clip);
+
+                // Anything in the scene between the light and the slice casts shadows into it.
+                let towards_light = point - vec3(1.0, -2.0, -0.5).normalize() * 2.0;
+                assert!(to_clip(matrix, towards_light).z >= 0.0);
+            }
+        }
+        near = far;
+    }
+}
+
+#[test]
+fn cascades_move_in_whole_texels() {
+    let settings = ShadowSettings::default();
+    let before = camera();
+    let mut after = camera();
+    after.target += vec3(0.0137, 0.0, 0.0071);
+    let first = cascade_light_spaces(SUN_DIRECTION, &before, &settings, &bounds())[0];
+    let second = cascade_light_spaces(SUN_DIRECTION, &after, &settings, &bounds())[0];
+
+    // A fixed point moves by a whole number of texels, so its shadow doesn't shimmer.
+    let point = vec3(0.3, 0.0, -4.0);
+    let texels =
+        (to_clip(&second, point) - to_clip(&first, point)).xy() * (settings.map_size as f32 / 2.0);
+    for offset in texels.iter() {
+        assert!((offset - offset.round()).abs() < 1e-3, "{:?}", texels);
+    }
+}
+
+#[test]
+fn spot_shadows_look_along_the_cone() {
+    let matrix = spot_light_space(&spot(), &bounds());
+
+    let below = to_clip(&matrix, vec3(0.0, -1.0, -2.0));
+    assert!(below.x.abs() < 1e-5 && below.y.abs() < 1e-5, "{:?}", below);
+    assert!((0.0..1.0).contains(&below.z));
+    // The far plane reaches past the whole scene.
+    assert!(to_clip(&matrix, vec3(0.0, -2.0, -2.0)).z < 1.0);
+}
+
+#[test]
+fn only_shadow_casting_lights_get_maps() {
+    let point: Light = PointLight {
+        position: [0.0, 1.0, 0.0],
+        color: [1.0, 1.0, 1.0],
+        intensity: 1.0,
+        range: 0.0,
+        cast_shadows: false,
+    }
+    .into();
+    let lights = vec![sun(false), point, spot().into(), sun(true)];
+    let settings = ShadowSettings::default();
+
+    let casters = shadow_casters(&lights, &camera(), &settings, Some(bounds()));
+    let layers = casters
+        .iter()
+        .map(|caster| (caster.light, caster.first_layer, caster.light_spaces.len()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        layers,
+        vec![(2, 0, 1), (3, 1, settings.cascade_count as usize)]
+    );
+
+    // Nothing is drawn, so there is nothing to cast a shadow.
+    assert!(shadow_casters(&lights, &camera(), &settings, None).is_empty());
+}
+
+#[test]
+fn shadow_maps_are_capped() {
+    let settings = ShadowSettings {
+        cascade_count: 3,
+        ..Default::default()
+    };
+    let mut lights = vec![sun(true); 3];
+    lights.extend(vec![spot().into(); 3]);
+
+    // Two suns take 6 layers, two spot lights fill up the rest.
+    let casters = shadow_casters(&lights, &camera(), &settings, Some(bounds()));
+    let layers = casters
+        .iter()
+        .map(|caster| (caster.light, caster.first_layer))
+        .collect::<Vec<_>>();
+    assert_eq!(layers, vec![(0, 0), (1, 3), (3, 6), (4, 7)]);
+    let used = casters.iter().map(|caster| caster.light_spaces.len());
+    assert_eq!(used.sum::<usize>(), MAX_SHADOW_MAPS);
+}
+
+#[test]
+fn point_shadows_follow_the_cube_map_layout() {
+    let casters = point_shadow_casters(&[lamp(6.0)], Some(bounds()));
+    let caster = &casters[0];
+    assert_eq!((caster.near, caster.far), (0.05, 6.0));
+
+    // Direction of each face, in layer order, and the directions the s and t texture
+    // coordinates grow towards on it.
+    let faces = [
+        (
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 0.0, -1.0),
+            vec3(0.0, -1.0, 0.0),
+        ),
+        (
+            vec3(-1.0, 0.0, 0.0),
+            vec3(0.0, 0.0, 1.0),
+            vec3(0.0, -1.0, 0.0),
+        ),
+        (
+            vec3(0.0, 1.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 0.0, 1.0),
+        ),
+        (
+            vec3(0.0, -1.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 0.0, -1.0),
+        ),
+        (
+            vec3(0.0, 0.0, 1.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, -1.0, 0.0),
+        ),
+        (
+            vec3(0.0, 0.0, -1.0),
+            vec3(-1.0, 0.0, 0.0),
+            vec3(0.0, -1.0, 0.0),
+        ),
+    ];
+    let light = vec3(1.0, 2.0, -3.0);
+    for (matrix, (direction, s, t)) in caster.light_spaces.iter().zip(faces) {
+        let distance = 2.0;
+        let clip = to_clip(matrix, light + direction * distance);
+        assert!(clip.x.abs() < 1e-5 && clip.y.abs() < 1e-5, "{:?}", clip);
+        // The fragment shader compares against the depth along the face's axis.
+        let (near, far) = (caster.near, caster.far);
+        let depth = far * (distance - near) / (distance * (far - near));
+        assert!((clip.z - depth).abs() < 1e-5, "{} != {}", clip.z, depth);
+
+        let clip = to_clip(matrix, light + (direction + s * 0.5 + t * 0.25) * distance);
+        assert!(
+            (clip.x - 0.5).abs() < 1e-5 && (clip.y - 0.25).abs() < 1e-5,
+            "{:?}",
+            clip
+        );
+    }
+}
+
+#[test]
+fn point_shadows_are_capped() {
+    let mut lights = vec![lamp(0.0); MAX_POINT_SHADOWS + 1];
+    lights.insert(0, sun(true));
+
+    let casters = point_shadow_casters(&lights, Some(bounds()));
+    let slots = casters
+        .iter()
+        .map(|caster| (caster.light, caster.slot))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        slots,
+        (0..MAX_POINT_SHADOWS)
+            .map(|i| (i + 1, i))
+            .collect::<Vec<_>>()
+    );
+    // Without a range, the maps reach past the whole scene.
+    let farthest = bounds()
+        .corners()
+        .iter()
+        .map(|corner| (corner - vec3(1.0, 2.0, -3.0)).norm())
+        .fold(0.0, f32::max);
+    assert!(casters[0].far >= farthest);
+
+    // Point lights don't take layers of the shadow map array.
+    let casters = shadow_casters(
+        &lights,
+        &camera(),
+        &ShadowSettings::default(),
+        Some(bounds()),
+    );
+    assert_eq!(casters.len(), 1);
+}
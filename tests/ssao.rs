@@ -0,0 +1,39 @@
+use rufix::{
+    ssao::{ssao_kernel, SsaoSettings, MAX_SSAO_SAMPLES},
+    Scene,
+};
+
+#[test]
+fn kernel_fills_the_hemisphere_above_the_surface() {
+    let kernel = ssao_kernel(MAX_SSAO_SAMPLES);
+    assert_eq!(kernel.len(), MAX_SSAO_SAMPLES);
+    for offset in &kernel {
+        assert!(offset.z > 0.0, "{offset:?} is below the surface");
+        assert!(offset.norm() <= 1.0, "{offset:?} is outside the radius");
+        assert!(
+            offset.norm() >= 0.05,
+            "{offset:?} is too close to the center"
+        );
+    }
+
+    // Later offsets reach further out, so fewer samples still cover the area near the surface.
+    let mean_length = |offsets: &[nalgebra_glm::Vec3]| {
+        offsets.iter().map(|offset| offset.norm()).sum::<f32>() / offsets.len() as f32
+    };
+    let (near, far) = kernel.split_at(MAX_SSAO_SAMPLES / 2);
+    assert!(mean_length(near) < mean_length(far));
+}
+
+#[test]
+fn kernel_is_the_same_on_every_call() {
+    assert_eq!(ssao_kernel(16), ssao_kernel(16));
+    assert_ne!(ssao_kernel(16)[0], ssao_kernel(16)[1]);
+}
+
+#[test]
+fn ssao_is_off_by_default() {
+    let settings = SsaoSettings::default();
+    assert!(!settings.enabled);
+    assert!(settings.sample_count as usize <= MAX_SSAO_SAMPLES);
+    assert_eq!(Scene::new().ssao, settings);
+}
@@ -0,0 +1,32 @@
+use std::path::PathBuf;
+
+use rufix::texture::load_image;
+
+fn temp_path(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join("image.png")
+}
+
+#[test]
+fn decodes_png_to_rgba() {
+    // A 1x2 gray and alpha image: an opaque white pixel above a half transparent black one.
+    let path = temp_path("texture_png");
+    let file = std::fs::File::create(&path).unwrap();
+    let mut encoder = png::Encoder::new(file, 1, 2);
+    encoder.set_color(png::ColorType::GrayscaleAlpha);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&[255, 255, 0, 128]).unwrap();
+    writer.finish().unwrap();
+
+    let image = load_image(&path).unwrap();
+    assert_eq!((image.width, image.height), (1, 2));
+    assert_eq!(image.rgba, [255, 255, 255, 255, 0, 0, 0, 128]);
+}
+
+#[test]
+fn reports_missing_files() {
+    let error = load_image("does/not/exist.png").unwrap_err();
+    assert!(format!("{}", error).contains("exist.png"), "{}", error);
+}